@@ -0,0 +1,49 @@
+//! Compares `fun::vm` with `fun::eval` on `fac` and `fib`, compiling included. Run it with
+//! `cargo bench`; it fails if the VM is not at least ten times faster.
+use fun::eval::{eval_strict, on_eval_stack};
+use fun::parser::parse_main_program;
+use fun::vm::compile;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+const PROGRAMS: &str = "
+    fac = λn. if n == 0 then 1 else n * fac (n - 1);
+    fib = λn. if n < 2 then n else fib (n - 1) + fib (n - 2);
+";
+
+const MIN_SPEEDUP: f64 = 10.0;
+
+// The shortest time of a run of `f`, over a few samples of `runs` runs, and its result
+fn time<T>(runs: u32, f: impl Fn() -> T) -> (T, Duration) {
+    let mut best = Duration::MAX;
+    for _ in 0..5 {
+        let start = Instant::now();
+        for _ in 1..runs {
+            std::hint::black_box(f());
+        }
+        best = best.min(start.elapsed() / (runs - 1));
+    }
+    (f(), best)
+}
+
+fn main() -> ExitCode {
+    // Evaluation would start a thread for its stack on every run otherwise
+    on_eval_stack(|| {
+        let mut ok = true;
+        for (main, runs) in [("fac 20", 2000), ("fib 20", 10)] {
+            let prog = parse_main_program(&format!("{PROGRAMS} main = {main};")).unwrap();
+            let (expected, eval_time) = time(runs, || eval_strict(&prog.env, &prog.main));
+            let (result, vm_time) = time(runs, || compile(&prog).unwrap().run());
+            assert_eq!(expected, result, "{main}");
+            let speedup = eval_time.as_secs_f64() / vm_time.as_secs_f64();
+            println!("{main}: eval {eval_time:?}, vm {vm_time:?}, {speedup:.1}x faster");
+            ok &= speedup >= MIN_SPEEDUP;
+        }
+        if ok {
+            ExitCode::SUCCESS
+        } else {
+            println!("the VM should be at least {MIN_SPEEDUP}x faster");
+            ExitCode::FAILURE
+        }
+    })
+}
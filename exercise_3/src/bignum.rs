@@ -0,0 +1,468 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// An integer of arbitrary size, the values of fun's `Int`. Values that fit into an `i64` are
+/// stored as such, so arithmetic on them only falls back to the slow path when it overflows.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BigInt(Repr);
+
+// Every value has exactly one representation, so the derived equality is correct.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Repr {
+    Small(i64),
+    /// A value outside the range of `i64`, as its sign and magnitude in base 2^32 with the
+    /// least significant digit first and no leading zeros.
+    Big { negative: bool, magnitude: Vec<u32> },
+}
+
+// A magnitude in base 2^32, least significant digit first
+type Magnitude = Vec<u32>;
+
+impl BigInt {
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        match &self.0 {
+            Repr::Small(n) => Some(*n),
+            Repr::Big { .. } => None,
+        }
+    }
+
+    /// The nearest float; values beyond the range of `f64` become infinite.
+    pub fn to_f64(&self) -> f64 {
+        match &self.0 {
+            Repr::Small(n) => *n as f64,
+            Repr::Big { negative, magnitude } => {
+                let x = magnitude.iter().rev().fold(0.0, |x, &digit| x * 4294967296.0 + digit as f64);
+                if *negative { -x } else { x }
+            }
+        }
+    }
+
+    /// The integer part of a float, rounded towards zero. `None` for infinities and NaN.
+    pub fn from_f64(x: f64) -> Option<BigInt> {
+        if !x.is_finite() {
+            return None;
+        }
+        let x = x.trunc();
+        if x.abs() < 9.2e18 {
+            return Some(BigInt(Repr::Small(x as i64)));
+        }
+        // A float this large is an integer: its 53-bit mantissa times a power of two
+        let bits = x.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as u32 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let value = &BigInt(Repr::Small(mantissa as i64)) * &BigInt(Repr::Small(2)).pow(exponent);
+        Some(if x < 0.0 { -&value } else { value })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == BigInt(Repr::Small(0))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match &self.0 {
+            Repr::Small(n) => *n < 0,
+            Repr::Big { negative, .. } => *negative,
+        }
+    }
+
+    /// Truncating division, like `/` on `i64`. `None` if `other` is zero.
+    pub fn checked_div(&self, other: &BigInt) -> Option<BigInt> {
+        if other.is_zero() {
+            return None;
+        }
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            if let Some(n) = n1.checked_div(*n2) {
+                return Some(BigInt(Repr::Small(n)));
+            }
+        }
+        let ((neg1, mag1), (neg2, mag2)) = (self.parts(), other.parts());
+        let (quotient, _) = divide(&mag1, &mag2);
+        Some(BigInt::from_parts(neg1 != neg2, quotient))
+    }
+
+    /// The remainder of truncating division, with the sign of `self`. `None` if `other` is zero.
+    pub fn checked_rem(&self, other: &BigInt) -> Option<BigInt> {
+        if other.is_zero() {
+            return None;
+        }
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            if let Some(n) = n1.checked_rem(*n2) {
+                return Some(BigInt(Repr::Small(n)));
+            }
+        }
+        let ((neg1, mag1), (_, mag2)) = (self.parts(), other.parts());
+        let (_, remainder) = divide(&mag1, &mag2);
+        Some(BigInt::from_parts(neg1, remainder))
+    }
+
+    /// Raises `self` to the power `exp` by repeated squaring.
+    pub fn pow(&self, mut exp: u32) -> BigInt {
+        if let Repr::Small(n) = &self.0 {
+            if let Some(n) = n.checked_pow(exp) {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        let mut base = self.clone();
+        let mut result = BigInt(Repr::Small(1));
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = &result * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+
+    // The sign and magnitude of the value.
+    fn parts(&self) -> (bool, Magnitude) {
+        match &self.0 {
+            Repr::Small(n) => {
+                let magnitude = n.unsigned_abs();
+                (*n < 0, trim(vec![magnitude as u32, (magnitude >> 32) as u32]))
+            }
+            Repr::Big { negative, magnitude } => (*negative, magnitude.clone()),
+        }
+    }
+
+    // The canonical representation of a sign and magnitude: small if it fits into an `i64`.
+    fn from_parts(negative: bool, magnitude: Magnitude) -> BigInt {
+        let magnitude = trim(magnitude);
+        if magnitude.len() <= 2 {
+            let n = magnitude.iter().rev().fold(0u64, |n, &digit| (n << 32) | digit as u64);
+            let n = if negative { -(n as i128) } else { n as i128 };
+            if let Ok(n) = i64::try_from(n) {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        BigInt(Repr::Big { negative, magnitude })
+    }
+}
+
+fn trim(mut magnitude: Magnitude) -> Magnitude {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    magnitude
+}
+
+fn compare(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Magnitude {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (n, &digit) in long.iter().enumerate() {
+        let sum = digit as u64 + *short.get(n).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+    trim(result)
+}
+
+// a - b, where a >= b
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Magnitude {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (n, &digit) in a.iter().enumerate() {
+        let mut diff = digit as i64 - *b.get(n).unwrap_or(&0) as i64 - borrow;
+        borrow = (diff < 0) as i64;
+        if diff < 0 {
+            diff += 1 << 32;
+        }
+        result.push(diff as u32);
+    }
+    trim(result)
+}
+
+fn mul_magnitudes(a: &[u32], b: &[u32]) -> Magnitude {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (n, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (m, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[n + m] as u64 + carry;
+            result[n + m] = product as u32;
+            carry = product >> 32;
+        }
+        result[n + b.len()] = carry as u32;
+    }
+    trim(result)
+}
+
+// Divides by a single digit, returning the quotient and the remainder.
+fn divide_by_digit(a: &[u32], divisor: u32) -> (Magnitude, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (n, &digit) in a.iter().enumerate().rev() {
+        let current = (remainder << 32) | digit as u64;
+        quotient[n] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    (trim(quotient), remainder as u32)
+}
+
+// Long division one bit at a time, returning the quotient and the remainder. `b` is not zero.
+fn divide(a: &[u32], b: &[u32]) -> (Magnitude, Magnitude) {
+    if let [divisor] = b {
+        let (quotient, remainder) = divide_by_digit(a, *divisor);
+        return (quotient, trim(vec![remainder]));
+    }
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Magnitude = Vec::new();
+    for bit in (0..a.len() * 32).rev() {
+        // remainder = remainder * 2 + the next bit of a
+        let mut carry = (a[bit / 32] >> (bit % 32)) & 1;
+        for digit in remainder.iter_mut() {
+            let shifted = (*digit << 1) | carry;
+            carry = *digit >> 31;
+            *digit = shifted;
+        }
+        if carry != 0 {
+            remainder.push(carry);
+        }
+        if compare(&remainder, b) != Ordering::Less {
+            remainder = sub_magnitudes(&remainder, b);
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+    (trim(quotient), remainder)
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        BigInt(Repr::Small(n))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            if let Some(n) = n1.checked_add(*n2) {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        let ((neg1, mag1), (neg2, mag2)) = (self.parts(), other.parts());
+        if neg1 == neg2 {
+            return BigInt::from_parts(neg1, add_magnitudes(&mag1, &mag2));
+        }
+        match compare(&mag1, &mag2) {
+            Ordering::Less => BigInt::from_parts(neg2, sub_magnitudes(&mag2, &mag1)),
+            _ => BigInt::from_parts(neg1, sub_magnitudes(&mag1, &mag2)),
+        }
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        if let Repr::Small(n) = &self.0 {
+            if let Some(n) = n.checked_neg() {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        let (negative, magnitude) = self.parts();
+        BigInt::from_parts(!negative, magnitude)
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            if let Some(n) = n1.checked_sub(*n2) {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            if let Some(n) = n1.checked_mul(*n2) {
+                return BigInt(Repr::Small(n));
+            }
+        }
+        let ((neg1, mag1), (neg2, mag2)) = (self.parts(), other.parts());
+        BigInt::from_parts(neg1 != neg2, mul_magnitudes(&mag1, &mag2))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        if let (Repr::Small(n1), Repr::Small(n2)) = (&self.0, &other.0) {
+            return n1.cmp(n2);
+        }
+        let ((neg1, mag1), (neg2, mag2)) = (self.parts(), other.parts());
+        match (neg1, neg2) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare(&mag1, &mag2),
+            (true, true) => compare(&mag2, &mag1),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (negative, mut magnitude) = match &self.0 {
+            Repr::Small(n) => return write!(f, "{n}"),
+            Repr::Big { negative, magnitude } => (*negative, magnitude.clone()),
+        };
+        // Split off nine decimal digits at a time, least significant first
+        let mut chunks = Vec::new();
+        while !magnitude.is_empty() {
+            let (quotient, chunk) = divide_by_digit(&magnitude, 1_000_000_000);
+            chunks.push(chunk);
+            magnitude = quotient;
+        }
+        if negative {
+            write!(f, "-")?;
+        }
+        let (first, rest) = chunks.split_last().expect("big values are not zero");
+        write!(f, "{first}")?;
+        rest.iter().rev().try_for_each(|chunk| write!(f, "{chunk:09}"))
+    }
+}
+
+/// The error of parsing something that is not an optionally signed sequence of decimal digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid integer literal")
+    }
+}
+
+impl std::error::Error for ParseBigIntError {}
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<BigInt, ParseBigIntError> {
+        if let Ok(n) = i64::from_str(s) {
+            return Ok(BigInt(Repr::Small(n)));
+        }
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+        let mut magnitude = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let value = chunk.iter().fold(0u32, |n, c| n * 10 + (c - b'0') as u32);
+            magnitude = mul_magnitudes(&magnitude, &[10u32.pow(chunk.len() as u32)]);
+            magnitude = add_magnitudes(&magnitude, &[value]);
+        }
+        Ok(BigInt::from_parts(negative, magnitude))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        let cases = [
+            "0",
+            "-1",
+            "9223372036854775807",
+            "-9223372036854775808",
+            "9223372036854775808",
+            "-123456789012345678901234567890",
+            "1000000000000000000000000000",
+        ];
+        for s in cases {
+            assert_eq!(s, big(s).to_string());
+        }
+        assert_eq!(BigInt(Repr::Small(i64::MIN)), big("-9223372036854775808"));
+        assert!(matches!(big("9223372036854775808").0, Repr::Big { .. }));
+        assert_eq!(BigInt(Repr::Small(42)), big("00042"));
+        assert_eq!(Err(ParseBigIntError), "12a".parse::<BigInt>());
+        assert_eq!(Err(ParseBigIntError), "-".parse::<BigInt>());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let max = BigInt::from(i64::MAX);
+        let one = BigInt::from(1);
+        assert_eq!(big("9223372036854775808"), &max + &one);
+        assert_eq!(max, &(&max + &one) - &one);
+        assert_eq!(big("-9223372036854775809"), &BigInt::from(i64::MIN) - &one);
+        assert_eq!(big("85070591730234615847396907784232501249"), &max * &max);
+        assert_eq!(big("-85070591730234615847396907784232501249"), &max * &-&max);
+        assert_eq!(big("15511210043330985984000000"), (1..=25).fold(one.clone(), |n, k| &n * &BigInt::from(k)));
+        assert_eq!(big("1267650600228229401496703205376"), BigInt::from(2).pow(100));
+        assert_eq!(BigInt::from(-8), BigInt::from(-2).pow(3));
+    }
+
+    #[test]
+    fn test_division() {
+        let n = big("-123456789012345678901234567890");
+        let d = big("9876543210987");
+        let q = n.checked_div(&d).unwrap();
+        let r = n.checked_rem(&d).unwrap();
+        assert_eq!(big("-12499999886094578"), q);
+        assert_eq!(n, &(&q * &d) + &r);
+        assert!(r.is_negative());
+        assert_eq!(big("9223372036854775808"), BigInt::from(i64::MIN).checked_div(&BigInt::from(-1)).unwrap());
+        assert_eq!(BigInt::from(0), BigInt::from(i64::MIN).checked_rem(&BigInt::from(-1)).unwrap());
+        assert_eq!(None, n.checked_div(&BigInt::from(0)));
+        assert_eq!(None, n.checked_rem(&BigInt::from(0)));
+    }
+
+    #[test]
+    fn test_float_conversion() {
+        assert_eq!(-2.0, BigInt::from(-2).to_f64());
+        assert_eq!(1e30, big("1000000000000000000000000000000").to_f64());
+        assert_eq!(Some(BigInt::from(-2)), BigInt::from_f64(-2.9));
+        assert_eq!(Some(big("1000000000000000019884624838656")), BigInt::from_f64(1e30));
+        assert_eq!(Some(big("-9223372036854775808")), BigInt::from_f64(-9223372036854775808.0));
+        assert_eq!(None, BigInt::from_f64(f64::INFINITY));
+        assert_eq!(None, BigInt::from_f64(f64::NAN));
+    }
+
+    #[test]
+    fn test_ordering() {
+        let mut values = vec![
+            big("100000000000000000000"),
+            BigInt::from(-3),
+            big("-100000000000000000000"),
+            BigInt::from(7),
+        ];
+        values.sort();
+        assert_eq!(
+            vec![big("-100000000000000000000"), BigInt::from(-3), BigInt::from(7), big("100000000000000000000")],
+            values
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use crate::eval::EvalError;
+use crate::term::*;
+use crate::value::{apply_builtin, apply_value_primop, match_value, readback, List, Lookup, Scope, Value};
+use std::collections::HashMap;
+
+/// Evaluates a term to a value like `value::eval_value`, but as a CEK machine: the term
+/// under evaluation (control), its local bindings (environment) and what to do with its
+/// value (continuation) are explicit, and the continuation is a stack on the heap.
+///
+/// Evaluation therefore never recurses in Rust. Nested applications, operators and
+/// conditions can be as deep as memory allows, and tail calls run in constant space.
+pub fn eval_cek<'a>(env: &'a Env, term: &'a Term) -> Result<Value<'a>, EvalError> {
+    Machine::new(env).run(term)
+}
+
+// The state of the machine between transitions.
+enum Control<'a> {
+    Eval(&'a Term, Scope<'a>),
+    Return(Value<'a>),
+}
+
+// The terms whose values are collected into a vector before they are used.
+enum Collect<'a> {
+    Con(&'a str, usize),
+    List,
+    Tuple,
+    Builtin(Builtin),
+}
+
+// A continuation frame: what to do with the value of the term being evaluated.
+enum Kont<'a> {
+    // The function of an application was evaluated, the argument is next
+    AppArg(&'a Term, Scope<'a>),
+    // The argument was evaluated, the function is applied to it
+    AppFun(Value<'a>),
+    If(&'a Term, &'a Term, Scope<'a>),
+    // The left operand was evaluated, the right one is next
+    PrimOpRight(PrimOp, &'a Term, Scope<'a>),
+    PrimOpApply(PrimOp, Value<'a>),
+    Items {
+        collect: Collect<'a>,
+        done: Vec<Value<'a>>,
+        rest: &'a [Term],
+        scope: Scope<'a>,
+    },
+    Record {
+        done: Vec<(&'a str, Value<'a>)>,
+        rest: &'a [(String, Term)],
+        scope: Scope<'a>,
+    },
+    Proj(usize),
+    Select(&'a str),
+    // The record was evaluated, the updates are next
+    Update(&'a [(String, Term)], Scope<'a>),
+    UpdateField {
+        fields: Vec<(&'a str, Value<'a>)>,
+        index: usize,
+        rest: &'a [(String, Term)],
+        scope: Scope<'a>,
+    },
+    Let {
+        name: &'a str,
+        rest: &'a [(String, Term)],
+        body: &'a Term,
+        scope: Scope<'a>,
+    },
+    Case(&'a [Arm], Scope<'a>),
+    // The guard of an arm whose pattern matched; `arm_scope` has the pattern variables
+    Guard {
+        value: Value<'a>,
+        body: &'a Term,
+        rest: &'a [Arm],
+        arm_scope: Scope<'a>,
+        scope: Scope<'a>,
+    },
+    // A top-level binding was evaluated and is cached
+    Global(&'a str),
+    Map {
+        f: Value<'a>,
+        done: Vec<Value<'a>>,
+        rest: List<'a>,
+    },
+    Filter {
+        p: Value<'a>,
+        kept: Vec<Value<'a>>,
+        current: Value<'a>,
+        rest: List<'a>,
+    },
+    // foldr f z xs goes through xs from the end: f x is applied first, then the result to acc
+    FoldrFun {
+        f: Value<'a>,
+        items: Vec<Value<'a>>,
+        acc: Value<'a>,
+    },
+    FoldrAcc {
+        f: Value<'a>,
+        items: Vec<Value<'a>>,
+    },
+}
+
+struct Machine<'a> {
+    globals: &'a Env,
+    cache: HashMap<&'a str, Value<'a>>,
+    // The top-level bindings being evaluated, innermost last
+    pending: Vec<&'a str>,
+    stack: Vec<Kont<'a>>,
+    // The largest number of frames on the stack so far
+    max_stack: usize,
+}
+
+impl<'a> Machine<'a> {
+    fn new(globals: &'a Env) -> Self {
+        Machine {
+            globals,
+            cache: HashMap::new(),
+            pending: Vec::new(),
+            stack: Vec::new(),
+            max_stack: 0,
+        }
+    }
+
+    fn run(&mut self, term: &'a Term) -> Result<Value<'a>, EvalError> {
+        let mut control = Control::Eval(term, Scope::default());
+        loop {
+            control = match control {
+                Control::Eval(term, scope) => self.eval(term, scope)?,
+                Control::Return(value) => match self.stack.pop() {
+                    Some(kont) => self.resume(kont, value)?,
+                    None => return Ok(value),
+                },
+            };
+            self.max_stack = self.max_stack.max(self.stack.len());
+        }
+    }
+
+    // Starts evaluating a term: either its value is known right away or a frame is pushed.
+    fn eval(&mut self, term: &'a Term, scope: Scope<'a>) -> Result<Control<'a>, EvalError> {
+        Ok(match term {
+            Term::Var(x) => match scope.lookup(x) {
+                Some(Lookup::Value(v)) => Control::Return(v.clone()),
+                Some(Lookup::Recursive { term, scope, .. }) => Control::Eval(term, scope.clone()),
+                None => return self.global(x),
+            },
+            Term::Let(bindings, body) => self.bind_next(bindings, body, scope),
+            Term::LetRec(bindings, body) => Control::Eval(body, scope.bind_recursive(bindings)),
+            Term::Abs(param, body) => Control::Return(Value::Closure { param, body, env: scope }),
+            Term::AbsPattern(pattern, body) => Control::Return(Value::PatternClosure { pattern, body, env: scope }),
+            Term::App(t1, t2) => self.push(Kont::AppArg(t2, scope.clone()), t1, scope),
+            Term::Int(n) => Control::Return(Value::Int(n.clone())),
+            Term::Float(x) => Control::Return(Value::Float(*x)),
+            Term::Bool(v) => Control::Return(Value::Bool(*v)),
+            Term::Str(s) => Control::Return(Value::Str(s.clone())),
+            Term::Char(c) => Control::Return(Value::Char(*c)),
+            Term::If(cond, t1, t2) => self.push(Kont::If(t1, t2, scope.clone()), cond, scope),
+            Term::PrimOp(op, t1, t2) => self.push(Kont::PrimOpRight(*op, t2, scope.clone()), t1, scope),
+            Term::Con(tag, arity, fields) => return self.collect(Collect::Con(tag, *arity), Vec::new(), fields, scope),
+            Term::List(items) => return self.collect(Collect::List, Vec::new(), items, scope),
+            Term::Tuple(items) => return self.collect(Collect::Tuple, Vec::new(), items, scope),
+            Term::Builtin(builtin, args) => return self.collect(Collect::Builtin(*builtin), Vec::new(), args, scope),
+            Term::Record(fields) => self.record_next(Vec::new(), fields, scope),
+            Term::Proj(index, t) => self.push(Kont::Proj(*index), t, scope),
+            Term::Select(t, field) => self.push(Kont::Select(field), t, scope),
+            Term::Update(t, updates) => self.push(Kont::Update(updates, scope.clone()), t, scope),
+            Term::Case(scrutinee, arms) => self.push(Kont::Case(arms, scope.clone()), scrutinee, scope),
+        })
+    }
+
+    // Evaluates `term` and continues with `kont`.
+    fn push(&mut self, kont: Kont<'a>, term: &'a Term, scope: Scope<'a>) -> Control<'a> {
+        self.stack.push(kont);
+        Control::Eval(term, scope)
+    }
+
+    // Passes the value of a term to the frame that was waiting for it.
+    fn resume(&mut self, kont: Kont<'a>, value: Value<'a>) -> Result<Control<'a>, EvalError> {
+        Ok(match kont {
+            Kont::AppArg(t, scope) => self.push(Kont::AppFun(value), t, scope),
+            Kont::AppFun(f) => return self.apply(f, value),
+            Kont::If(t1, t2, scope) => match value {
+                Value::Bool(true) => Control::Eval(t1, scope),
+                Value::Bool(false) => Control::Eval(t2, scope),
+                c => return Err(EvalError::NonBooleanCondition(readback(&c))),
+            },
+
+            // The right operand is only evaluated if the left one does not decide the result
+            Kont::PrimOpRight(op @ (PrimOp::And | PrimOp::Or), t2, scope) => match value {
+                Value::Bool(v) if v == (op == PrimOp::Or) => Control::Return(Value::Bool(v)),
+                l @ Value::Bool(_) => self.push(Kont::PrimOpApply(op, l), t2, scope),
+                l => return Err(EvalError::TypeMismatch { op, left: readback(&l), right: t2.clone() }),
+            },
+            Kont::PrimOpRight(op, t2, scope) => self.push(Kont::PrimOpApply(op, value), t2, scope),
+            Kont::PrimOpApply(op, l) => Control::Return(apply_value_primop(op, l, value)?),
+
+            Kont::Items { collect, mut done, rest, scope } => {
+                done.push(value);
+                return self.collect(collect, done, rest, scope);
+            }
+            Kont::Record { mut done, rest, scope } => {
+                done.push((rest[0].0.as_str(), value));
+                self.record_next(done, &rest[1..], scope)
+            }
+            Kont::Proj(index) => match value {
+                Value::Tuple(mut items) if (1..=items.len()).contains(&index) => {
+                    Control::Return(items.swap_remove(index - 1))
+                }
+                v => return Err(EvalError::InvalidArgument(proj(index, readback(&v)))),
+            },
+            Kont::Select(field) => match value {
+                Value::Record(fields) => match fields.iter().position(|(name, _)| *name == field) {
+                    Some(n) => Control::Return(fields[n].1.clone()),
+                    None => {
+                        return Err(EvalError::MissingField {
+                            field: field.to_string(),
+                            record: readback(&Value::Record(fields)),
+                        })
+                    }
+                },
+                v => return Err(EvalError::InvalidArgument(select(readback(&v), field))),
+            },
+            Kont::Update(updates, scope) => match value {
+                Value::Record(fields) => return self.update_next(fields, updates, scope),
+                v => {
+                    return Err(EvalError::InvalidArgument(Term::Update(Box::new(readback(&v)), updates.to_vec())))
+                }
+            },
+            Kont::UpdateField { mut fields, index, rest, scope } => {
+                fields[index].1 = value;
+                return self.update_next(fields, rest, scope);
+            }
+            Kont::Let { name, rest, body, scope } => self.bind_next(rest, body, scope.bind(name, value)),
+            Kont::Case(arms, scope) => return self.match_arms(value, arms, scope),
+            Kont::Guard { value: v, body, rest, arm_scope, scope } => match value {
+                Value::Bool(true) => Control::Eval(body, arm_scope),
+                Value::Bool(false) => return self.match_arms(v, rest, scope),
+                c => return Err(EvalError::NonBooleanCondition(readback(&c))),
+            },
+            Kont::Global(name) => {
+                self.pending.pop();
+                self.cache.insert(name, value.clone());
+                Control::Return(value)
+            }
+            Kont::Map { f, mut done, rest } => {
+                done.push(value);
+                return self.map_next(f, done, &rest);
+            }
+            Kont::Filter { p, mut kept, current, rest } => {
+                match value {
+                    Value::Bool(true) => kept.push(current),
+                    Value::Bool(false) => {}
+                    c => return Err(EvalError::NonBooleanCondition(readback(&c))),
+                }
+                return self.filter_next(p, kept, &rest);
+            }
+            Kont::FoldrFun { f, items, acc } => {
+                self.stack.push(Kont::FoldrAcc { f, items });
+                return self.apply(value, acc);
+            }
+            Kont::FoldrAcc { f, items } => return self.foldr_next(f, items, value),
+        })
+    }
+
+    fn apply(&mut self, f: Value<'a>, arg: Value<'a>) -> Result<Control<'a>, EvalError> {
+        match f {
+            Value::Closure { param, body, env } => Ok(Control::Eval(body, env.bind(param, arg))),
+            Value::Con { tag, arity, mut fields } if fields.len() < arity => {
+                fields.push(arg);
+                Ok(Control::Return(Value::Con { tag, arity, fields }))
+            }
+            Value::PatternClosure { pattern, body, env } => {
+                let mut scope = env;
+                if match_value(pattern, &arg, &mut scope) {
+                    Ok(Control::Eval(body, scope))
+                } else {
+                    Err(EvalError::MatchFailure(readback(&arg)))
+                }
+            }
+            Value::Builtin { builtin, mut args } => {
+                args.push(arg);
+                self.call_builtin(builtin, args)
+            }
+            f => Err(EvalError::NotAFunction(readback(&f))),
+        }
+    }
+
+    // Applies a built-in function once all of its arguments are present. The ones that call
+    // a function argument push a frame for each call.
+    fn call_builtin(&mut self, builtin: Builtin, args: Vec<Value<'a>>) -> Result<Control<'a>, EvalError> {
+        if args.len() < builtin.arity() {
+            return Ok(Control::Return(Value::Builtin { builtin, args }));
+        }
+        let mut args = args;
+        match (builtin, args.pop()) {
+            (Builtin::Map, Some(Value::List(items))) => {
+                let f = args.pop().expect("map takes two arguments");
+                self.map_next(f, Vec::new(), &items)
+            }
+            (Builtin::Filter, Some(Value::List(items))) => {
+                let p = args.pop().expect("filter takes two arguments");
+                self.filter_next(p, Vec::new(), &items)
+            }
+            (Builtin::Foldr, Some(Value::List(items))) => {
+                let z = args.pop().expect("foldr takes three arguments");
+                let f = args.pop().expect("foldr takes three arguments");
+                self.foldr_next(f, items.iter().cloned().collect(), z)
+            }
+            (_, last) => {
+                args.extend(last);
+                Ok(Control::Return(apply_builtin(builtin, args)?))
+            }
+        }
+    }
+
+    fn map_next(&mut self, f: Value<'a>, done: Vec<Value<'a>>, rest: &List<'a>) -> Result<Control<'a>, EvalError> {
+        match rest.uncons() {
+            Some((x, rest)) => {
+                self.stack.push(Kont::Map { f: f.clone(), done, rest: rest.clone() });
+                self.apply(f, x.clone())
+            }
+            None => Ok(Control::Return(Value::List(done.into_iter().collect()))),
+        }
+    }
+
+    fn filter_next(&mut self, p: Value<'a>, kept: Vec<Value<'a>>, rest: &List<'a>) -> Result<Control<'a>, EvalError> {
+        match rest.uncons() {
+            Some((x, rest)) => {
+                self.stack.push(Kont::Filter { p: p.clone(), kept, current: x.clone(), rest: rest.clone() });
+                self.apply(p, x.clone())
+            }
+            None => Ok(Control::Return(Value::List(kept.into_iter().collect()))),
+        }
+    }
+
+    fn foldr_next(
+        &mut self,
+        f: Value<'a>,
+        mut items: Vec<Value<'a>>,
+        acc: Value<'a>,
+    ) -> Result<Control<'a>, EvalError> {
+        match items.pop() {
+            Some(x) => {
+                self.stack.push(Kont::FoldrFun { f: f.clone(), items, acc });
+                self.apply(f, x)
+            }
+            None => Ok(Control::Return(acc)),
+        }
+    }
+
+    // Evaluates the remaining terms of a constructor, list, tuple or built-in application.
+    fn collect(
+        &mut self,
+        collect: Collect<'a>,
+        done: Vec<Value<'a>>,
+        rest: &'a [Term],
+        scope: Scope<'a>,
+    ) -> Result<Control<'a>, EvalError> {
+        if let [t, rest @ ..] = rest {
+            return Ok(self.push(Kont::Items { collect, done, rest, scope: scope.clone() }, t, scope));
+        }
+        Ok(Control::Return(match collect {
+            Collect::Con(tag, arity) => Value::Con { tag, arity, fields: done },
+            Collect::List => Value::List(done.into_iter().collect()),
+            Collect::Tuple => Value::Tuple(done),
+            Collect::Builtin(builtin) => return self.call_builtin(builtin, done),
+        }))
+    }
+
+    fn record_next(
+        &mut self,
+        mut done: Vec<(&'a str, Value<'a>)>,
+        rest: &'a [(String, Term)],
+        scope: Scope<'a>,
+    ) -> Control<'a> {
+        match rest.first() {
+            Some((_, t)) => self.push(Kont::Record { done, rest, scope: scope.clone() }, t, scope),
+            None => {
+                done.sort_by_key(|(name, _)| *name);
+                Control::Return(Value::Record(done))
+            }
+        }
+    }
+
+    fn update_next(
+        &mut self,
+        fields: Vec<(&'a str, Value<'a>)>,
+        updates: &'a [(String, Term)],
+        scope: Scope<'a>,
+    ) -> Result<Control<'a>, EvalError> {
+        let Some(((name, t), rest)) = updates.split_first() else {
+            return Ok(Control::Return(Value::Record(fields)));
+        };
+        let Some(index) = fields.iter().position(|(field, _)| field == name) else {
+            return Err(EvalError::MissingField {
+                field: name.clone(),
+                record: readback(&Value::Record(fields)),
+            });
+        };
+        Ok(self.push(Kont::UpdateField { fields, index, rest, scope: scope.clone() }, t, scope))
+    }
+
+    // Evaluates the bindings of a `let` one after the other, then its body.
+    fn bind_next(&mut self, bindings: &'a [(String, Term)], body: &'a Term, scope: Scope<'a>) -> Control<'a> {
+        match bindings.split_first() {
+            Some(((name, t), rest)) => self.push(Kont::Let { name, rest, body, scope: scope.clone() }, t, scope),
+            None => Control::Eval(body, scope),
+        }
+    }
+
+    // Tries the arms of a `case` in order. A guard is evaluated on the stack and comes back
+    // here with the remaining arms if it is false.
+    fn match_arms(&mut self, value: Value<'a>, arms: &'a [Arm], scope: Scope<'a>) -> Result<Control<'a>, EvalError> {
+        for (n, arm) in arms.iter().enumerate() {
+            let mut arm_scope = scope.clone();
+            if !match_value(&arm.pattern, &value, &mut arm_scope) {
+                continue;
+            }
+            return Ok(match &arm.guard {
+                Some(guard) => {
+                    let kont = Kont::Guard {
+                        value,
+                        body: &arm.body,
+                        rest: &arms[n + 1..],
+                        arm_scope: arm_scope.clone(),
+                        scope,
+                    };
+                    self.push(kont, guard, arm_scope)
+                }
+                None => Control::Eval(&arm.body, arm_scope),
+            });
+        }
+        Err(EvalError::MatchFailure(readback(&value)))
+    }
+
+    // Top-level bindings are closed terms, so they are evaluated in an empty scope.
+    fn global(&mut self, name: &str) -> Result<Control<'a>, EvalError> {
+        if let Some(v) = self.cache.get(name) {
+            return Ok(Control::Return(v.clone()));
+        }
+        let Some((name, term)) = self.globals.get_key_value(name) else {
+            return match Builtin::from_name(name) {
+                Some(builtin) => Ok(Control::Return(Value::Builtin { builtin, args: vec![] })),
+                None => Err(EvalError::UnboundVariable(name.to_string())),
+            };
+        };
+        if self.pending.contains(&name.as_str()) {
+            return Err(EvalError::CyclicBinding(name.clone()));
+        }
+        self.pending.push(name);
+        Ok(self.push(Kont::Global(name), term, Scope::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{empty_env, eval};
+    use crate::parser::parse_main_program;
+    use crate::value::eval_value;
+
+    // Runs a program and returns its value and the largest continuation stack on the way.
+    fn run(input: &str) -> (Result<Term, EvalError>, usize) {
+        let prog = parse_main_program(input).unwrap();
+        let mut machine = Machine::new(&prog.env);
+        let result = machine.run(&prog.main).map(|v| readback(&v));
+        (result, machine.max_stack)
+    }
+
+    #[test]
+    fn test_eval_cek_agrees_with_eval() {
+        let inputs = [
+            r#"
+                fac = λn. if n == 0 then 1 else n * fac (n - 1);
+                fib = λn. if n < 2 then n else fib (n - 1) + fib (n - 2);
+                twice = λf x. f (f x);
+                main = (twice (λn. fac n) 3 + fib 10, fac 25);
+            "#,
+            r#"
+                data List = Nil | Cons a List;
+                sum = λxs. case xs of Nil -> 0 | Cons x rest if x > 0 -> x + sum rest | Cons _ rest -> sum rest;
+                main = sum (Cons 1 (Cons (0 - 5) (Cons 2 Nil)));
+            "#,
+            r#"
+                twice = λf x. f (f x);
+                main = (foldr (λx acc. x :: acc) [] (map (twice (λx. x + 1)) (append [1] (tail [0, 2]))),
+                        filter (λx. x % 2 == 0) [1, 2, 3, 4], show (length "abc") ++ "!", show 1.5);
+            "#,
+            r#"
+                divmod = λn d. (n / d, n - n / d * d);
+                add = λ(q, r) k. q * k + r;
+                origin = { y = 0, x = 0 };
+                move = λp d. { p | x = p.x + d, y = p.y - d };
+                main = (add (divmod 17 5) 10, #1 (fst (divmod 7 2), true), move (move origin 2) 3, (move origin 1).y);
+            "#,
+            r#"
+                member = λx xs. not (null xs) && (head xs == x || member x (tail xs));
+                main = let n = 10; step = 2 in
+                    letrec go = λi. if i >= n then [] else i :: go (i + step)
+                    in (go 0, let n = 1 in n, member 3 [1, 2, 3], member 4 [1, 2], (λx y. x) 1);
+            "#,
+        ];
+        for input in inputs {
+            let prog = parse_main_program(input).unwrap();
+            let v = eval_cek(&prog.env, &prog.main).unwrap();
+            assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v), "{input}");
+            assert_eq!(readback(&eval_value(&prog.env, &prog.main).unwrap()), readback(&v), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_eval_cek_deep_recursion() {
+        // Every pending addition is a frame on the heap, not on the native stack
+        let (result, max_stack) = run("sum = λn. if n == 0 then 0 else n + sum (n - 1); main = sum 100000;");
+        assert_eq!(Ok(i(5000050000)), result);
+        assert!(max_stack > 100000);
+    }
+
+    #[test]
+    fn test_eval_cek_tail_calls_in_constant_space() {
+        let input = r#"
+            loop = λn acc. if n == 0 then acc else loop (n - 1) (acc + n);
+            even = λn. if n == 0 then true else odd (n - 1);
+            odd = λn. if n == 0 then false else even (n - 1);
+            main = (loop 100000 0, case even 100001 of true -> 1 | false -> 0);
+        "#;
+        let (result, max_stack) = run(input);
+        assert_eq!(Ok(tuple(vec![i(5000050000), i(0)])), result);
+        assert!(max_stack < 10, "the stack grew to {max_stack} frames");
+    }
+
+    #[test]
+    fn test_eval_cek_long_lists() {
+        // Consing onto a list shares it instead of copying it, so this takes linear time
+        let input = r#"
+            build = λn acc. if n == 0 then acc else build (n - 1) (n :: acc);
+            main = let xs = build 100000 [] in (length xs, head xs, head (tail (xs ++ xs)));
+        "#;
+        let (result, _) = run(input);
+        assert_eq!(Ok(tuple(vec![i(100000), i(1), i(2)])), result);
+    }
+
+    #[test]
+    fn test_eval_cek_errors() {
+        let env = empty_env();
+        let term = app(abs("x", var("x")), var("y"));
+        assert_eq!(EvalError::UnboundVariable("y".to_string()), eval_cek(&env, &term).unwrap_err());
+        let term = case(i(1), vec![arm(Pattern::Var("x".to_string()), Some(var("x")), i(0))]);
+        assert_eq!(EvalError::NonBooleanCondition(i(1)), eval_cek(&env, &term).unwrap_err());
+        let term = case(i(1), vec![arm(Pattern::Int(0.into()), None, i(0))]);
+        assert_eq!(EvalError::MatchFailure(i(1)), eval_cek(&env, &term).unwrap_err());
+        let term = app(app(var("map"), i(1)), list(vec![i(1)]));
+        assert_eq!(EvalError::NotAFunction(i(1)), eval_cek(&env, &term).unwrap_err());
+        let (result, _) = run("ones = 1 :: ones; main = head ones;");
+        assert_eq!(Err(EvalError::CyclicBinding("ones".to_string())), result);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::eval::free_variables;
+use crate::term::*;
+use std::collections::HashSet;
+use std::fmt;
+
+/// A problem found by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// The top-level binding the problem occurs in.
+    pub binding: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A variable that is neither bound locally nor at the top level, nor a built-in.
+    UnboundVariable(String),
+    /// A top-level name that is defined more than once. The last definition is used.
+    DuplicateDefinition(String),
+    /// A top-level binding that `main` does not use, directly or indirectly.
+    UnusedBinding(String),
+    /// A local variable that hides another variable of the same name: a local one, a
+    /// top-level binding or a built-in.
+    Shadowing(String),
+}
+
+impl Diagnostic {
+    /// Errors keep a program from running; warnings are only reported.
+    pub fn is_error(&self) -> bool {
+        matches!(self.kind, DiagnosticKind::UnboundVariable(_) | DiagnosticKind::DuplicateDefinition(_))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", if self.is_error() { "error" } else { "warning" })?;
+        match &self.kind {
+            DiagnosticKind::UnboundVariable(x) => write!(f, "unbound variable `{x}`")?,
+            DiagnosticKind::DuplicateDefinition(x) => write!(f, "`{x}` is defined more than once")?,
+            DiagnosticKind::UnusedBinding(x) => write!(f, "`{x}` is not used by `main`")?,
+            DiagnosticKind::Shadowing(x) => write!(f, "`{x}` shadows another variable")?,
+        }
+        if let Some(binding) = &self.binding {
+            write!(f, " in `{binding}`")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks a program without running it: reports duplicate top-level names, unbound variables
+/// and shadowed variables in every binding, and the bindings that `main` does not use.
+///
+/// The diagnostics are in the order the names are defined.
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut defined = HashSet::new();
+    for name in &program.names {
+        if !defined.insert(name) {
+            let kind = DiagnosticKind::DuplicateDefinition(name.clone());
+            if !diagnostics.contains(&Diagnostic { kind: kind.clone(), binding: None }) {
+                diagnostics.push(Diagnostic { kind, binding: None });
+            }
+        }
+    }
+
+    let constructors: HashSet<&String> = program
+        .data
+        .iter()
+        .flat_map(|decl| decl.constructors.iter().map(|(tag, _)| tag))
+        .collect();
+    let mut bindings: Vec<&String> = Vec::new();
+    for name in &program.names {
+        if !constructors.contains(name) && !bindings.contains(&name) {
+            bindings.push(name);
+        }
+    }
+
+    for name in &bindings {
+        let mut checker = Checker { env: &program.env, binding: name, scope: Vec::new(), diagnostics: Vec::new() };
+        checker.check(&program.env[*name]);
+        diagnostics.extend(checker.diagnostics);
+    }
+
+    // The bindings that main refers to, directly or through other bindings
+    let mut used = HashSet::from(["main".to_string()]);
+    let mut pending = vec!["main".to_string()];
+    while let Some(name) = pending.pop() {
+        for x in program.env.get(&name).map(free_variables).unwrap_or_default() {
+            if program.env.contains_key(&x) && used.insert(x.clone()) {
+                pending.push(x);
+            }
+        }
+    }
+    for name in bindings {
+        if !used.contains(name) {
+            diagnostics.push(Diagnostic { kind: DiagnosticKind::UnusedBinding(name.clone()), binding: None });
+        }
+    }
+    diagnostics
+}
+
+// Walks the term of one top-level binding, keeping track of the local variables in scope.
+struct Checker<'a> {
+    env: &'a Env,
+    binding: &'a str,
+    scope: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, kind: DiagnosticKind) {
+        let diagnostic = Diagnostic { kind, binding: Some(self.binding.to_string()) };
+        if !self.diagnostics.contains(&diagnostic) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // Brings a local variable into scope; `unbind` removes it again.
+    fn bind(&mut self, x: &str) {
+        if self.scope.iter().any(|y| y == x) || self.env.contains_key(x) || Builtin::from_name(x).is_some() {
+            self.report(DiagnosticKind::Shadowing(x.to_string()));
+        }
+        self.scope.push(x.to_string());
+    }
+
+    fn unbind(&mut self, count: usize) {
+        self.scope.truncate(self.scope.len() - count);
+    }
+
+    // Checks a term in which the variables bound by `pattern` are in scope.
+    fn check_under(&mut self, pattern: &Pattern, terms: &[&Term]) {
+        let binders = pattern.binders();
+        for x in &binders {
+            self.bind(x);
+        }
+        for t in terms {
+            self.check(t);
+        }
+        self.unbind(binders.len());
+    }
+
+    fn check(&mut self, term: &Term) {
+        match term {
+            Term::Var(x) => {
+                let bound = self.scope.contains(x) || self.env.contains_key(x) || Builtin::from_name(x).is_some();
+                if !bound {
+                    self.report(DiagnosticKind::UnboundVariable(x.clone()));
+                }
+            }
+            Term::Abs(param, body) => {
+                self.bind(param);
+                self.check(body);
+                self.unbind(1);
+            }
+            Term::AbsPattern(pattern, body) => self.check_under(pattern, &[body]),
+            Term::App(t1, t2) | Term::PrimOp(_, t1, t2) => {
+                self.check(t1);
+                self.check(t2);
+            }
+            Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => {}
+            Term::If(cond, t1, t2) => {
+                self.check(cond);
+                self.check(t1);
+                self.check(t2);
+            }
+            Term::Con(_, _, items) | Term::List(items) | Term::Builtin(_, items) | Term::Tuple(items) => {
+                items.iter().for_each(|t| self.check(t));
+            }
+            Term::Proj(_, t) | Term::Select(t, _) => self.check(t),
+            Term::Record(fields) => fields.iter().for_each(|(_, t)| self.check(t)),
+            Term::Update(t, fields) => {
+                self.check(t);
+                fields.iter().for_each(|(_, t)| self.check(t));
+            }
+            Term::Case(scrutinee, arms) => {
+                self.check(scrutinee);
+                for arm in arms {
+                    let terms: Vec<&Term> = arm.guard.iter().chain([&arm.body]).collect();
+                    self.check_under(&arm.pattern, &terms);
+                }
+            }
+            Term::Let(bindings, body) => {
+                for (x, t) in bindings {
+                    self.check(t);
+                    self.bind(x);
+                }
+                self.check(body);
+                self.unbind(bindings.len());
+            }
+            Term::LetRec(bindings, body) => {
+                for (x, _) in bindings {
+                    self.bind(x);
+                }
+                bindings.iter().for_each(|(_, t)| self.check(t));
+                self.check(body);
+                self.unbind(bindings.len());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_main_program;
+
+    fn diagnostics(input: &str) -> Vec<String> {
+        let prog = parse_main_program(input).unwrap();
+        check(&prog).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        let input = r#"
+            data List a = Nil | Cons a (List a);
+            sum = λxs. case xs of Nil -> 0 | Cons x rest -> x + sum rest;
+            main = let xs = Cons 1 (Cons 2 Nil) in sum xs + length [1];
+        "#;
+        assert!(diagnostics(input).is_empty());
+    }
+
+    #[test]
+    fn test_unbound_variables() {
+        let input = r#"
+            f = λx. x + y + y;
+            main = f (λz. z + w) + (letrec g = λn. g n in g 1);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let diagnostics = check(&prog);
+        let unbound = |x: &str, binding: &str| Diagnostic {
+            kind: DiagnosticKind::UnboundVariable(x.to_string()),
+            binding: Some(binding.to_string()),
+        };
+        assert_eq!(vec![unbound("y", "f"), unbound("w", "main")], diagnostics);
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+        assert_eq!("error: unbound variable `y` in `f`", diagnostics[0].to_string());
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        let input = r#"
+            data T = A | B;
+            x = 1;
+            x = 2;
+            A = 3;
+            main = (x, A, B);
+        "#;
+        assert_eq!(
+            vec!["error: `x` is defined more than once", "error: `A` is defined more than once"],
+            diagnostics(input)
+        );
+    }
+
+    #[test]
+    fn test_unused_bindings() {
+        let input = r#"
+            helper = λx. x;
+            used = λx. indirect x;
+            indirect = λx. x;
+            loop = λx. loop x;
+            data Unused = Never;
+            main = used 1;
+        "#;
+        assert_eq!(
+            vec!["warning: `helper` is not used by `main`", "warning: `loop` is not used by `main`"],
+            diagnostics(input)
+        );
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let input = r#"
+            x = 1;
+            main = (λx. x) (λy. λy. case y of (show, _) -> show) + (let z = x; z = z in z + x);
+        "#;
+        assert_eq!(
+            vec![
+                "warning: `x` shadows another variable in `main`",
+                "warning: `y` shadows another variable in `main`",
+                "warning: `show` shadows another variable in `main`",
+                "warning: `z` shadows another variable in `main`",
+            ],
+            diagnostics(input)
+        );
+        let diagnostics = check(&parse_main_program("main = λx. λx. x;").unwrap());
+        assert!(!diagnostics[0].is_error());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::eval::free_variables;
+use crate::term::*;
+use std::collections::HashMap;
+
+/// Splits the bindings of `env` into groups of mutually recursive definitions (the strongly
+/// connected components of the dependency graph). The groups are in dependency order: a
+/// group only refers to names defined in itself or in an earlier group.
+pub fn binding_groups(env: &Env) -> Vec<Vec<String>> {
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    let deps: HashMap<&str, Vec<String>> = names
+        .iter()
+        .map(|name| {
+            let mut refs: Vec<String> = free_variables(&env[*name])
+                .into_iter()
+                .filter(|x| env.contains_key(x))
+                .collect();
+            refs.sort();
+            (name.as_str(), refs)
+        })
+        .collect();
+
+    let mut tarjan = Tarjan {
+        deps: &deps,
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: Vec::new(),
+        groups: Vec::new(),
+    };
+    for name in names {
+        if !tarjan.index.contains_key(name.as_str()) {
+            tarjan.visit(name);
+        }
+    }
+    tarjan.groups
+}
+
+// Tarjan's algorithm emits each component after all components it depends on.
+struct Tarjan<'a> {
+    deps: &'a HashMap<&'a str, Vec<String>>,
+    index: HashMap<&'a str, usize>,
+    low: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    groups: Vec<Vec<String>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, name: &'a str) {
+        let index = self.index.len();
+        self.index.insert(name, index);
+        self.low.insert(name, index);
+        self.stack.push(name);
+
+        for dep in &self.deps[name] {
+            let dep = dep.as_str();
+            if !self.index.contains_key(dep) {
+                self.visit(dep);
+                let low = self.low[name].min(self.low[dep]);
+                self.low.insert(name, low);
+            } else if self.stack.contains(&dep) {
+                let low = self.low[name].min(self.index[dep]);
+                self.low.insert(name, low);
+            }
+        }
+
+        if self.low[name] == self.index[name] {
+            let mut group = Vec::new();
+            while let Some(n) = self.stack.pop() {
+                group.push(n.to_string());
+                if n == name {
+                    break;
+                }
+            }
+            group.sort();
+            self.groups.push(group);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binding_groups() {
+        let env: Env = [
+            ("main", app(var("even"), var("x"))),
+            ("even", abs("n", app(var("odd"), var("n")))),
+            ("odd", abs("n", app(var("even"), var("n")))),
+            ("x", i(4)),
+        ]
+        .into_iter()
+        .map(|(name, term)| (name.to_string(), term))
+        .collect();
+
+        let groups = binding_groups(&env);
+        assert_eq!(
+            vec![
+                vec!["even".to_string(), "odd".to_string()],
+                vec!["x".to_string()],
+                vec!["main".to_string()],
+            ],
+            groups
+        );
+    }
+}
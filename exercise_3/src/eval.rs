@@ -1,106 +1,987 @@
+use crate::bignum::BigInt;
 use crate::term::*;
 use std::collections::HashSet;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::fmt;
+use crate::pretty::op_symbol;
 
 /// Creates a new empty environment.
 pub fn empty_env() -> Env {
     HashMap::new()
 }
 
+/// Errors raised while evaluating a term. Each variant carries the offending subterm.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// Integer division by zero, e.g. `(1 / 0)`.
+    DivisionByZero(Term),
+    /// An integer power whose exponent is too large to compute, e.g. `(2 ^ 10000000000)`.
+    ArithmeticOverflow(Term),
+    /// A primitive operation was applied to operands of the wrong type (strict mode only).
+    TypeMismatch { op: PrimOp, left: Term, right: Term },
+    /// The condition of an `if` did not evaluate to a boolean (strict mode only).
+    NonBooleanCondition(Term),
+    /// The left-hand side of an application is not a function (strict mode only).
+    NotAFunction(Term),
+    /// A variable is neither bound by a lambda nor by the environment (strict mode only).
+    UnboundVariable(String),
+    /// No arm of a case expression matches the scrutinee.
+    MatchFailure(Term),
+    /// `head` or `tail` of the empty list.
+    EmptyList(Term),
+    /// A built-in function was applied to arguments of the wrong type (strict mode only).
+    InvalidArgument(Term),
+    /// A field that the record does not have was selected or updated.
+    MissingField { field: String, record: Term },
+    /// `substring` was asked for characters beyond the end of the string.
+    IndexOutOfRange(Term),
+    /// An integer raised to a negative power, e.g. `(2 ^ -1)`.
+    NegativeExponent(Term),
+    /// A top-level binding that is not a function needs its own value, e.g. `x = x + 1;`.
+    CyclicBinding(String),
+    /// Evaluation reached one of the `EvalLimits` after `steps` reductions.
+    LimitExceeded { limit: Limit, steps: usize },
+}
+
+/// One of the `EvalLimits`, with its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps(usize),
+    Depth(usize),
+    TermSize(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "more than {n} reductions"),
+            Limit::Depth(n) => write!(f, "recursion deeper than {n}"),
+            Limit::TermSize(n) => write!(f, "a term larger than {n}"),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::DivisionByZero(t) => write!(f, "division by zero in {t}"),
+            EvalError::ArithmeticOverflow(t) => write!(f, "arithmetic overflow in {t}"),
+            EvalError::TypeMismatch { op, left, right } => write!(
+                f,
+                "type mismatch: cannot apply {} to {left} and {right}",
+                op_symbol(*op)
+            ),
+            EvalError::NonBooleanCondition(t) => write!(f, "condition is not a boolean: {t}"),
+            EvalError::NotAFunction(t) => write!(f, "cannot apply {t}, it is not a function"),
+            EvalError::UnboundVariable(x) => write!(f, "unbound variable `{x}`"),
+            EvalError::MatchFailure(t) => write!(f, "no pattern matches {t}"),
+            EvalError::EmptyList(t) => write!(f, "empty list in {t}"),
+            EvalError::InvalidArgument(t) => write!(f, "invalid argument in {t}"),
+            EvalError::MissingField { field, record } => write!(f, "record {record} has no field `{field}`"),
+            EvalError::IndexOutOfRange(t) => write!(f, "index out of range in {t}"),
+            EvalError::NegativeExponent(t) => write!(f, "negative exponent in {t}"),
+            EvalError::CyclicBinding(x) => write!(f, "the value of `{x}` depends on itself"),
+            EvalError::LimitExceeded { limit, steps } => {
+                write!(f, "evaluation stopped after {steps} reductions: {limit}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl EvalError {
+    // Applies `f` to the term the error carries.
+    fn map_term(self, f: impl Fn(&Term) -> Term) -> EvalError {
+        match self {
+            EvalError::DivisionByZero(t) => EvalError::DivisionByZero(f(&t)),
+            EvalError::ArithmeticOverflow(t) => EvalError::ArithmeticOverflow(f(&t)),
+            EvalError::TypeMismatch { op, left, right } => {
+                EvalError::TypeMismatch { op, left: f(&left), right: f(&right) }
+            }
+            EvalError::NonBooleanCondition(t) => EvalError::NonBooleanCondition(f(&t)),
+            EvalError::NotAFunction(t) => EvalError::NotAFunction(f(&t)),
+            EvalError::MatchFailure(t) => EvalError::MatchFailure(f(&t)),
+            EvalError::EmptyList(t) => EvalError::EmptyList(f(&t)),
+            EvalError::InvalidArgument(t) => EvalError::InvalidArgument(f(&t)),
+            EvalError::MissingField { field, record } => EvalError::MissingField { field, record: f(&record) },
+            EvalError::IndexOutOfRange(t) => EvalError::IndexOutOfRange(f(&t)),
+            EvalError::NegativeExponent(t) => EvalError::NegativeExponent(f(&t)),
+            EvalError::UnboundVariable(_) | EvalError::CyclicBinding(_) | EvalError::LimitExceeded { .. } => self,
+        }
+    }
+}
+
+/// Options that control how terms are evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct EvalOptions {
+    /// Report terms that cannot be reduced any further (type mismatches, non-boolean
+    /// conditions, unbound variables) as errors instead of returning them unevaluated.
+    /// Abstractions are values in strict mode; their bodies are not evaluated.
+    pub strict: bool,
+    /// When the argument of an abstraction or the value of a `let` binding is evaluated.
+    pub strategy: EvalStrategy,
+    /// How much work evaluation may do before it stops with `EvalError::LimitExceeded`.
+    pub limits: EvalLimits,
+}
+
+/// Bounds on evaluation, so that a program that does not terminate fails with
+/// `EvalError::LimitExceeded` instead of running forever or overflowing the stack.
+/// `None` means unbounded. By default only the depth is bounded, by `DEFAULT_MAX_DEPTH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// The number of reductions, as recorded by `eval_traced`.
+    pub max_steps: Option<usize>,
+    /// How deeply evaluation may nest, roughly the depth of the call stack of the program.
+    /// Evaluation runs on a thread whose stack has room for `DEFAULT_MAX_DEPTH` levels, see
+    /// `on_eval_stack`, so a larger limit can overflow it.
+    pub max_depth: Option<usize>,
+    /// The size of a term produced by a reduction, see `Term::size`.
+    pub max_term_size: Option<usize>,
+}
+
+/// The `max_depth` of `EvalLimits::default()`, so that unbounded recursion stops with
+/// `EvalError::LimitExceeded` instead of overflowing the stack. The `fun` binary uses it too.
+pub const DEFAULT_MAX_DEPTH: usize = 50_000;
+
+// The stack of the thread that evaluates. It has room for `DEFAULT_MAX_DEPTH` levels, also in
+// a debug build, where a level takes about ten kilobytes.
+const EVAL_STACK_SIZE: usize = 1 << 30;
+
+thread_local! {
+    // Whether the current thread was started by `on_eval_stack`
+    static ON_EVAL_STACK: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` on a thread with a stack that has room for evaluation up to `DEFAULT_MAX_DEPTH`,
+/// or right away if the current thread is such a thread already. Evaluation does this by
+/// itself; callers that recurse deeply in other ways, like the `fun` binary, can run all
+/// their work on one such thread.
+pub fn on_eval_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    if ON_EVAL_STACK.with(Cell::get) {
+        return f();
+    }
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(EVAL_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                ON_EVAL_STACK.with(|on| on.set(true));
+                f()
+            })
+            .expect("cannot start an evaluation thread");
+        thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        EvalLimits { max_steps: None, max_depth: Some(DEFAULT_MAX_DEPTH), max_term_size: None }
+    }
+}
+
+/// When arguments are evaluated.
+///
+/// Only abstractions and `let` take their arguments unevaluated under the lazy strategies.
+/// Primitive operations, built-ins, constructors and patterns still need theirs evaluated,
+/// so data structures are always fully evaluated. The strategies therefore agree on every
+/// program that terminates without an error, except that a function in the result can
+/// contain an argument that was never needed and is left unevaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvalStrategy {
+    /// Arguments are evaluated before they are passed.
+    #[default]
+    CallByValue,
+    /// Arguments are passed unevaluated and evaluated every time they are used.
+    CallByName,
+    /// Arguments are passed unevaluated and evaluated the first time they are used; all
+    /// uses share that value.
+    CallByNeed,
+}
+
 // Evaluates a term:
 // env is a list of variable bindings
-pub fn eval(env: &Env, term: &Term) -> Result<Term, String> {
-    match term {
-        Term::App(t1, t2) => {
-            let l = eval(env, t1)?;
-            let r = eval(env, t2)?;
-            // If left is an abstraction, substitute the parameter with right
-            if let Term::Abs(param, body) = l {
-                // Remove the parameter from the environment
-                // The parameter shadows any outer bindings
-                let mut env = env.clone();
-                env.remove(&param);
-
-                eval(&env, &substitute(&body, &param, &r))
+pub fn eval(env: &Env, term: &Term) -> Result<Term, EvalError> {
+    eval_with(env, term, &EvalOptions::default())
+}
+
+/// Evaluates a term in strict mode, see `EvalOptions::strict`.
+pub fn eval_strict(env: &Env, term: &Term) -> Result<Term, EvalError> {
+    eval_with(env, term, &EvalOptions { strict: true, ..EvalOptions::default() })
+}
+
+/// Evaluates a term with the given options.
+///
+/// A top-level binding is evaluated the first time it is used, so after the bindings it
+/// depends on, and its value is reused from then on.
+pub fn eval_with(env: &Env, term: &Term, options: &EvalOptions) -> Result<Term, EvalError> {
+    Evaluator::new(options, None).run(env, term)
+}
+
+/// A single reduction performed during evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reduction {
+    /// The reducible term, with its subterms already evaluated.
+    pub redex: Term,
+    /// The term it reduced to.
+    pub result: Term,
+}
+
+/// Evaluates a term and records every reduction (beta, primitive operation, if, global lookup)
+/// in the order they were performed, also when evaluation fails.
+pub fn eval_traced(env: &Env, term: &Term, options: &EvalOptions) -> (Result<Term, EvalError>, Vec<Reduction>) {
+    let mut evaluator = Evaluator::new(options, Some(Vec::new()));
+    let result = evaluator.run(env, term);
+    (result, evaluator.trace.unwrap_or_default())
+}
+
+struct Evaluator<'a> {
+    options: &'a EvalOptions,
+    trace: Option<Vec<Reduction>>,
+    // The values of the top-level bindings evaluated so far
+    globals: HashMap<String, Term>,
+    // The top-level bindings being evaluated, innermost last
+    pending: Vec<String>,
+    // The arguments passed under call-by-need, referred to by `thunk_name`
+    thunks: Vec<Thunk>,
+    // The number of reductions so far and the current nesting of `eval`
+    steps: usize,
+    depth: usize,
+}
+
+// An argument that is evaluated when it is first needed.
+struct Thunk {
+    term: Term,
+    value: Option<Term>,
+}
+
+// The name of the variable that stands for a thunk. It cannot be written in a program.
+fn thunk_name(index: usize) -> String {
+    format!("<thunk {index}>")
+}
+
+fn thunk_index(name: &str) -> Option<usize> {
+    name.strip_prefix("<thunk ")?.strip_suffix('>')?.parse().ok()
+}
+
+impl<'a> Evaluator<'a> {
+    fn new(options: &'a EvalOptions, trace: Option<Vec<Reduction>>) -> Self {
+        Evaluator {
+            options,
+            trace,
+            globals: HashMap::new(),
+            pending: Vec::new(),
+            thunks: Vec::new(),
+            steps: 0,
+            depth: 0,
+        }
+    }
+
+    // Evaluates a term and replaces the thunks that are left in the result or the error.
+    fn run(&mut self, env: &Env, term: &Term) -> Result<Term, EvalError> {
+        on_eval_stack(|| {
+            let result = self.eval(env, term);
+            if self.thunks.is_empty() {
+                return result;
+            }
+            result.map(|v| self.resolve(&v)).map_err(|e| e.map_term(|t| self.resolve(t)))
+        })
+    }
+
+    // Counts a reduction, records it in the trace and checks the limits on steps and term size.
+    fn record(&mut self, redex: impl FnOnce() -> Term, result: &Term) -> Result<(), EvalError> {
+        if let Some(mut trace) = self.trace.take() {
+            trace.push(Reduction { redex: self.resolve(&redex()), result: self.resolve(result) });
+            self.trace = Some(trace);
+        }
+        self.steps += 1;
+        if let Some(max) = self.options.limits.max_steps.filter(|max| self.steps > *max) {
+            return Err(self.limit_exceeded(Limit::Steps(max)));
+        }
+        Ok(())
+    }
+
+    // Records a reduction that substitutes into a term. Only these can make a term larger than
+    // the redex, so only their results are measured against `max_term_size`; computing the
+    // size costs about as much as the substitution did.
+    fn record_substitution(&mut self, redex: impl FnOnce() -> Term, result: &Term) -> Result<(), EvalError> {
+        self.record(redex, result)?;
+        if let Some(max) = self.options.limits.max_term_size.filter(|max| result.size() > *max) {
+            return Err(self.limit_exceeded(Limit::TermSize(max)));
+        }
+        Ok(())
+    }
+
+    fn limit_exceeded(&self, limit: Limit) -> EvalError {
+        EvalError::LimitExceeded { limit, steps: self.steps }
+    }
+
+    fn eval(&mut self, env: &Env, term: &Term) -> Result<Term, EvalError> {
+        if let Some(max) = self.options.limits.max_depth.filter(|max| self.depth >= *max) {
+            return Err(self.limit_exceeded(Limit::Depth(max)));
+        }
+        self.depth += 1;
+        let result = self.eval_term(env, term);
+        self.depth -= 1;
+        result
+    }
+
+    // Defers the evaluation of an argument under the lazy strategies.
+    fn delay(&mut self, term: &Term) -> Term {
+        match term {
+            // Variables and values are cheap to evaluate again
+            Term::Var(_) | Term::Abs(..) | Term::AbsPattern(..) => term.clone(),
+            Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => term.clone(),
+            _ if self.options.strategy == EvalStrategy::CallByNeed => {
+                self.thunks.push(Thunk { term: term.clone(), value: None });
+                var(&thunk_name(self.thunks.len() - 1))
+            }
+            _ => term.clone(),
+        }
+    }
+
+    // Evaluates a thunk the first time it is needed and remembers its value.
+    fn force(&mut self, env: &Env, index: usize) -> Result<Term, EvalError> {
+        if let Some(v) = &self.thunks[index].value {
+            return Ok(v.clone());
+        }
+        let term = self.thunks[index].term.clone();
+        let v = self.eval(env, &term)?;
+        self.thunks[index].value = Some(v.clone());
+        Ok(v)
+    }
+
+    // Replaces every thunk in a term by its value, or by its term if it was never needed.
+    fn resolve(&self, term: &Term) -> Term {
+        if self.thunks.is_empty() {
+            return term.clone();
+        }
+        free_variables(term).into_iter().fold(term.clone(), |t, x| match thunk_index(&x) {
+            Some(index) => {
+                let thunk = &self.thunks[index];
+                substitute(&t, &x, &self.resolve(thunk.value.as_ref().unwrap_or(&thunk.term)))
+            }
+            None => t,
+        })
+    }
+
+    // Evaluates a top-level binding the first time it is used and remembers its value.
+    fn global(&mut self, env: &Env, name: &str, term: &Term) -> Result<Term, EvalError> {
+        if let Some(v) = self.globals.get(name) {
+            return Ok(v.clone());
+        }
+        if self.pending.iter().any(|x| x == name) {
+            return match term {
+                // Only evaluation under the abstraction gets here, which leaves the call
+                Term::Abs(..) | Term::AbsPattern(..) => Ok(var(name)),
+                _ => Err(EvalError::CyclicBinding(name.to_string())),
+            };
+        }
+        self.pending.push(name.to_string());
+        let result = self.eval(env, term);
+        self.pending.pop();
+        let v = result?;
+        self.globals.insert(name.to_string(), v.clone());
+        Ok(v)
+    }
+
+    // Every kind of term that needs more than a line is evaluated by its own method. That keeps
+    // the stack frame of this function small, and with it the stack used per level of `max_depth`.
+    fn eval_term(&mut self, env: &Env, term: &Term) -> Result<Term, EvalError> {
+        let options = self.options;
+        match term {
+            Term::App(t1, t2) => self.eval_app(env, t1, t2),
+
+            Term::Abs(..) | Term::AbsPattern(..) if options.strict => Ok(term.clone()),
+
+            // eagerly evaluate the body of an abstraction
+            Term::Abs(param, body) => self.eval_under_abs(env, term, param, body),
+
+            Term::AbsPattern(pattern, body) => self.eval_under_abs_pattern(env, term, pattern, body),
+
+            Term::Var(x) => self.eval_var(env, term, x),
+
+            Term::Int(_) | Term::Float(_) => Ok(term.clone()),
+
+            Term::Bool(v) => Ok(b(*v)),
+
+            Term::Str(_) | Term::Char(_) => Ok(term.clone()),
+
+            Term::Con(tag, arity, fields) => Ok(Term::Con(tag.clone(), *arity, self.eval_all(env, fields)?)),
+
+            Term::If(cond, t1, t2) => self.eval_if(env, cond, t1, t2),
+
+            Term::List(items) => Ok(Term::List(self.eval_all(env, items)?)),
+
+            Term::Builtin(builtin, args) => {
+                let args = self.eval_all(env, args)?;
+                self.call_builtin(env, *builtin, args)
+            }
+
+            Term::Tuple(items) => Ok(Term::Tuple(self.eval_all(env, items)?)),
+
+            Term::Proj(index, t) => self.eval_proj(env, *index, t),
+
+            // Fields are kept sorted by name, so equal records are equal terms
+            Term::Record(fields) => self.eval_record(env, fields),
+
+            Term::Select(t, field) => self.eval_select(env, t, field),
+
+            Term::Update(t, updates) => self.eval_update(env, t, updates),
+
+            Term::Let(bindings, body) => self.eval_let(env, bindings, body),
+
+            // Every name is replaced by the group itself, so a recursive call unfolds it again
+            Term::LetRec(bindings, body) => self.eval_letrec(env, term, bindings, body),
+
+            Term::Case(scrutinee, arms) => self.eval_case(env, scrutinee, arms),
+
+            Term::PrimOp(op, t1, t2) => self.eval_primop(env, *op, t1, t2),
+        }
+    }
+}
+
+impl Evaluator<'_> {
+    // Evaluates an application, applying the function to the argument.
+    fn eval_app(&mut self, env: &Env, t1: &Term, t2: &Term) -> Result<Term, EvalError> {
+        let options = self.options;
+        let l = self.eval(env, t1)?;
+        let r = match (&l, options.strategy) {
+            (Term::Abs(..), EvalStrategy::CallByName | EvalStrategy::CallByNeed) => self.delay(t2),
+            _ => self.eval(env, t2)?,
+        };
+        // If left is an abstraction, substitute the parameter with right
+        if let Term::Abs(param, body) = &l {
+            // The argument replaces every occurrence of the parameter, so no name in the
+            // result refers to it any more; the remaining free names are globals
+            let result = substitute(body, param, &r);
+            self.record_substitution(|| app(l.clone(), r.clone()), &result)?;
+            self.eval(env, &result)
+        } else if let Term::Con(tag, arity, fields) = &l {
+            // A constructor that still expects fields takes the argument as its next field
+            if fields.len() < *arity {
+                let mut fields = fields.clone();
+                fields.push(r);
+                Ok(Term::Con(tag.clone(), *arity, fields))
+            } else if options.strict {
+                Err(EvalError::NotAFunction(l))
             } else {
                 Ok(app(l, r))
             }
+        } else if let Term::AbsPattern(pattern, body) = &l {
+            let mut bindings = Vec::new();
+            match match_pattern(pattern, &r, &mut bindings) {
+                Some(true) => {
+                    let result = substitute_all(body, &bindings);
+                    self.record_substitution(|| app(l.clone(), r.clone()), &result)?;
+                    self.eval(env, &result)
+                }
+                Some(false) => Err(EvalError::MatchFailure(r)),
+                None => Ok(app(l, r)),
+            }
+        } else if let Term::Builtin(builtin, args) = &l {
+            let mut args = args.clone();
+            args.push(r);
+            self.call_builtin(env, *builtin, args)
+        } else if options.strict {
+            Err(EvalError::NotAFunction(l))
+        } else {
+            Ok(app(l, r))
         }
+    }
+
+    // Evaluates the body of an abstraction, which lenient mode does eagerly.
+    fn eval_under_abs(&mut self, env: &Env, term: &Term, param: &str, body: &Term) -> Result<Term, EvalError> {
+        let local = local_name(param, term, env);
+        let body = substitute(body, param, &var(&local));
+        Ok(abs(&local, self.eval(env, &body)?))
+    }
 
-        // eagerly evaluate the body of an abstraction
-        Term::Abs(param, body) => Ok(abs(param, eval(env, body)?)), 
+    // Like `eval_under_abs`, for an abstraction that takes its argument apart with a pattern.
+    fn eval_under_abs_pattern(
+        &mut self,
+        env: &Env,
+        term: &Term,
+        pattern: &Pattern,
+        body: &Term,
+    ) -> Result<Term, EvalError> {
+        let (mut pattern, mut body) = (pattern.clone(), body.clone());
+        for x in pattern.binders() {
+            let local = local_name(&x, term, env);
+            pattern = rename_pattern(&pattern, &x, &local);
+            body = substitute(&body, &x, &var(&local));
+        }
+        Ok(abs_pattern(pattern, self.eval(env, &body)?))
+    }
 
-        Term::Var(x) => {
-            // Look up the variable in the environment
-            if let Some(t) = env.get(x) {
-                Ok(t.clone())
-            } else {
-                Ok(var(x))
+    // Evaluates a variable: a thunk, a top-level binding or a built-in.
+    fn eval_var(&mut self, env: &Env, term: &Term, x: &str) -> Result<Term, EvalError> {
+        let options = self.options;
+        if let Some(index) = thunk_index(x) {
+            self.force(env, index)
+        } else if let Some(t) = env.get(x) {
+            let v = self.global(env, x, t)?;
+            self.record(|| term.clone(), &v)?;
+            Ok(v)
+        } else if let Some(builtin) = Builtin::from_name(x) {
+            Ok(Term::Builtin(builtin, vec![]))
+        } else if options.strict {
+            Err(EvalError::UnboundVariable(x.to_string()))
+        } else {
+            Ok(var(x))
+        }
+    }
+
+    // Evaluates the condition and then one of the branches.
+    fn eval_if(&mut self, env: &Env, cond: &Term, t1: &Term, t2: &Term) -> Result<Term, EvalError> {
+        let options = self.options;
+        let c = self.eval(env, cond)?;
+        if let Term::Bool(b) = c {
+            let branch = if b { t1 } else { t2 };
+            self.record(|| ifte(c.clone(), t1.clone(), t2.clone()), branch)?;
+            self.eval(env, branch)
+        } else if options.strict {
+            Err(EvalError::NonBooleanCondition(c))
+        } else {
+            Ok(ifte(c, t1.clone(), t2.clone()))
+        }
+    }
+
+    // Evaluates the n-th component of a tuple.
+    fn eval_proj(&mut self, env: &Env, index: usize, t: &Term) -> Result<Term, EvalError> {
+        let options = self.options;
+        let v = self.eval(env, t)?;
+        match &v {
+            Term::Tuple(items) if (1..=items.len()).contains(&index) => {
+                let result = items[index - 1].clone();
+                self.record(|| proj(index, v.clone()), &result)?;
+                Ok(result)
             }
-        },
+            _ if options.strict => Err(EvalError::InvalidArgument(proj(index, v))),
+            _ => Ok(proj(index, v)),
+        }
+    }
+
+    // Evaluates the fields of a record and sorts them by name.
+    fn eval_record(&mut self, env: &Env, fields: &[(String, Term)]) -> Result<Term, EvalError> {
+        let mut fields = fields
+            .iter()
+            .map(|(name, t)| Ok((name.clone(), self.eval(env, t)?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(Term::Record(fields))
+    }
+
+    // Evaluates a field of a record.
+    fn eval_select(&mut self, env: &Env, t: &Term, field: &str) -> Result<Term, EvalError> {
+        let options = self.options;
+        let v = self.eval(env, t)?;
+        match &v {
+            Term::Record(fields) => match fields.iter().find(|(name, _)| name == field) {
+                Some((_, result)) => {
+                    self.record(|| select(v.clone(), field), result)?;
+                    Ok(result.clone())
+                }
+                None => Err(EvalError::MissingField { field: field.to_string(), record: v }),
+            },
+            _ if options.strict => Err(EvalError::InvalidArgument(select(v, field))),
+            _ => Ok(select(v, field)),
+        }
+    }
 
+    // Evaluates a record update.
+    fn eval_update(&mut self, env: &Env, t: &Term, updates: &[(String, Term)]) -> Result<Term, EvalError> {
+        let options = self.options;
+        let v = self.eval(env, t)?;
+        let Term::Record(fields) = &v else {
+            let stuck = Term::Update(Box::new(v), updates.to_vec());
+            return if options.strict { Err(EvalError::InvalidArgument(stuck)) } else { Ok(stuck) };
+        };
+        let mut fields = fields.clone();
+        for (name, t) in updates {
+            let Some(field) = fields.iter_mut().find(|(field, _)| field == name) else {
+                return Err(EvalError::MissingField { field: name.clone(), record: v });
+            };
+            field.1 = self.eval(env, t)?;
+        }
+        Ok(Term::Record(fields))
+    }
 
-        Term::Int(n) => Ok(i(*n)),
+    // Substitutes the first binding of a `let` and evaluates the rest.
+    fn eval_let(&mut self, env: &Env, bindings: &[(String, Term)], body: &Term) -> Result<Term, EvalError> {
+        let Some(((x, t), rest)) = bindings.split_first() else {
+            return self.eval(env, body);
+        };
+        let v = match self.options.strategy {
+            EvalStrategy::CallByValue => self.eval(env, t)?,
+            EvalStrategy::CallByName | EvalStrategy::CallByNeed => self.delay(t),
+        };
+        let result = match substitute_let(rest, body, x, &v) {
+            (rest, t) if rest.is_empty() => t,
+            (rest, t) => Term::Let(rest, Box::new(t)),
+        };
+        self.record_substitution(
+            || {
+                let mut bindings = bindings.to_vec();
+                bindings[0].1 = v.clone();
+                Term::Let(bindings, Box::new(body.clone()))
+            },
+            &result,
+        )?;
+        self.eval(env, &result)
+    }
 
-        Term::Bool(v) => Ok(b(*v)),
+    // Unfolds a letrec and evaluates its body.
+    fn eval_letrec(
+        &mut self,
+        env: &Env,
+        term: &Term,
+        bindings: &[(String, Term)],
+        body: &Term,
+    ) -> Result<Term, EvalError> {
+        let unfolded = match body {
+            Term::Var(x) => match bindings.iter().find(|(name, _)| name == x) {
+                Some((_, t)) => unfold_letrec(bindings, t),
+                None => unfold_letrec(bindings, body),
+            },
+            _ => unfold_letrec(bindings, body),
+        };
+        self.record_substitution(|| term.clone(), &unfolded)?;
+        self.eval(env, &unfolded)
+    }
 
-        Term::If(cond, t1, t2) => {
-            let c = eval(env, cond)?;
-            if let Term::Bool(b) = c {
-                if b {
-                    return eval(env, t1);
-                } else {
-                    return eval(env, t2);
+    // Evaluates the first arm of a case expression that matches.
+    fn eval_case(&mut self, env: &Env, scrutinee: &Term, arms: &[Arm]) -> Result<Term, EvalError> {
+        let options = self.options;
+        let v = self.eval(env, scrutinee)?;
+        for arm in arms {
+            let mut bindings = Vec::new();
+            match match_pattern(&arm.pattern, &v, &mut bindings) {
+                Some(true) => {}
+                Some(false) => continue,
+                // The scrutinee is not evaluated far enough to decide
+                None => return Ok(case(v, arms.to_vec())),
+            }
+            let bind = |t: &Term| substitute_all(t, &bindings);
+            if let Some(guard) = &arm.guard {
+                match self.eval(env, &bind(guard))? {
+                    Term::Bool(true) => {}
+                    Term::Bool(false) => continue,
+                    c if options.strict => return Err(EvalError::NonBooleanCondition(c)),
+                    _ => return Ok(case(v, arms.to_vec())),
                 }
-            } else {
-                Ok(ifte(c, *t1.clone(), *t2.clone()))
             }
+            let body = bind(&arm.body);
+            self.record_substitution(|| case(v.clone(), arms.to_vec()), &body)?;
+            return self.eval(env, &body);
         }
+        Err(EvalError::MatchFailure(v))
+    }
 
-        Term::PrimOp(op, t1, t2) => {
-            let l = eval(env, t1)?;
-            let r = eval(env, t2)?;
-            match op {
-                PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div  => {
-                    if let (Term::Int(n1), Term::Int(n2)) = (&l, &r) {
-                        Ok(i(match op {
-                            PrimOp::Add => n1 + n2,
-                            PrimOp::Sub => n1 - n2,
-                            PrimOp::Mul => n1 * n2,
-                            PrimOp::Div => n1 / n2, // TODO: handle division by zero and add a test
-                            _ => unreachable!()
-                        }))
-                    } else {
-                        Ok(primop(*op, l, r))
-                    }
+    // Evaluates a primitive operation.
+    fn eval_primop(&mut self, env: &Env, op: PrimOp, t1: &Term, t2: &Term) -> Result<Term, EvalError> {
+        let options = self.options;
+        let l = self.eval(env, t1)?;
+        // `&&` and `||` do not evaluate the right operand if the left one decides the result
+        if let PrimOp::And | PrimOp::Or = op {
+            match l {
+                Term::Bool(v) if v == (op == PrimOp::Or) => {
+                    self.record(|| primop(op, l.clone(), t2.clone()), &l)?;
+                    return Ok(l);
                 }
+                Term::Bool(_) => {}
+                // Like the branches of an `if`, the right operand waits for the left one
+                _ => return stuck_primop(op, l, t2.clone(), options),
+            }
+        }
+        let r = self.eval(env, t2)?;
+        let redex = self.trace.is_some().then(|| primop(op, l.clone(), r.clone()));
+        let result = apply_primop(op, l, r, options)?;
+        // Operands of the wrong type leave the operation as it is, which is no reduction
+        if !matches!(result, Term::PrimOp(..)) {
+            self.record(|| redex.expect("the redex is kept while tracing"), &result)?;
+        }
+        Ok(result)
+    }
 
-                PrimOp::Lt | PrimOp::Gt => {
-                    if let (Term::Int(n1), Term::Int(n2)) = (&l, &r) {
-                        Ok(b(match op {
-                            PrimOp::Lt => n1 < n2,
-                            PrimOp::Gt => n1 > n2,
-                            _ => unreachable!()
-                        }))
-                    } else {
-                        Ok(primop(*op, l, r))
+    // Evaluates terms from left to right.
+    fn eval_all(&mut self, env: &Env, terms: &[Term]) -> Result<Vec<Term>, EvalError> {
+        terms.iter().map(|t| self.eval(env, t)).collect()
+    }
+
+    // Applies a built-in function to evaluated arguments once all of them are present.
+    fn call_builtin(&mut self, env: &Env, builtin: Builtin, args: Vec<Term>) -> Result<Term, EvalError> {
+        if args.len() < builtin.arity() {
+            return Ok(Term::Builtin(builtin, args));
+        }
+        // The fully applied built-in, for errors and the trace
+        let redex = || Term::Builtin(builtin, args.clone());
+        let result = match (builtin, args.as_slice()) {
+            (Builtin::Head, [Term::List(items)]) => {
+                items.first().cloned().ok_or_else(|| EvalError::EmptyList(redex()))?
+            }
+            (Builtin::Tail, [Term::List(items)]) if !items.is_empty() => list(items[1..].to_vec()),
+            (Builtin::Tail, [Term::List(_)]) => return Err(EvalError::EmptyList(redex())),
+            (Builtin::Null, [Term::List(items)]) => b(items.is_empty()),
+            (Builtin::Length, [Term::List(items)]) => i(items.len() as i64),
+            (Builtin::Length, [Term::Str(s)]) => i(s.chars().count() as i64),
+            (Builtin::Substring, [Term::Str(s), Term::Int(start), Term::Int(len)]) => {
+                let chars: Vec<char> = s.chars().collect();
+                match (to_index(start), to_index(len)) {
+                    (Some(start), Some(len)) if start.checked_add(len).is_some_and(|end| end <= chars.len()) => {
+                        Term::Str(chars[start..start + len].iter().collect())
                     }
+                    _ => return Err(EvalError::IndexOutOfRange(redex())),
                 }
-               
-                PrimOp::Eq => {
-                    if let (Term::Int(n1), Term::Int(n2)) = (&l, &r) {
-                        Ok(b(n1 == n2))
-                    } else if let (Term::Bool(b1), Term::Bool(b2)) = (&l, &r) {
-                        Ok(b(b1 == b2))
-                    } else {
-                        Ok(primop(*op, l, r))
+            }
+            (Builtin::Show, [v @ (Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_))]) => {
+                Term::Str(v.to_string())
+            }
+            (Builtin::Append, [Term::List(xs), Term::List(ys)]) => list([xs.as_slice(), ys].concat()),
+            (Builtin::Map, [f, Term::List(items)]) => list(
+                items
+                    .iter()
+                    .map(|x| self.eval(env, &app(f.clone(), x.clone())))
+                    .collect::<Result<_, _>>()?,
+            ),
+            (Builtin::Filter, [p, Term::List(items)]) => {
+                let mut kept = Vec::new();
+                for x in items {
+                    match self.eval(env, &app(p.clone(), x.clone()))? {
+                        Term::Bool(true) => kept.push(x.clone()),
+                        Term::Bool(false) => {}
+                        c if self.options.strict => return Err(EvalError::NonBooleanCondition(c)),
+                        _ => return Ok(redex()),
                     }
                 }
+                list(kept)
+            }
+            (Builtin::Not, [Term::Bool(v)]) => b(!v),
+            (Builtin::ToFloat, [Term::Int(n)]) => Term::Float(n.to_f64()),
+            (Builtin::Floor | Builtin::Round | Builtin::Truncate, [Term::Float(x)]) => {
+                Term::Int(float_to_int(builtin, *x).ok_or_else(|| EvalError::InvalidArgument(redex()))?)
+            }
+            (Builtin::Fst, [Term::Tuple(items)]) if items.len() == 2 => items[0].clone(),
+            (Builtin::Snd, [Term::Tuple(items)]) if items.len() == 2 => items[1].clone(),
+            (Builtin::Foldr, [f, z, Term::List(items)]) => items.iter().rev().try_fold(z.clone(), |acc, x| {
+                self.eval(env, &app(app(f.clone(), x.clone()), acc))
+            })?,
+            _ if self.options.strict => return Err(EvalError::InvalidArgument(redex())),
+            _ => return Ok(redex()),
+        };
+        self.record(redex, &result)?;
+        Ok(result)
+    }
+}
+
+// Converts an integer to an index, if it is one.
+pub(crate) fn to_index(n: &BigInt) -> Option<usize> {
+    n.to_i64().and_then(|n| usize::try_from(n).ok())
+}
+
+/// Applies a primitive operation to evaluated operands.
+pub(crate) fn apply_primop(op: PrimOp, l: Term, r: Term, options: &EvalOptions) -> Result<Term, EvalError> {
+    let result = match op {
+        PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div | PrimOp::Mod | PrimOp::Pow => {
+            if let (Term::Int(n1), Term::Int(n2)) = (&l, &r) {
+                if matches!(op, PrimOp::Div | PrimOp::Mod) && n2.is_zero() {
+                    return Err(EvalError::DivisionByZero(primop(op, l, r)));
+                }
+                if op == PrimOp::Pow && n2.is_negative() {
+                    return Err(EvalError::NegativeExponent(primop(op, l, r)));
+                }
+                int_arithmetic(op, n1, n2)
+                    .map(Term::Int)
+                    .ok_or_else(|| EvalError::ArithmeticOverflow(primop(op, l.clone(), r.clone())))?
+            } else if let (Some(x), Some(y)) = (as_float(&l), as_float(&r)) {
+                Term::Float(float_arithmetic(op, x, y))
+            } else {
+                return stuck_primop(op, l, r, options);
+            }
+        }
+
+        PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge => {
+            // None if a float operand is NaN, which makes every comparison false
+            let ordering = match (&l, &r) {
+                (Term::Int(n1), Term::Int(n2)) => Some(n1.cmp(n2)),
+                (Term::Str(s1), Term::Str(s2)) => Some(s1.cmp(s2)),
+                (Term::Char(c1), Term::Char(c2)) => Some(c1.cmp(c2)),
+                _ => match (as_float(&l), as_float(&r)) {
+                    (Some(x), Some(y)) => x.partial_cmp(&y),
+                    _ => return stuck_primop(op, l, r, options),
+                },
+            };
+            b(ordering.is_some_and(|ordering| compare(op, ordering)))
+        }
+       
+        PrimOp::And | PrimOp::Or => match (&l, &r) {
+            (Term::Bool(b1), Term::Bool(b2)) if op == PrimOp::And => b(*b1 && *b2),
+            (Term::Bool(b1), Term::Bool(b2)) => b(*b1 || *b2),
+            _ => return stuck_primop(op, l, r, options),
+        },
+
+        PrimOp::Concat => match (l, r) {
+            (Term::Str(s1), Term::Str(s2)) => Term::Str(s1 + &s2),
+            (Term::List(xs), Term::List(ys)) => list(xs.into_iter().chain(ys).collect()),
+            (l, r) => return stuck_primop(op, l, r, options),
+        },
+
+        PrimOp::Cons => {
+            if let Term::List(items) = r {
+                list(std::iter::once(l).chain(items).collect())
+            } else {
+                return stuck_primop(op, l, r, options);
             }
         }
+
+        PrimOp::Eq | PrimOp::Ne => {
+            let equal = if let (Term::Int(n1), Term::Int(n2)) = (&l, &r) {
+                n1 == n2
+            } else if let (Some(x), Some(y)) = (as_float(&l), as_float(&r)) {
+                x == y
+            } else if let (Term::Bool(b1), Term::Bool(b2)) = (&l, &r) {
+                b1 == b2
+            } else if let (Term::Str(s1), Term::Str(s2)) = (&l, &r) {
+                s1 == s2
+            } else if let (Term::Char(c1), Term::Char(c2)) = (&l, &r) {
+                c1 == c2
+            } else if matches!(l, Term::Con(..) | Term::List(_) | Term::Tuple(_) | Term::Record(_))
+                && matches!(r, Term::Con(..) | Term::List(_) | Term::Tuple(_) | Term::Record(_))
+                && is_data(&l)
+                && is_data(&r)
+            {
+                l == r
+            } else {
+                return stuck_primop(op, l, r, options);
+            };
+            b(equal == (op == PrimOp::Eq))
+        }
+    };
+    Ok(result)
+}
+
+// An arithmetic operation on integers, after division by zero and negative exponents were
+// ruled out. None if the result is too large to compute.
+pub(crate) fn int_arithmetic(op: PrimOp, n1: &BigInt, n2: &BigInt) -> Option<BigInt> {
+    match op {
+        PrimOp::Add => Some(n1 + n2),
+        PrimOp::Sub => Some(n1 - n2),
+        PrimOp::Mul => Some(n1 * n2),
+        PrimOp::Div => n1.checked_div(n2),
+        // Like `/`, rounds towards zero: n == n / d * d + n % d
+        PrimOp::Mod => n1.checked_rem(n2),
+        PrimOp::Pow => match n2.to_i64().and_then(|e| u32::try_from(e).ok()) {
+            Some(e) => Some(n1.pow(e)),
+            // Only 0, 1 and -1 have powers this large that can be computed
+            None => match n1.to_i64() {
+                Some(0 | 1) => Some(n1.clone()),
+                Some(-1) if n2.checked_rem(&BigInt::from(2)).is_some_and(|r| r.is_zero()) => Some(BigInt::from(1)),
+                Some(-1) => Some(n1.clone()),
+                _ => None,
+            },
+        },
+        _ => unreachable!("{op:?} is not arithmetic"),
+    }
+}
+
+// An arithmetic operation on floats. Division by zero and the like follow IEEE 754, e.g.
+// 1.0 / 0.0 is infinite.
+pub(crate) fn float_arithmetic(op: PrimOp, x: f64, y: f64) -> f64 {
+    match op {
+        PrimOp::Add => x + y,
+        PrimOp::Sub => x - y,
+        PrimOp::Mul => x * y,
+        PrimOp::Div => x / y,
+        PrimOp::Mod => x % y,
+        PrimOp::Pow => x.powf(y),
+        _ => unreachable!("{op:?} is not arithmetic"),
+    }
+}
+
+// Whether `<`, `>`, `<=` or `>=` holds for operands that compare as `ordering`.
+pub(crate) fn compare(op: PrimOp, ordering: std::cmp::Ordering) -> bool {
+    match op {
+        PrimOp::Lt => ordering.is_lt(),
+        PrimOp::Gt => ordering.is_gt(),
+        PrimOp::Le => ordering.is_le(),
+        PrimOp::Ge => ordering.is_ge(),
+        _ => unreachable!("{op:?} is not a comparison"),
+    }
+}
+
+// Rounds a float to an integer as `floor`, `round` or `truncate` do. None for infinities and NaN.
+pub(crate) fn float_to_int(builtin: Builtin, x: f64) -> Option<BigInt> {
+    BigInt::from_f64(match builtin {
+        Builtin::Floor => x.floor(),
+        Builtin::Round => x.round(),
+        _ => x.trunc(),
+    })
+}
+
+// A number as a float: integers are converted when they meet a float.
+fn as_float(term: &Term) -> Option<f64> {
+    match term {
+        Term::Int(n) => Some(n.to_f64()),
+        Term::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+// Fully applied constructors, lists, tuples and records whose elements are data again can be compared structurally.
+fn is_data(term: &Term) -> bool {
+    match term {
+        Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => true,
+        Term::Con(_, arity, fields) => fields.len() == *arity && fields.iter().all(is_data),
+        Term::List(items) | Term::Tuple(items) => items.iter().all(is_data),
+        Term::Record(fields) => fields.iter().all(|(_, t)| is_data(t)),
+        _ => false,
+    }
+}
+
+// Matches an evaluated term against a pattern, collecting the bound variables.
+// Returns None if the term is not a value yet, so it cannot be decided whether it matches.
+pub(crate) fn match_pattern(pattern: &Pattern, term: &Term, bindings: &mut Vec<(String, Term)>) -> Option<bool> {
+    match (pattern, term) {
+        (Pattern::Var(x), _) => {
+            bindings.push((x.clone(), term.clone()));
+            Some(true)
+        }
+        (Pattern::Wildcard, _) => Some(true),
+        (_, Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_))
+        | (_, Term::Abs(..) | Term::AbsPattern(..))
+        | (_, Term::Con(..) | Term::List(_) | Term::Builtin(..) | Term::Tuple(_) | Term::Record(_)) => match (pattern, term) {
+            (Pattern::Int(n1), Term::Int(n2)) => Some(n1 == n2),
+            (Pattern::Bool(b1), Term::Bool(b2)) => Some(b1 == b2),
+            (Pattern::Str(s1), Term::Str(s2)) => Some(s1 == s2),
+            (Pattern::Char(c1), Term::Char(c2)) => Some(c1 == c2),
+            (Pattern::Con(tag, args), Term::Con(t, _, fields)) if tag == t => match_all(args, fields, bindings),
+            (Pattern::Tuple(args), Term::Tuple(fields)) => match_all(args, fields, bindings),
+            _ => Some(false),
+        },
+        _ => None,
     }
 }
 
+fn match_all(patterns: &[Pattern], terms: &[Term], bindings: &mut Vec<(String, Term)>) -> Option<bool> {
+    if patterns.len() != terms.len() {
+        return Some(false);
+    }
+    for (pattern, term) in patterns.iter().zip(terms) {
+        if !match_pattern(pattern, term, bindings)? {
+            return Some(false);
+        }
+    }
+    Some(true)
+}
+
+// A primitive operation whose operands have the wrong type.
+fn stuck_primop(op: PrimOp, l: Term, r: Term, options: &EvalOptions) -> Result<Term, EvalError> {
+    if options.strict {
+        Err(EvalError::TypeMismatch { op, left: l, right: r })
+    } else {
+        Ok(primop(op, l, r))
+    }
+}
 
 /// Replace all occurrences of a variable `var` in a `term` with `replacement`.
 pub fn substitute(term: &Term, var: &str, replacement: &Term) -> Term {
@@ -124,8 +1005,9 @@ pub fn substitute(term: &Term, var: &str, replacement: &Term) -> Term {
             Box::new(substitute(t1, var, replacement)),
             Box::new(substitute(t2, var, replacement)),
         ),
-        Term::Int(n) => Term::Int(*n),
+        Term::Int(_) | Term::Float(_) => term.clone(),
         Term::Bool(b) => Term::Bool(*b),
+        Term::Str(_) | Term::Char(_) => term.clone(),
         Term::If(cond, t1, t2) => Term::If(
             Box::new(substitute(cond, var, replacement)),
             Box::new(substitute(t1, var, replacement)),
@@ -135,7 +1017,155 @@ pub fn substitute(term: &Term, var: &str, replacement: &Term) -> Term {
             *op,
             Box::new(substitute(t1, var, replacement)),
             Box::new(substitute(t2, var, replacement)),
-        )
+        ),
+        Term::Con(tag, arity, fields) => Term::Con(
+            tag.clone(),
+            *arity,
+            fields.iter().map(|t| substitute(t, var, replacement)).collect(),
+        ),
+        Term::List(items) => Term::List(items.iter().map(|t| substitute(t, var, replacement)).collect()),
+        Term::Tuple(items) => Term::Tuple(items.iter().map(|t| substitute(t, var, replacement)).collect()),
+        Term::Proj(index, t) => Term::Proj(*index, Box::new(substitute(t, var, replacement))),
+        Term::Record(fields) => Term::Record(substitute_fields(fields, var, replacement)),
+        Term::Select(t, field) => Term::Select(Box::new(substitute(t, var, replacement)), field.clone()),
+        Term::Update(t, fields) => Term::Update(
+            Box::new(substitute(t, var, replacement)),
+            substitute_fields(fields, var, replacement),
+        ),
+        Term::AbsPattern(pattern, body) => {
+            let arm = substitute_arm(&arm(*pattern.clone(), None, *body.clone()), var, replacement);
+            abs_pattern(arm.pattern, arm.body)
+        }
+        Term::Builtin(builtin, args) => Term::Builtin(
+            *builtin,
+            args.iter().map(|t| substitute(t, var, replacement)).collect(),
+        ),
+        Term::Case(scrutinee, arms) => Term::Case(
+            Box::new(substitute(scrutinee, var, replacement)),
+            arms.iter().map(|arm| substitute_arm(arm, var, replacement)).collect(),
+        ),
+        Term::Let(bindings, body) => {
+            let (bindings, body) = substitute_let(bindings, body, var, replacement);
+            Term::Let(bindings, Box::new(body))
+        }
+        Term::LetRec(bindings, _) if bindings.iter().any(|(x, _)| x == var) => term.clone(),
+        Term::LetRec(bindings, body) => {
+            let mut bindings = bindings.clone();
+            let mut body = *body.clone();
+            let free = free_variables(replacement);
+            for n in 0..bindings.len() {
+                let x = bindings[n].0.clone();
+                if !free.contains(&x) {
+                    continue;
+                }
+                // Prevent variable capture by renaming the bound name everywhere in the group
+                let fresh_var = fresh_name(&x, &Term::LetRec(bindings.clone(), Box::new(body.clone())), replacement);
+                let renamed = Term::Var(fresh_var.clone());
+                for (_, t) in &mut bindings {
+                    *t = substitute(t, &x, &renamed);
+                }
+                body = substitute(&body, &x, &renamed);
+                bindings[n].0 = fresh_var;
+            }
+            Term::LetRec(
+                substitute_fields(&bindings, var, replacement),
+                Box::new(substitute(&body, var, replacement)),
+            )
+        }
+    }
+}
+
+// Substitutes the values of pattern variables all at once. The variables are first renamed to
+// names that no value mentions, so a value substituted for one variable is neither captured by
+// another one nor substituted into itself.
+pub(crate) fn substitute_all(term: &Term, bindings: &[(String, Term)]) -> Term {
+    let values = Term::Tuple(bindings.iter().map(|(_, v)| v.clone()).collect());
+    let mut renamed = term.clone();
+    let mut fresh_vars = Vec::new();
+    for (x, _) in bindings {
+        // The names chosen so far are taken even if their variable does not occur
+        let mut taken = vec![renamed.clone()];
+        taken.extend(fresh_vars.iter().map(|y: &String| Term::Var(y.clone())));
+        let fresh_var = fresh_name(x, &Term::Tuple(taken), &values);
+        renamed = substitute(&renamed, x, &Term::Var(fresh_var.clone()));
+        fresh_vars.push(fresh_var);
+    }
+    fresh_vars.iter().zip(bindings).fold(renamed, |t, (y, (_, v))| substitute(&t, y, v))
+}
+
+// Substitutes in the bindings and the body of a `let`. Each bound name scopes over the later
+// bindings and the body, so a binding of `var` stops the substitution there.
+pub(crate) fn substitute_let(
+    bindings: &[(String, Term)],
+    body: &Term,
+    var: &str,
+    replacement: &Term,
+) -> (Vec<(String, Term)>, Term) {
+    let Some(((x, t), rest)) = bindings.split_first() else {
+        return (vec![], substitute(body, var, replacement));
+    };
+    let t = substitute(t, var, replacement);
+    let (x, (rest, body)) = if x == var {
+        (x.clone(), (rest.to_vec(), body.clone()))
+    } else if free_variables(replacement).contains(x) {
+        // Prevent variable capture by renaming the bound name
+        let fresh_var = fresh_name(x, &Term::Let(bindings.to_vec(), Box::new(body.clone())), replacement);
+        let (rest, body) = substitute_let(rest, body, x, &Term::Var(fresh_var.clone()));
+        (fresh_var, substitute_let(&rest, &body, var, replacement))
+    } else {
+        (x.clone(), substitute_let(rest, body, var, replacement))
+    };
+    let mut bindings = vec![(x, t)];
+    bindings.extend(rest);
+    (bindings, body)
+}
+
+// Replaces the names bound by a `letrec` in `term` with the group: f becomes letrec ... in f
+pub(crate) fn unfold_letrec(bindings: &[(String, Term)], term: &Term) -> Term {
+    bindings.iter().fold(term.clone(), |t, (x, _)| {
+        substitute(&t, x, &Term::LetRec(bindings.to_vec(), Box::new(Term::Var(x.clone()))))
+    })
+}
+
+fn substitute_fields(fields: &[(String, Term)], var: &str, replacement: &Term) -> Vec<(String, Term)> {
+    fields
+        .iter()
+        .map(|(name, t)| (name.clone(), substitute(t, var, replacement)))
+        .collect()
+}
+
+// Pattern variables bind in the guard and the body, like the parameter of an abstraction.
+// Also used for abstractions with a pattern parameter, which are arms without a guard.
+fn substitute_arm(arm: &Arm, var: &str, replacement: &Term) -> Arm {
+    let binders = arm.pattern.binders();
+    if binders.iter().any(|x| x == var) {
+        return arm.clone();
+    }
+    let mut arm = arm.clone();
+    let free = free_variables(replacement);
+    for x in binders.iter().filter(|x| free.contains(*x)) {
+        // Prevent variable capture by renaming the pattern variable
+        let fresh_var = fresh_name(x, &case(Term::Var(var.to_string()), vec![arm.clone()]), replacement);
+        let renamed = Term::Var(fresh_var.clone());
+        arm = Arm {
+            pattern: rename_pattern(&arm.pattern, x, &fresh_var),
+            guard: arm.guard.map(|g| substitute(&g, x, &renamed)),
+            body: substitute(&arm.body, x, &renamed),
+        };
+    }
+    Arm {
+        pattern: arm.pattern,
+        guard: arm.guard.map(|g| substitute(&g, var, replacement)),
+        body: substitute(&arm.body, var, replacement),
+    }
+}
+
+fn rename_pattern(pattern: &Pattern, from: &str, to: &str) -> Pattern {
+    match pattern {
+        Pattern::Var(x) if x == from => Pattern::Var(to.to_string()),
+        Pattern::Con(tag, args) => Pattern::Con(tag.clone(), args.iter().map(|p| rename_pattern(p, from, to)).collect()),
+        Pattern::Tuple(args) => Pattern::Tuple(args.iter().map(|p| rename_pattern(p, from, to)).collect()),
+        _ => pattern.clone(),
     }
 }
 
@@ -157,7 +1187,7 @@ pub fn free_variables(term: &Term) -> HashSet<String> {
             set.extend(free_variables(t2));
             set
         }
-        Term::Int(_) | Term::Bool(_) => HashSet::new(),
+        Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => HashSet::new(),
         Term::If(cond, t1, t2) => {
             let mut set = free_variables(cond);
             set.extend(free_variables(t1));
@@ -169,8 +1199,66 @@ pub fn free_variables(term: &Term) -> HashSet<String> {
             set.extend(free_variables(t2));
             set
         }
+        Term::Con(_, _, fields) | Term::List(fields) | Term::Builtin(_, fields) | Term::Tuple(fields) => {
+            fields.iter().flat_map(free_variables).collect()
+        }
+        Term::Proj(_, t) | Term::Select(t, _) => free_variables(t),
+        Term::Record(fields) => fields.iter().flat_map(|(_, t)| free_variables(t)).collect(),
+        Term::Update(t, fields) => {
+            let mut set = free_variables(t);
+            set.extend(fields.iter().flat_map(|(_, t)| free_variables(t)));
+            set
+        }
+        Term::AbsPattern(pattern, body) => {
+            let mut set = free_variables(body);
+            for x in pattern.binders() {
+                set.remove(&x);
+            }
+            set
+        }
+        Term::Case(scrutinee, arms) => {
+            let mut set = free_variables(scrutinee);
+            for arm in arms {
+                let mut arm_set = free_variables(&arm.body);
+                if let Some(guard) = &arm.guard {
+                    arm_set.extend(free_variables(guard));
+                }
+                for x in arm.pattern.binders() {
+                    arm_set.remove(&x);
+                }
+                set.extend(arm_set);
+            }
+            set
+        }
+        Term::Let(bindings, body) => bindings.iter().rev().fold(free_variables(body), |mut set, (x, t)| {
+            set.remove(x);
+            set.extend(free_variables(t));
+            set
+        }),
+        Term::LetRec(bindings, body) => {
+            let mut set = free_variables(body);
+            set.extend(bindings.iter().flat_map(|(_, t)| free_variables(t)));
+            for (x, _) in bindings {
+                set.remove(x);
+            }
+            set
+        }
+    }
+}
 
+// The name of a variable bound in `term` that will be evaluated under its binder. A name that
+// a global or a built-in already has is replaced by a fresh one, so that the references to the
+// variable stay apart from references to the global.
+fn local_name(name: &str, term: &Term, env: &Env) -> String {
+    let taken = |x: &str| env.contains_key(x) || Builtin::from_name(x).is_some();
+    if !taken(name) {
+        return name.to_string();
     }
+    let vars = collect_all_vars(term);
+    (1..)
+        .map(|counter| format!("{name}_{counter}"))
+        .find(|x| !taken(x) && !vars.contains(x))
+        .expect("there are infinitely many names")
 }
 
 /// Generates a fresh variable name based on `base_name` that doesn't exist in `existing_vars`.
@@ -207,7 +1295,7 @@ fn collect_all_vars(term: &Term) -> HashSet<String> {
             vars.extend(collect_all_vars(t2));
             vars
         }
-        Term::Int(_) | Term::Bool(_) => HashSet::new(),
+        Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => HashSet::new(),
         Term::If(cond, t1, t2) => {
             let mut vars = collect_all_vars(cond);
             vars.extend(collect_all_vars(t1));
@@ -219,6 +1307,40 @@ fn collect_all_vars(term: &Term) -> HashSet<String> {
             vars.extend(collect_all_vars(t2));
             vars
         }
+        Term::Con(_, _, fields) | Term::List(fields) | Term::Builtin(_, fields) | Term::Tuple(fields) => {
+            fields.iter().flat_map(collect_all_vars).collect()
+        }
+        Term::Proj(_, t) | Term::Select(t, _) => collect_all_vars(t),
+        Term::Record(fields) => fields.iter().flat_map(|(_, t)| collect_all_vars(t)).collect(),
+        Term::Update(t, fields) => {
+            let mut vars = collect_all_vars(t);
+            vars.extend(fields.iter().flat_map(|(_, t)| collect_all_vars(t)));
+            vars
+        }
+        Term::AbsPattern(pattern, body) => {
+            let mut vars = collect_all_vars(body);
+            vars.extend(pattern.binders());
+            vars
+        }
+        Term::Case(scrutinee, arms) => {
+            let mut vars = collect_all_vars(scrutinee);
+            for arm in arms {
+                vars.extend(arm.pattern.binders());
+                vars.extend(collect_all_vars(&arm.body));
+                if let Some(guard) = &arm.guard {
+                    vars.extend(collect_all_vars(guard));
+                }
+            }
+            vars
+        }
+        Term::Let(bindings, body) | Term::LetRec(bindings, body) => {
+            let mut vars = collect_all_vars(body);
+            for (x, t) in bindings {
+                vars.insert(x.clone());
+                vars.extend(collect_all_vars(t));
+            }
+            vars
+        }
     }
 }
 
@@ -287,6 +1409,629 @@ mod tests {
         assert_eq!(substituted, expected);
     }
 
+    #[test]
+    fn test_eval_division_by_zero() {
+        let term = div(i(1), sub(i(2), i(2)));
+        let err = eval(&empty_env(), &term).unwrap_err();
+        assert_eq!(EvalError::DivisionByZero(div(i(1), i(0))), err);
+    }
+
+    #[test]
+    fn test_eval_mod_and_pow() {
+        let env = empty_env();
+        assert_eq!(i(-2), eval(&env, &modulo(i(-17), i(5))).unwrap());
+        assert_eq!(i(1024), eval(&env, &pow(i(2), i(10))).unwrap());
+        assert_eq!(i(1), eval(&env, &pow(i(-1), i(1 << 40))).unwrap());
+        let err = eval(&env, &modulo(i(1), i(0))).unwrap_err();
+        assert_eq!(EvalError::DivisionByZero(modulo(i(1), i(0))), err);
+        let err = eval(&env, &pow(i(2), sub(i(0), i(1)))).unwrap_err();
+        assert_eq!(EvalError::NegativeExponent(pow(i(2), i(-1))), err);
+        assert_eq!("negative exponent in (2 ^ -1)", err.to_string());
+        let err = eval(&env, &pow(i(2), i(1 << 40))).unwrap_err();
+        assert_eq!(EvalError::ArithmeticOverflow(pow(i(2), i(1 << 40))), err);
+    }
+
+    #[test]
+    fn test_eval_logical_operators() {
+        let env = empty_env();
+        let cases = [
+            (and(b(true), le(i(1), i(1))), b(true)),
+            (or(b(false), ge(i(1), i(2))), b(false)),
+            (ne(string("a"), string("b")), b(true)),
+            (ne(list(vec![i(1)]), list(vec![i(1)])), b(false)),
+            (app(var("not"), lt(i(1), i(2))), b(false)),
+        ];
+        for (term, expected) in cases {
+            assert_eq!(expected, eval_strict(&env, &term).unwrap(), "{term}");
+        }
+        // The right operand would fail if it was evaluated
+        assert_eq!(b(false), eval_strict(&env, &and(b(false), div(i(1), i(0)))).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &or(b(true), var("undefined"))).unwrap());
+        assert!(matches!(
+            eval_strict(&env, &and(b(true), i(1))),
+            Err(EvalError::TypeMismatch { op: PrimOp::And, .. })
+        ));
+        let (_, trace) = eval_traced(&env, &or(b(true), var("x")), &EvalOptions::default());
+        assert_eq!(vec![Reduction { redex: or(b(true), var("x")), result: b(true) }], trace);
+    }
+
+    #[test]
+    fn test_eval_big_integers() {
+        let env = empty_env();
+        let big = |s: &str| Term::Int(s.parse().unwrap());
+        let term = mul(i(i64::MAX), i(2));
+        assert_eq!(big("18446744073709551614"), eval(&env, &term).unwrap());
+        let term = div(sub(pow(i(2), i(64)), i(1)), pow(i(2), i(32)));
+        assert_eq!(i(u32::MAX as i64), eval(&env, &term).unwrap());
+        let term = modulo(big("-100000000000000000000"), i(7));
+        assert_eq!(i(-2), eval(&env, &term).unwrap());
+        let term = lt(big("99999999999999999999"), big("100000000000000000000"));
+        assert_eq!(b(true), eval(&env, &term).unwrap());
+        // Results that fit into an `i64` again are small, so they compare equal to literals
+        assert_eq!(b(true), eval(&env, &eq(sub(big("9223372036854775808"), i(1)), i(i64::MAX))).unwrap());
+    }
+
+    #[test]
+    fn test_eval_floats() {
+        let env = empty_env();
+        let cases = [
+            (add(f(0.5), f(0.25)), f(0.75)),
+            (mul(i(2), f(1.5)), f(3.0)),
+            (div(i(7), i(2)), i(3)),
+            (div(i(7), f(2.0)), f(3.5)),
+            (modulo(f(7.5), i(2)), f(1.5)),
+            (pow(f(2.0), f(0.5)), f(2f64.sqrt())),
+            (lt(i(1), f(1.5)), b(true)),
+            (eq(i(2), f(2.0)), b(true)),
+            (ge(f(f64::NAN), f(0.0)), b(false)),
+            (app(var("toFloat"), i(3)), f(3.0)),
+            (app(var("floor"), f(-2.5)), i(-3)),
+            (app(var("round"), f(-2.5)), i(-3)),
+            (app(var("truncate"), f(-2.5)), i(-2)),
+            (app(var("show"), f(0.1)), string("0.1")),
+        ];
+        for (term, expected) in cases {
+            assert_eq!(expected, eval_strict(&env, &term).unwrap(), "{term}");
+        }
+        // Floats follow IEEE 754 rather than raising errors
+        let Term::Float(x) = eval(&env, &div(f(1.0), i(0))).unwrap() else { panic!("expected a float") };
+        assert_eq!(f64::INFINITY, x);
+        let term = app(var("floor"), div(f(0.0), f(0.0)));
+        let err = eval_strict(&env, &term).unwrap_err();
+        assert_eq!("invalid argument in (floor (0.0 / 0.0))", err.to_string());
+    }
+
+    #[test]
+    fn test_eval_strict_errors() {
+        let env = empty_env();
+        // lenient evaluation returns stuck terms
+        assert_eq!(add(i(1), b(true)), eval(&env, &add(i(1), b(true))).unwrap());
+        assert_eq!(
+            EvalError::TypeMismatch { op: PrimOp::Add, left: i(1), right: b(true) },
+            eval_strict(&env, &add(i(1), b(true))).unwrap_err()
+        );
+        assert_eq!(
+            EvalError::NonBooleanCondition(i(3)),
+            eval_strict(&env, &ifte(i(3), i(1), i(2))).unwrap_err()
+        );
+        assert_eq!(
+            EvalError::UnboundVariable("y".to_string()),
+            eval_strict(&env, &app(abs("x", var("x")), var("y"))).unwrap_err()
+        );
+        assert_eq!(
+            EvalError::NotAFunction(i(1)),
+            eval_strict(&env, &app(i(1), i(2))).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_eval_strict_does_not_evaluate_under_abstraction() {
+        let term = abs("x", add(var("x"), i(1)));
+        assert_eq!(term, eval_strict(&empty_env(), &term).unwrap());
+        let applied = app(term, i(2));
+        assert_eq!(i(3), eval_strict(&empty_env(), &applied).unwrap());
+    }
+
+    #[test]
+    fn test_eval_constructors() {
+        let env: Env = [("Nil", con("Nil", 0, vec![])), ("Cons", con("Cons", 2, vec![]))]
+            .into_iter()
+            .map(|(name, term)| (name.to_string(), term))
+            .collect();
+        let list = app(app(var("Cons"), i(1)), app(app(var("Cons"), add(i(1), i(1))), var("Nil")));
+        let expected = con("Cons", 2, vec![i(1), con("Cons", 2, vec![i(2), con("Nil", 0, vec![])])]);
+        assert_eq!(expected, eval_strict(&env, &list).unwrap());
+        assert_eq!("(Cons 1 (Cons 2 Nil))", expected.to_string());
+        assert_eq!(b(true), eval_strict(&env, &eq(list.clone(), list.clone())).unwrap());
+        assert_eq!(
+            EvalError::NotAFunction(con("Nil", 0, vec![])),
+            eval_strict(&env, &app(var("Nil"), i(1))).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_substitute_in_constructor() {
+        let term = con("Cons", 2, vec![var("x"), var("y")]);
+        assert_eq!(vec!["x".to_string(), "y".to_string()], {
+            let mut fv: Vec<String> = free_variables(&term).into_iter().collect();
+            fv.sort();
+            fv
+        });
+        assert_eq!(con("Cons", 2, vec![i(1), var("y")]), substitute(&term, "x", &i(1)));
+    }
+
+    #[test]
+    fn test_eval_case() {
+        let sign = |n: i64| {
+            case(
+                i(n),
+                vec![
+                    arm(Pattern::Int(0.into()), None, i(0)),
+                    arm(Pattern::Var("m".to_string()), Some(gt(var("m"), i(0))), i(1)),
+                    arm(Pattern::Wildcard, None, i(-1)),
+                ],
+            )
+        };
+        assert_eq!(i(0), eval_strict(&empty_env(), &sign(0)).unwrap());
+        assert_eq!(i(1), eval_strict(&empty_env(), &sign(7)).unwrap());
+        assert_eq!(i(-1), eval(&empty_env(), &sign(-7)).unwrap());
+
+        let pair = con("Pair", 2, vec![i(1), con("Pair", 2, vec![i(2), i(3)])]);
+        let pattern = Pattern::Con(
+            "Pair".to_string(),
+            vec![Pattern::Var("x".to_string()), Pattern::Con("Pair".to_string(), vec![Pattern::Wildcard, Pattern::Var("y".to_string())])],
+        );
+        let term = case(pair, vec![arm(pattern, None, add(var("x"), var("y")))]);
+        assert_eq!(i(4), eval_strict(&empty_env(), &term).unwrap());
+    }
+
+    #[test]
+    fn test_eval_case_constructor_binds_at_once() {
+        // case P (λz. y) 1 of P x y -> x 0, where the y in the first field is the global one
+        let mut env = empty_env();
+        env.insert("y".to_string(), i(5));
+        let pattern = Pattern::Con("P".to_string(), vec![Pattern::Var("x".to_string()), Pattern::Var("y".to_string())]);
+        let term = case(con("P", 2, vec![abs("z", var("y")), i(1)]), vec![arm(pattern, None, app(var("x"), i(0)))]);
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+    }
+
+    #[test]
+    fn test_eval_case_errors() {
+        let term = case(b(true), vec![arm(Pattern::Bool(false), None, i(0))]);
+        assert_eq!(EvalError::MatchFailure(b(true)), eval(&empty_env(), &term).unwrap_err());
+        let term = case(i(1), vec![arm(Pattern::Var("x".to_string()), Some(var("x")), i(0))]);
+        assert_eq!(EvalError::NonBooleanCondition(i(1)), eval_strict(&empty_env(), &term).unwrap_err());
+        // A free scrutinee cannot be matched against a literal pattern
+        let term = case(var("y"), vec![arm(Pattern::Int(1.into()), None, i(0))]);
+        assert_eq!(term, eval(&empty_env(), &term).unwrap());
+    }
+
+    #[test]
+    fn test_substitute_in_case() {
+        // case y of x -> x + y: x is bound by the pattern, y is free
+        let term = case(var("y"), vec![arm(Pattern::Var("x".to_string()), None, add(var("x"), var("y")))]);
+        let mut fv: Vec<String> = free_variables(&term).into_iter().collect();
+        fv.sort();
+        assert_eq!(vec!["y".to_string()], fv);
+        assert_eq!(term, substitute(&term, "x", &i(1)));
+        // Substituting x for y must rename the pattern variable
+        let expected = case(var("x"), vec![arm(Pattern::Var("x_1".to_string()), None, add(var("x_1"), var("x")))]);
+        assert_eq!(expected, substitute(&term, "y", &var("x")));
+    }
+
+    #[test]
+    fn test_eval_lists() {
+        let env = empty_env();
+        let xs = || list(vec![i(1), i(2), i(3)]);
+        let run = |t: Term| eval_strict(&env, &t).unwrap();
+        assert_eq!(xs(), run(cons(i(1), cons(add(i(1), i(1)), list(vec![i(3)])))));
+        assert_eq!(i(1), run(app(var("head"), xs())));
+        assert_eq!(list(vec![i(2), i(3)]), run(app(var("tail"), xs())));
+        assert_eq!(b(true), run(app(var("null"), list(vec![]))));
+        assert_eq!(i(3), run(app(var("length"), xs())));
+        assert_eq!(list(vec![i(1), i(2), i(3), i(1), i(2), i(3)]), run(app(app(var("append"), xs()), xs())));
+        let double = abs("x", mul(var("x"), i(2)));
+        assert_eq!(list(vec![i(2), i(4), i(6)]), run(app(app(var("map"), double), xs())));
+        let odd = abs("x", gt(sub(var("x"), mul(div(var("x"), i(2)), i(2))), i(0)));
+        assert_eq!(list(vec![i(1), i(3)]), run(app(app(var("filter"), odd), xs())));
+        let minus = abs("x", abs("acc", sub(var("x"), var("acc"))));
+        // 1 - (2 - (3 - 0))
+        assert_eq!(i(2), run(app(app(app(var("foldr"), minus), i(0)), xs())));
+        assert_eq!(b(true), run(eq(xs(), cons(i(1), list(vec![i(2), i(3)])))));
+    }
+
+    #[test]
+    fn test_eval_list_errors() {
+        let env = empty_env();
+        assert_eq!(
+            EvalError::EmptyList(Term::Builtin(Builtin::Head, vec![list(vec![])])),
+            eval(&env, &app(var("head"), list(vec![]))).unwrap_err()
+        );
+        assert_eq!("empty list in (tail [])", eval(&env, &app(var("tail"), list(vec![]))).unwrap_err().to_string());
+        assert_eq!(
+            EvalError::InvalidArgument(Term::Builtin(Builtin::Length, vec![i(1)])),
+            eval_strict(&env, &app(var("length"), i(1))).unwrap_err()
+        );
+        assert_eq!(
+            EvalError::TypeMismatch { op: PrimOp::Cons, left: i(1), right: i(2) },
+            eval_strict(&env, &cons(i(1), i(2))).unwrap_err()
+        );
+        // Definitions in the environment take precedence over the built-ins
+        let env: Env = [("length".to_string(), abs("xs", i(0)))].into_iter().collect();
+        assert_eq!(i(0), eval_strict(&env, &app(var("length"), list(vec![i(1)]))).unwrap());
+    }
+
+    #[test]
+    fn test_eval_tuples() {
+        let env = empty_env();
+        let pair = || tuple(vec![add(i(1), i(1)), b(true)]);
+        assert_eq!(tuple(vec![i(2), b(true)]), eval_strict(&env, &pair()).unwrap());
+        assert_eq!(i(2), eval_strict(&env, &app(var("fst"), pair())).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &app(var("snd"), pair())).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &proj(2, pair())).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &eq(pair(), tuple(vec![i(2), b(true)]))).unwrap());
+        assert_eq!(
+            EvalError::InvalidArgument(proj(3, tuple(vec![i(2), b(true)]))),
+            eval_strict(&env, &proj(3, pair())).unwrap_err()
+        );
+
+        // λ(x, (y, _)). x - y
+        let pattern = Pattern::Tuple(vec![
+            Pattern::Var("x".to_string()),
+            Pattern::Tuple(vec![Pattern::Var("y".to_string()), Pattern::Wildcard]),
+        ]);
+        let f = abs_pattern(pattern, sub(var("x"), var("y")));
+        let arg = tuple(vec![i(5), tuple(vec![i(3), b(false)])]);
+        assert_eq!(i(2), eval_strict(&env, &app(f.clone(), arg)).unwrap());
+        assert_eq!(f, eval_strict(&env, &f).unwrap());
+        assert_eq!(EvalError::MatchFailure(i(1)), eval(&env, &app(f, i(1))).unwrap_err());
+    }
+
+    #[test]
+    fn test_eval_tuple_pattern_binds_at_once() {
+        // The y in the first component is the global one, not the second component
+        let mut env = empty_env();
+        env.insert("y".to_string(), i(5));
+        let pattern = || Pattern::Tuple(vec![Pattern::Var("x".to_string()), Pattern::Var("y".to_string())]);
+        let arg = || tuple(vec![abs("z", var("y")), i(1)]);
+        let term = app(abs_pattern(pattern(), app(var("x"), i(0))), arg());
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+        let guard = eq(app(var("x"), i(0)), i(5));
+        let term = case(arg(), vec![arm(pattern(), Some(guard), add(app(var("x"), i(0)), var("y")))]);
+        assert_eq!(i(6), eval_strict(&env, &term).unwrap());
+        // The values may also mention the names of the pattern variables themselves
+        let term = app(abs_pattern(pattern(), tuple(vec![var("x"), var("y")])), tuple(vec![var("y"), var("x")]));
+        assert_eq!(tuple(vec![var("y"), var("x")]), eval(&empty_env(), &term).unwrap());
+    }
+
+    #[test]
+    fn test_substitute_tuple_parameters() {
+        // λ(x, y). x + z binds x and y, z is free
+        let pattern = Pattern::Tuple(vec![Pattern::Var("x".to_string()), Pattern::Var("y".to_string())]);
+        let term = abs_pattern(pattern, add(var("x"), var("z")));
+        assert_eq!(vec!["z".to_string()], free_variables(&term).into_iter().collect::<Vec<_>>());
+        assert_eq!(term, substitute(&term, "x", &i(1)));
+        // Substituting y for z must rename the component y
+        let renamed = Pattern::Tuple(vec![Pattern::Var("x".to_string()), Pattern::Var("y_1".to_string())]);
+        let expected = abs_pattern(renamed, add(var("x"), var("y")));
+        assert_eq!(expected, substitute(&term, "z", &var("y")));
+        let all = collect_all_vars(&term);
+        assert!(["x", "y", "z"].iter().all(|x| all.contains(*x)));
+    }
+
+    #[test]
+    fn test_eval_records() {
+        let env = empty_env();
+        let person = || record(vec![("name", i(1)), ("age", add(i(40), i(2)))]);
+        let sorted = record(vec![("age", i(42)), ("name", i(1))]);
+        assert_eq!(sorted, eval_strict(&env, &person()).unwrap());
+        assert_eq!(i(42), eval_strict(&env, &select(person(), "age")).unwrap());
+        let older = update(person(), vec![("age", add(select(person(), "age"), i(1)))]);
+        assert_eq!(record(vec![("age", i(43)), ("name", i(1))]), eval_strict(&env, &older).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &eq(person(), sorted.clone())).unwrap());
+    }
+
+    #[test]
+    fn test_eval_record_errors() {
+        let env = empty_env();
+        let r = || record(vec![("x", i(1))]);
+        let missing = EvalError::MissingField { field: "y".to_string(), record: r() };
+        assert_eq!(missing, eval(&env, &select(r(), "y")).unwrap_err());
+        assert_eq!(missing, eval_strict(&env, &update(r(), vec![("y", i(2))])).unwrap_err());
+        assert_eq!("record {x = 1} has no field `y`", missing.to_string());
+        assert_eq!(
+            EvalError::InvalidArgument(select(i(1), "x")),
+            eval_strict(&env, &select(i(1), "x")).unwrap_err()
+        );
+        // The record is not known yet, so the selection is left as it is
+        assert_eq!(select(var("r"), "x"), eval(&env, &select(var("r"), "x")).unwrap());
+    }
+
+    #[test]
+    fn test_eval_strings() {
+        let env = empty_env();
+        let greeting = concat(string("héllo"), concat(string(", "), string("world")));
+        assert_eq!(string("héllo, world"), eval_strict(&env, &greeting).unwrap());
+        let length = app(var("length"), string("héllo"));
+        assert_eq!(i(5), eval_strict(&env, &length).unwrap());
+        let substring = app(app(app(var("substring"), string("héllo")), i(1)), i(3));
+        assert_eq!(string("éll"), eval_strict(&env, &substring).unwrap());
+        let shown = tuple(vec![
+            app(var("show"), i(-42)),
+            app(var("show"), b(true)),
+            app(var("show"), string("a\"b")),
+            app(var("show"), character('c')),
+        ]);
+        let expected = tuple(vec![string("-42"), string("true"), string("\"a\\\"b\""), string("'c'")]);
+        assert_eq!(expected, eval_strict(&env, &shown).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &lt(string("abc"), string("abd"))).unwrap());
+        assert_eq!(b(true), eval_strict(&env, &gt(character('b'), character('a'))).unwrap());
+        assert_eq!(b(false), eval_strict(&env, &eq(string("a"), string("b"))).unwrap());
+        assert_eq!(list(vec![i(1), i(2)]), eval_strict(&env, &concat(list(vec![i(1)]), list(vec![i(2)]))).unwrap());
+        let matched = case(string("b"), vec![arm(Pattern::Str("a".to_string()), None, i(1)), arm(Pattern::Wildcard, None, i(2))]);
+        assert_eq!(i(2), eval_strict(&env, &matched).unwrap());
+    }
+
+    #[test]
+    fn test_eval_string_errors() {
+        let env = empty_env();
+        let substring = |start, len| app(app(app(var("substring"), string("abc")), i(start)), i(len));
+        let err = eval(&env, &substring(2, 2)).unwrap_err();
+        assert_eq!(EvalError::IndexOutOfRange(Term::Builtin(Builtin::Substring, vec![string("abc"), i(2), i(2)])), err);
+        assert_eq!("index out of range in (substring \"abc\" 2 2)", err.to_string());
+        assert!(matches!(eval(&env, &substring(-1, 1)), Err(EvalError::IndexOutOfRange(_))));
+        assert_eq!(string(""), eval(&env, &substring(3, 0)).unwrap());
+        assert!(matches!(
+            eval_strict(&env, &concat(string("a"), i(1))),
+            Err(EvalError::TypeMismatch { op: PrimOp::Concat, .. })
+        ));
+    }
+
+    #[test]
+    fn test_eval_let() {
+        let env = empty_env();
+        // let x = 2; y = x + 1 in x * y
+        let term = let_in(vec![("x", i(2)), ("y", add(var("x"), i(1)))], mul(var("x"), var("y")));
+        assert_eq!(i(6), eval_strict(&env, &term).unwrap());
+        // A later binding shadows an earlier one from there on
+        let term = let_in(vec![("x", i(1)), ("y", var("x")), ("x", b(true))], tuple(vec![var("x"), var("y")]));
+        assert_eq!(tuple(vec![b(true), i(1)]), eval_strict(&env, &term).unwrap());
+        let (_, trace) = eval_traced(&env, &let_in(vec![("x", add(i(1), i(1)))], var("x")), &EvalOptions::default());
+        let expected = vec![
+            Reduction { redex: add(i(1), i(1)), result: i(2) },
+            Reduction { redex: let_in(vec![("x", i(2))], var("x")), result: i(2) },
+        ];
+        assert_eq!(expected, trace);
+    }
+
+    #[test]
+    fn test_eval_letrec() {
+        let env = empty_env();
+        let even = abs("n", ifte(eq(var("n"), i(0)), b(true), app(var("odd"), sub(var("n"), i(1)))));
+        let odd = abs("n", ifte(eq(var("n"), i(0)), b(false), app(var("even"), sub(var("n"), i(1)))));
+        let body = tuple(vec![app(var("even"), i(10)), app(var("odd"), i(10))]);
+        let term = letrec(vec![("even", even), ("odd", odd)], body);
+        assert_eq!(tuple(vec![b(true), b(false)]), eval_strict(&env, &term).unwrap());
+        // The recursive name is still bound in the function that is returned
+        let fac = abs("n", ifte(eq(var("n"), i(0)), i(1), mul(var("n"), app(var("fac"), sub(var("n"), i(1))))));
+        let group = letrec(vec![("fac", fac.clone())], var("fac"));
+        assert_eq!(substitute(&fac, "fac", &group), eval_strict(&env, &group).unwrap());
+        assert_eq!(i(120), eval_strict(&env, &app(group, i(5))).unwrap());
+    }
+
+    #[test]
+    fn test_substitute_in_let() {
+        // let x = y; y = x in x + y: the first y is free, the rest is bound
+        let term = let_in(vec![("x", var("y")), ("y", var("x"))], add(var("x"), var("y")));
+        assert_eq!(HashSet::from(["y".to_string()]), free_variables(&term));
+        let expected = let_in(vec![("x", i(1)), ("y", var("x"))], add(var("x"), var("y")));
+        assert_eq!(expected, substitute(&term, "y", &i(1)));
+        // Substituting x for y must rename the bound x
+        let expected = let_in(vec![("x_1", var("x")), ("y", var("x_1"))], add(var("x_1"), var("y")));
+        assert_eq!(expected, substitute(&term, "y", &var("x")));
+
+        // letrec f = λn. f y in f: f is bound everywhere in the group
+        let term = letrec(vec![("f", abs("n", app(var("f"), var("y"))))], var("f"));
+        assert_eq!(HashSet::from(["y".to_string()]), free_variables(&term));
+        assert_eq!(term, substitute(&term, "f", &i(1)));
+        let expected = letrec(vec![("f_1", abs("n", app(var("f_1"), var("f"))))], var("f_1"));
+        assert_eq!(expected, substitute(&term, "y", &var("f")));
+    }
+
+    #[test]
+    fn test_eval_globals_once() {
+        let mut env = empty_env();
+        env.insert("x".to_string(), add(i(2), i(3)));
+        env.insert("y".to_string(), mul(var("x"), var("x")));
+        assert_eq!(i(5), eval_strict(&env, &var("x")).unwrap());
+        // x is evaluated once, when y first needs it
+        let options = EvalOptions { strict: true, ..EvalOptions::default() };
+        let (result, trace) = eval_traced(&env, &add(var("y"), var("x")), &options);
+        assert_eq!(i(30), result.unwrap());
+        let expected = vec![
+            Reduction { redex: add(i(2), i(3)), result: i(5) },
+            Reduction { redex: var("x"), result: i(5) },
+            Reduction { redex: var("x"), result: i(5) },
+            Reduction { redex: mul(i(5), i(5)), result: i(25) },
+            Reduction { redex: var("y"), result: i(25) },
+            Reduction { redex: var("x"), result: i(5) },
+            Reduction { redex: add(i(25), i(5)), result: i(30) },
+        ];
+        assert_eq!(expected, trace);
+    }
+
+    #[test]
+    fn test_eval_cyclic_globals() {
+        let mut env = empty_env();
+        env.insert("x".to_string(), add(var("y"), i(1)));
+        env.insert("y".to_string(), var("x"));
+        env.insert("f".to_string(), abs("n", app(var("f"), var("n"))));
+        let err = eval_strict(&env, &var("y")).unwrap_err();
+        assert_eq!(EvalError::CyclicBinding("y".to_string()), err);
+        assert_eq!("the value of `y` depends on itself", err.to_string());
+        assert!(matches!(eval(&env, &var("x")), Err(EvalError::CyclicBinding(_))));
+        // A function may refer to itself, also when its body is evaluated
+        assert_eq!(abs("n", app(var("f"), var("n"))), eval(&env, &var("f")).unwrap());
+    }
+
+    #[test]
+    fn test_eval_global_scoping() {
+        let mut env = empty_env();
+        env.insert("x".to_string(), i(5));
+        env.insert("f".to_string(), abs("y", var("x")));
+        // The x in f is the global one, not the parameter it is called with
+        let term = app(abs("x", app(var("f"), var("x"))), i(1));
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+        let term = app(abs("g", abs("x", app(var("g"), var("x")))), var("f"));
+        assert_eq!(abs("x_1", i(5)), eval(&env, &term).unwrap());
+        // A parameter hides the global and the built-in of the same name
+        let term = abs("x", abs("map", add(var("x"), var("map"))));
+        assert_eq!(abs("x_1", abs("map_1", add(var("x_1"), var("map_1")))), eval(&env, &term).unwrap());
+        // Nor is it a pattern variable of the same name that is bound next to f
+        let g_and_x = || Pattern::Tuple(vec![Pattern::Var("g".to_string()), Pattern::Var("x".to_string())]);
+        let term = app(abs_pattern(g_and_x(), app(var("g"), i(0))), tuple(vec![var("f"), i(1)]));
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+        let term = case(tuple(vec![var("f"), i(1)]), vec![arm(g_and_x(), None, app(var("g"), i(0)))]);
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+        let pattern = Pattern::Con("P".to_string(), vec![Pattern::Var("g".to_string()), Pattern::Var("x".to_string())]);
+        let term = case(con("P", 2, vec![var("f"), i(1)]), vec![arm(pattern, None, app(var("g"), i(0)))]);
+        assert_eq!(i(5), eval_strict(&env, &term).unwrap());
+    }
+
+    fn with_strategy(strategy: EvalStrategy) -> EvalOptions {
+        EvalOptions { strict: true, strategy, ..EvalOptions::default() }
+    }
+
+    #[test]
+    fn test_eval_lazy_strategies() {
+        let mut env = empty_env();
+        env.insert("spin".to_string(), abs("n", app(var("spin"), add(var("n"), i(1)))));
+        env.insert("bad".to_string(), div(i(1), i(0)));
+        for strategy in [EvalStrategy::CallByName, EvalStrategy::CallByNeed] {
+            let options = with_strategy(strategy);
+            // Unused arguments are never evaluated
+            let term = app(abs("x", i(1)), app(var("spin"), i(0)));
+            assert_eq!(i(1), eval_with(&env, &term, &options).unwrap());
+            let term = let_in(vec![("x", app(var("spin"), i(0))), ("y", var("bad"))], i(2));
+            assert_eq!(i(2), eval_with(&env, &term, &options).unwrap());
+            let term = app(abs("x", app(abs("y", var("x")), var("bad"))), add(i(1), i(1)));
+            let (result, trace) = eval_traced(&env, &term, &options);
+            assert_eq!(i(2), result.unwrap());
+            let expected = vec![
+                Reduction { redex: term.clone(), result: app(abs("y", add(i(1), i(1))), var("bad")) },
+                Reduction { redex: app(abs("y", add(i(1), i(1))), var("bad")), result: add(i(1), i(1)) },
+                Reduction { redex: add(i(1), i(1)), result: i(2) },
+            ];
+            assert_eq!(expected, trace, "{strategy:?}");
+            // Primitive operations need their operands
+            let term = app(abs("x", add(var("x"), i(1))), var("bad"));
+            assert!(matches!(eval_with(&env, &term, &options), Err(EvalError::DivisionByZero(_))));
+        }
+    }
+
+    #[test]
+    fn test_eval_call_by_need_shares_arguments() {
+        let mut env = empty_env();
+        let recurse = |k| app(var("fib"), sub(var("n"), i(k)));
+        env.insert("fib".to_string(), abs("n", ifte(lt(var("n"), i(2)), var("n"), add(recurse(1), recurse(2)))));
+        let term = app(abs("x", add(var("x"), var("x"))), app(var("fib"), i(10)));
+        let calls = |strategy| {
+            let (result, trace) = eval_traced(&env, &term, &with_strategy(strategy));
+            assert_eq!(i(110), result.unwrap());
+            trace.iter().filter(|r| r.redex == var("fib")).count()
+        };
+        let once = calls(EvalStrategy::CallByValue);
+        assert_eq!(once, calls(EvalStrategy::CallByNeed));
+        assert_eq!(2 * once, calls(EvalStrategy::CallByName));
+    }
+
+    #[test]
+    fn test_eval_strategies_agree() {
+        let mut env = empty_env();
+        let fac = abs("n", ifte(eq(var("n"), i(0)), i(1), mul(var("n"), app(var("fac"), sub(var("n"), i(1))))));
+        env.insert("fac".to_string(), fac);
+        env.insert("twice".to_string(), abs("f", abs("x", app(var("f"), app(var("f"), var("x"))))));
+        let pair = Pattern::Tuple(vec![Pattern::Var("a".to_string()), Pattern::Var("b".to_string())]);
+        let arms = vec![arm(Pattern::Int(0.into()), None, b(true)), arm(Pattern::Wildcard, None, b(false))];
+        let zero = abs("x", case(var("x"), arms));
+        let terms = [
+            app(var("fac"), i(10)),
+            app(app(var("twice"), abs("x", mul(var("x"), var("x")))), add(i(1), i(2))),
+            let_in(vec![("x", app(var("fac"), i(3))), ("y", add(var("x"), var("x")))], list(vec![var("x"), var("y")])),
+            app(app(var("map"), app(var("twice"), abs("x", add(var("x"), i(1))))), list(vec![i(1), i(2)])),
+            app(abs_pattern(pair, sub(var("a"), var("b"))), tuple(vec![i(5), i(3)])),
+            app(zero, sub(i(2), i(2))),
+        ];
+        for term in terms {
+            let expected = eval_strict(&env, &term).unwrap();
+            for strategy in [EvalStrategy::CallByName, EvalStrategy::CallByNeed] {
+                assert_eq!(expected, eval_with(&env, &term, &with_strategy(strategy)).unwrap(), "{term} {strategy:?}");
+            }
+        }
+    }
+
+    fn with_limits(max_steps: Option<usize>, max_depth: Option<usize>, max_term_size: Option<usize>) -> EvalOptions {
+        let limits = EvalLimits { max_steps, max_depth, max_term_size };
+        EvalOptions { strict: true, limits, ..EvalOptions::default() }
+    }
+
+    #[test]
+    fn test_eval_limits() {
+        let mut env = empty_env();
+        let omega = abs("x", app(var("x"), var("x")));
+        env.insert("omega".to_string(), app(omega.clone(), omega));
+        env.insert("down".to_string(), abs("n", add(var("n"), app(var("down"), sub(var("n"), i(1))))));
+        env.insert("grow".to_string(), abs("x", app(var("grow"), list(vec![var("x"), var("x"), var("x")]))));
+
+        let err = eval_with(&env, &var("omega"), &with_limits(Some(10), Some(40), None)).unwrap_err();
+        assert_eq!(EvalError::LimitExceeded { limit: Limit::Steps(10), steps: 11 }, err);
+        assert_eq!("evaluation stopped after 11 reductions: more than 10 reductions", err.to_string());
+
+        let err = eval_with(&env, &app(var("down"), i(10)), &with_limits(None, Some(30), None)).unwrap_err();
+        assert!(matches!(err, EvalError::LimitExceeded { limit: Limit::Depth(30), steps } if steps > 0), "{err}");
+
+        let (result, trace) = eval_traced(&env, &app(var("grow"), i(1)), &with_limits(None, Some(40), Some(100)));
+        let err = result.unwrap_err();
+        assert_eq!(EvalError::LimitExceeded { limit: Limit::TermSize(100), steps: trace.len() }, err);
+        assert!(trace.last().unwrap().result.size() > 100);
+
+        // Terminating programs within the limits are not affected
+        let term = app(abs("x", mul(var("x"), var("x"))), i(7));
+        assert_eq!(i(49), eval_with(&env, &term, &with_limits(Some(2), Some(5), Some(5))).unwrap());
+    }
+
+    #[test]
+    fn test_eval_default_limits() {
+        let mut env = empty_env();
+        let omega = abs("x", app(var("x"), var("x")));
+        env.insert("down".to_string(), abs("n", add(var("n"), app(var("down"), sub(var("n"), i(1))))));
+        let recurse = add(var("n"), app(var("sum"), sub(var("n"), i(1))));
+        env.insert("sum".to_string(), abs("n", ifte(eq(var("n"), i(0)), i(0), recurse)));
+
+        // The test thread has a small stack, which is not what bounds the depth
+        let depth = Limit::Depth(DEFAULT_MAX_DEPTH);
+        let err = eval(&env, &app(omega.clone(), omega)).unwrap_err();
+        assert!(matches!(err, EvalError::LimitExceeded { ref limit, .. } if *limit == depth), "{err}");
+        let err = eval(&env, &app(var("down"), i(10))).unwrap_err();
+        assert!(matches!(err, EvalError::LimitExceeded { ref limit, .. } if *limit == depth), "{err}");
+
+        // Recursion deeper than the stack of the test thread allows
+        assert_eq!(i(50_005_000), eval(&env, &app(var("sum"), i(10_000))).unwrap());
+    }
+
+    #[test]
+    fn test_on_eval_stack_nests() {
+        // Evaluation inside `on_eval_stack` stays on its thread instead of starting another one
+        let (outer, inner) = on_eval_stack(|| {
+            let outer = std::thread::current().id();
+            let (result, inner) = on_eval_stack(|| (eval(&empty_env(), &add(i(1), i(2))), std::thread::current().id()));
+            assert_eq!(Ok(i(3)), result);
+            (outer, inner)
+        });
+        assert_eq!(outer, inner);
+        assert_ne!(std::thread::current().id(), outer);
+    }
+
     #[test]
     fn test_eval_complex_application() {
         let term = app(abs("x", app(abs("y", var("y")), var("x"))), var("z"));
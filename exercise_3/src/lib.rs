@@ -1,4 +1,13 @@
+pub mod bignum;
 pub mod term;
 pub mod pretty;
 pub mod eval;
+pub mod step;
 pub mod parser;
+pub mod value;
+pub mod cek;
+pub mod vm;
+pub mod deps;
+pub mod types;
+pub mod check;
+pub mod repl;
@@ -1,42 +1,204 @@
 use fun::term::*;
+use fun::cek::eval_cek;
+use fun::check::{check, Diagnostic};
 use fun::eval::*;
 use fun::parser::*;
+use fun::types::{infer_program, TypeError};
+use fun::value::readback;
+use fun::vm::{compile, CompileError};
+use std::fmt;
+use std::fs;
+use std::process::ExitCode;
 
-/// Driver code to run the lambda calculus evaluator.
-fn main() -> Result<(), String> {
-    // Task: Add the numbers 2 and 3
-
-    // Before extending the interpreter:
-    // Encoding of the value two: (λf.λx.f (f x))
-    let two = abs("f", abs("x", app(var("f"), app(var("f"), var("x")))));
-    
-    // Encoding of the value three: (λf.λx.f (f (f x)))
-    let three = abs("f", abs("x", app(var("f"), app(var("f"), app(var("f"), var("x"))))));
- 
-    // Addition for church encoded numbers: (λm.λn.λf.λx.m f (n f x))
-    let church_add = abs("m", abs("n", abs("f", abs("x", app(app(var("m"), var("f")), app(app(var("n"), var("f")), var("x")))))));
- 
-    // Add two and three
-    let sum = app(app(church_add, two), three);
- 
-    let result = eval(&empty_env(), &sum)?;
-    // Prints the encoding of 5: λf. λx. f (f (f (f (f x))))
-    println!("The result of adding 2 and 3 is: {}\n", result);
-
-
-    // After extending the interpreter:
-    let sum = add(i(2), i(3));
-    println!("Direct representation of 2 + 3: {:?}", sum);
-    let result = eval(&empty_env(), &sum)?;
-    println!("The result of adding 2 and 3 is: {}\n", result);  // Should print 5
- 
-
-    // After adding a parser:
-    let (_, expr) = parse_expression("(2 + 3)").expect("parse error");
-    println!("Parsed expression: {:?}", expr);
-    let result = eval(&empty_env(), &expr)?;
-    println!("The result of adding 2 and 3 is: {}", result);  // Should print 5
-    Ok(())
+const USAGE: &str = "\
+Usage:
+  fun run [--cek|--vm] <file>     evaluate `main` of a program
+  fun check <file>                parse, check and type check a program without running it
+  fun eval [--cek|--vm] '<expr>'  evaluate a single expression
+  fun repl                        start an interactive session
+
+Programs are checked for unbound and duplicate names before they run.
+With --cek, evaluation runs on the CEK machine, whose recursion is only
+limited by memory, instead of stopping at a depth of 50000. With --vm,
+programs that the bytecode covers are compiled and run on the stack VM.
+Exit codes: 1 usage or I/O error, 2 parse error, 3 missing `main`,
+4 evaluation error, 5 type error, 6 unbound or duplicate names.";
+
+/// Why a command failed. Each kind maps to its own exit code.
+#[derive(Debug)]
+enum Failure {
+    Usage,
+    Io(String),
+    Parse(ParseError),
+    MissingMain(ParseError),
+    Eval(EvalError),
+    Type(TypeError),
+    Check(Vec<Diagnostic>),
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Failure::Usage | Failure::Io(_) => 1,
+            Failure::Parse(_) => 2,
+            Failure::MissingMain(_) => 3,
+            Failure::Eval(_) => 4,
+            Failure::Type(_) => 5,
+            Failure::Check(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage => write!(f, "{USAGE}"),
+            Failure::Io(e) => write!(f, "{e}"),
+            Failure::Parse(e) | Failure::MissingMain(e) => write!(f, "{e}"),
+            Failure::Eval(e) => write!(f, "evaluation error: {e}"),
+            Failure::Type(e) => write!(f, "{e}"),
+            Failure::Check(diagnostics) => write!(f, "{}", lines(diagnostics)),
+        }
+    }
+}
+
+impl From<ParseError> for Failure {
+    fn from(e: ParseError) -> Self {
+        match e.kind {
+            ParseErrorKind::MissingMain => Failure::MissingMain(e),
+            _ => Failure::Parse(e),
+        }
+    }
+}
+
+impl From<EvalError> for Failure {
+    fn from(e: EvalError) -> Self {
+        Failure::Eval(e)
+    }
+}
+
+impl From<TypeError> for Failure {
+    fn from(e: TypeError) -> Self {
+        Failure::Type(e)
+    }
+}
+
+fn lines(diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+// Parses and checks a program. Fails on errors and returns the warnings otherwise.
+fn load_program(file: &str) -> Result<(Program, Vec<Diagnostic>), Failure> {
+    let source = fs::read_to_string(file).map_err(|e| Failure::Io(format!("cannot read {file}: {e}")))?;
+    let prog = parse_main_program(&source)?;
+    let diagnostics = check(&prog);
+    if diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(Failure::Check(diagnostics));
+    }
+    Ok((prog, diagnostics))
+}
+
+// Evaluation stops with an error beyond a depth of `DEFAULT_MAX_DEPTH`
+fn eval_options() -> EvalOptions {
+    EvalOptions { strict: true, ..EvalOptions::default() }
+}
+
+// How `run` and `eval` evaluate, chosen with a flag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Evaluator {
+    Substitution,
+    Cek,
+    // The bytecode VM, falling back to the substitution evaluator
+    Vm,
+}
+
+impl Evaluator {
+    fn from_flag(flag: &str) -> Result<Evaluator, Failure> {
+        match flag {
+            "--cek" => Ok(Evaluator::Cek),
+            "--vm" => Ok(Evaluator::Vm),
+            _ => Err(Failure::Usage),
+        }
+    }
+}
+
+fn evaluate(prog: &Program, evaluator: Evaluator) -> Result<Term, Failure> {
+    Ok(match evaluator {
+        Evaluator::Substitution => eval_with(&prog.env, &prog.main, &eval_options())?,
+        Evaluator::Cek => readback(&eval_cek(&prog.env, &prog.main)?),
+        Evaluator::Vm => match compile(prog) {
+            Ok(module) => module.run()?,
+            // Programs with terms that the bytecode lacks are evaluated instead, and said so
+            Err(e @ CompileError::Unsupported(_)) => {
+                eprintln!("warning: {e}, evaluating the program without the VM");
+                eval_with(&prog.env, &prog.main, &eval_options())?
+            }
+            Err(CompileError::UnboundVariable(x)) => return Err(EvalError::UnboundVariable(x).into()),
+        },
+    })
+}
+
+fn run_program(file: &str, evaluator: Evaluator) -> Result<String, Failure> {
+    let (prog, warnings) = load_program(file)?;
+    if !warnings.is_empty() {
+        eprintln!("{}", lines(&warnings));
+    }
+    Ok(evaluate(&prog, evaluator)?.to_string())
+}
+
+fn eval_expression(expr: &str, evaluator: Evaluator) -> Result<String, Failure> {
+    let main = parse_expression(expr)?;
+    let prog = Program { env: empty_env(), main, data: Vec::new(), names: Vec::new() };
+    Ok(evaluate(&prog, evaluator)?.to_string())
+}
+
+// Runs a command and returns what it prints on success.
+fn run_command(args: &[String]) -> Result<String, Failure> {
+    match args {
+        [command, file] if command == "run" => run_program(file, Evaluator::Substitution),
+        [command, flag, file] if command == "run" => run_program(file, Evaluator::from_flag(flag)?),
+        [command, file] if command == "check" => {
+            let (prog, warnings) = load_program(file)?;
+            let types = infer_program(&prog)?;
+            let main = format!("main : {}", types["main"]);
+            Ok(if warnings.is_empty() { main } else { format!("{}\n{main}", lines(&warnings)) })
+        }
+        [command, expr] if command == "eval" => eval_expression(expr, Evaluator::Substitution),
+        [command, flag, expr] if command == "eval" => eval_expression(expr, Evaluator::from_flag(flag)?),
+        _ => Err(Failure::Usage),
+    }
+}
+
+/// Command-line driver for fun programs.
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // Parsing, checking and the evaluators recurse too, so all of it runs on the large stack
+    on_eval_stack(move || run_main(args))
+}
+
+fn run_main(args: Vec<String>) -> ExitCode {
+    if args.len() == 1 && args[0] == "repl" {
+        let stdin = std::io::stdin();
+        let repl = fun::repl::Repl::new();
+        return match fun::repl::run_with(repl, stdin.lock(), std::io::stdout()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::from(Failure::Io(e.to_string()).exit_code())
+            }
+        };
+    }
+
+    match run_command(&args) {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(failure) => {
+            eprintln!("{failure}");
+            ExitCode::from(failure.exit_code())
+        }
+    }
 }
 
 // Tests
@@ -59,9 +221,8 @@ mod tests {
 
     #[test]
     fn test_fibonacci() {
-        // TODO: implement the fibonacci function https://en.wikipedia.org/wiki/Fibonacci_sequence
         let input = r#"
-            fib = TODO: Implement fibonacci function;
+            fib = λn. if n < 2 then n else (fib (n - 1)) + (fib (n - 2));
             main = (fib 10);
         "#;
 
@@ -69,4 +230,101 @@ mod tests {
         let result = eval(&prog.env, &prog.main).unwrap();
         assert_eq!(i(55), result);
     }
+
+    fn command(args: &[&str]) -> Result<String, Failure> {
+        run_command(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    }
+
+    fn program_file(name: &str, source: &str) -> String {
+        let path = std::env::temp_dir().join(format!("fun-{name}-{}.fun", std::process::id()));
+        fs::write(&path, source).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!("7", command(&["eval", "1 + 2 * 3"]).unwrap());
+
+        let file = program_file("ok", "fac = λn. if n == 0 then 1 else n * fac (n - 1);\nmain = fac 5;\n");
+        assert_eq!("120", command(&["run", &file]).unwrap());
+        assert_eq!("main : Int", command(&["check", &file]).unwrap());
+        assert_eq!("120", command(&["run", "--cek", &file]).unwrap());
+        fs::remove_file(file).unwrap();
+        assert_eq!("7", command(&["eval", "--cek", "1 + 2 * 3"]).unwrap());
+        assert_eq!("(λy. 1)", command(&["eval", "--cek", "(λx. λy. x) 1"]).unwrap());
+        assert_eq!("7", command(&["eval", "--vm", "1 + 2 * 3"]).unwrap());
+        assert_eq!(1, command(&["eval", "--fast", "1"]).unwrap_err().exit_code());
+    }
+
+    #[test]
+    fn test_run_vm() {
+        let file = program_file("vm", "fib = λn. if n < 2 then n else fib (n - 1) + fib (n - 2);\nmain = fib 15;\n");
+        assert_eq!("610", command(&["run", "--vm", &file]).unwrap());
+        fs::remove_file(file).unwrap();
+
+        // Lists are not compiled, so the program is evaluated instead
+        let file = program_file("vm-fallback", "main = head [1, 2] + fst (3, 4);\n");
+        assert_eq!("4", command(&["run", "--vm", &file]).unwrap());
+        assert_eq!(4, command(&["eval", "--vm", "head []"]).unwrap_err().exit_code());
+        let failure = command(&["eval", "--vm", "1 + y"]).unwrap_err();
+        assert_eq!("evaluation error: unbound variable `y`", failure.to_string());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_run_cek_deep_recursion() {
+        // Deeper than `DEFAULT_MAX_DEPTH`, on the small stack of the test thread
+        let file = program_file("deep", "sum = λn. if n == 0 then 0 else n + sum (n - 1);\nmain = sum 100000;\n");
+        assert_eq!("5000050000", command(&["run", "--cek", &file]).unwrap());
+        assert_eq!(4, command(&["run", &file]).unwrap_err().exit_code());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let exit_code = |args: &[&str]| command(args).unwrap_err().exit_code();
+        assert_eq!(1, exit_code(&["frobnicate"]));
+        assert_eq!(1, exit_code(&["run", "/nonexistent/file.fun"]));
+        assert_eq!(2, exit_code(&["eval", "(1 +"]));
+        assert_eq!(4, exit_code(&["eval", "1 / 0"]));
+        let failure = command(&["eval", "(λx. x x) (λx. x x)"]).unwrap_err();
+        assert_eq!(4, failure.exit_code());
+        assert!(failure.to_string().ends_with("reductions: recursion deeper than 50000"), "{failure}");
+
+        let file = program_file("no-main", "x = 1;\n");
+        assert_eq!(3, exit_code(&["run", &file]));
+        assert_eq!(3, exit_code(&["check", &file]));
+        fs::remove_file(file).unwrap();
+
+        let file = program_file("ill-typed", "main = if 3 then 1 else 2;\n");
+        assert_eq!(5, exit_code(&["check", &file]));
+        assert_eq!(4, exit_code(&["run", &file]));
+        fs::remove_file(file).unwrap();
+
+        let file = program_file("unbound", "x = 1;\nx = 2;\nmain = 1 + y;\n");
+        assert_eq!(6, exit_code(&["run", &file]));
+        let failure = command(&["check", &file]).unwrap_err();
+        let expected = "error: `x` is defined more than once\nerror: unbound variable `y` in `main`\n\
+                        warning: `x` is not used by `main`";
+        assert_eq!(expected, failure.to_string());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_check_warnings() {
+        let file = program_file("warnings", "unused = 1;\nmain = λx. λx. x;\n");
+        let expected = "warning: `x` shadows another variable in `main`\nwarning: `unused` is not used by `main`\n\
+                        main : a -> b -> b";
+        assert_eq!(expected, command(&["check", &file]).unwrap());
+        assert_eq!("(λx. (λx. x))", command(&["run", &file]).unwrap());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_check_agrees_with_run() {
+        let file = program_file("promotion", "half = λx. x / 2.0;\nmain = (λx. x + 1.5) 2 + half 0.0;\n");
+        assert_eq!("main : Float", command(&["check", &file]).unwrap());
+        assert_eq!("3.5", command(&["run", &file]).unwrap());
+        fs::remove_file(file).unwrap();
+    }
 }
@@ -1,140 +1,759 @@
+use crate::bignum::BigInt;
 use crate::term::*;
 
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while},
-    character::complete::{char, digit1, multispace0, satisfy},
-    combinator::{map, map_res, recognize},
-    error::{make_error, ErrorKind},
-    multi::many1,
-    sequence::{delimited, pair, separated_pair, terminated},
-    IResult,
+    character::complete::{char, digit1, multispace0, one_of, satisfy},
+    combinator::{cut, map, map_res, not, opt, recognize},
+    error::{ErrorKind, FromExternalError},
+    multi::{fold_many0, many0, many1, separated_list1},
+    sequence::{delimited, pair, preceded, terminated},
 };
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, ops::Range, str::FromStr};
+
+/// A parse failure, located in the source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offsets of the offending input.
+    pub span: Range<usize>,
+    /// 1-based line number of the start of `span`.
+    pub line: usize,
+    /// 1-based column (in characters) of the start of `span`.
+    pub column: usize,
+    source_line: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The input did not match the grammar.
+    Unexpected { expected: Vec<String>, found: String },
+    /// The program does not define `main`.
+    MissingMain,
+}
+
+impl ParseError {
+    fn new(source: &str, span: Range<usize>, kind: ParseErrorKind) -> ParseError {
+        let line_start = source[..span.start].rfind('\n').map_or(0, |n| n + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |n| span.start + n);
+        ParseError {
+            kind,
+            line: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            source_line: source[line_start..line_end].to_string(),
+            span,
+        }
+    }
+
+    // Builds the error for a failure at the position where `rest` starts.
+    fn unexpected(source: &str, rest: &str, expected: Vec<&str>) -> ParseError {
+        let start = source.len() - rest.len();
+        let token = next_token(rest);
+        let found = if token.is_empty() {
+            "end of input".to_string()
+        } else {
+            format!("`{token}`")
+        };
+        let mut expected: Vec<String> = expected.into_iter().map(String::from).collect();
+        expected.dedup();
+        ParseError::new(
+            source,
+            start..start + token.len(),
+            ParseErrorKind::Unexpected { expected, found },
+        )
+    }
+
+    /// Renders the offending source line with a caret under the error location.
+    pub fn snippet(&self) -> String {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let width = self.source_line[self.column_offset()..]
+            .chars()
+            .take(self.span.len().max(1))
+            .count()
+            .max(1);
+        format!(
+            "{gutter} |\n{} | {}\n{gutter} | {}{}",
+            self.line,
+            self.source_line,
+            " ".repeat(self.column - 1),
+            "^".repeat(width)
+        )
+    }
+
+    fn column_offset(&self) -> usize {
+        self.source_line
+            .char_indices()
+            .nth(self.column - 1)
+            .map_or(self.source_line.len(), |(n, _)| n)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "parse error at {}:{}: ", self.line, self.column)?;
+        match &self.kind {
+            ParseErrorKind::Unexpected { expected, found } => {
+                if let Some((last, init)) = expected.split_last() {
+                    write!(f, "expected ")?;
+                    if !init.is_empty() {
+                        write!(f, "{} or ", init.join(", "))?;
+                    }
+                    write!(f, "{last}, ")?;
+                }
+                write!(f, "found {found}")?;
+            }
+            ParseErrorKind::MissingMain => write!(f, "main function not found")?,
+        }
+        write!(f, "\n{}", self.snippet())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// The next token of the input, used to describe what was found at an error.
+fn next_token(input: &str) -> &str {
+    match input.chars().next() {
+        None => "",
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => {
+            let end = input
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(input.len());
+            &input[..end]
+        }
+        Some(c) => &input[..c.len_utf8()],
+    }
+}
+
+// Internal error of the nom parsers: the furthest position reached and what was expected there
+#[derive(Debug)]
+struct Failure<'a> {
+    input: &'a str,
+    expected: Vec<&'static str>,
+}
+
+impl<'a> nom::error::ParseError<&'a str> for Failure<'a> {
+    fn from_error_kind(input: &'a str, _kind: ErrorKind) -> Self {
+        Failure { input, expected: Vec::new() }
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    // Keep the alternative that got furthest; merge expectations at the same position.
+    fn or(mut self, other: Self) -> Self {
+        match self.input.len().cmp(&other.input.len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                self.expected.extend(other.expected);
+                self
+            }
+        }
+    }
+}
+
+impl<'a, E> FromExternalError<&'a str, E> for Failure<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _e: E) -> Self {
+        nom::error::ParseError::from_error_kind(input, kind)
+    }
+}
+
+type IResult<'a, O> = nom::IResult<&'a str, O, Failure<'a>>;
+
+// Labels the failure of `inner` at its starting position with what was expected.
+fn expect<'a, F, O>(what: &'static str, mut inner: F) -> impl FnMut(&'a str) -> IResult<'a, O>
+where
+    F: FnMut(&'a str) -> IResult<'a, O>,
+{
+    move |input: &'a str| {
+        inner(input).map_err(|e| {
+            e.map(|failure| {
+                if failure.input.len() >= input.len() {
+                    Failure { input, expected: vec![what] }
+                } else {
+                    failure
+                }
+            })
+        })
+    }
+}
 
 // Helper function to parse whitespace
-fn ws<'a, F: 'a, O>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O>
+fn ws<'a, F, O>(inner: F) -> impl FnMut(&'a str) -> IResult<'a, O>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O>,
+    F: FnMut(&'a str) -> IResult<'a, O> + 'a,
 {
     delimited(multispace0, inner, multispace0)
 }
 
-fn parse_var(input: &str) -> IResult<&str, Term> {
+// Matches a keyword that is not the prefix of a longer identifier
+fn keyword<'a>(word: &'static str) -> impl FnMut(&'a str) -> IResult<'a, &'a str> {
+    terminated(
+        tag(word),
+        not(satisfy(|c: char| c.is_ascii_alphanumeric() || c == '_')),
+    )
+}
+
+fn parse_var(input: &str) -> IResult<'_, Term> {
     map(parse_identifier, |s: String| Term::Var(s.to_string()))(input)
 }
 
-fn parse_int(input: &str) -> IResult<&str, Term> {
-    map_res(digit1, |s: &str| i64::from_str(s).map(Term::Int))(input)
+// Syntax: 42, 2.75, 1e-3, 2.5E10. A number with a fraction or an exponent is a float.
+fn parse_number(input: &str) -> IResult<'_, Term> {
+    let fraction = pair(char('.'), digit1);
+    // An `e` right after a number starts an exponent, so it must be followed by digits
+    let exponent = pair(one_of("eE"), cut(pair(opt(one_of("+-")), expect("exponent digits", digit1))));
+    let (rest, (digits, float)) = pair(digit1, recognize(pair(opt(fraction), opt(exponent))))(input)?;
+    let literal = &input[..digits.len() + float.len()];
+    // Both always succeed on this syntax; floats too large to represent become infinite
+    let term = match float {
+        "" => Term::Int(BigInt::from_str(literal).expect("digits form an integer")),
+        _ => Term::Float(f64::from_str(literal).expect("the syntax of a float literal")),
+    };
+    Ok((rest, term))
+}
+
+// Syntax: -42, -0.5. Only allowed where an application starts, as `f -1` is `f - 1`.
+fn parse_negative_number(input: &str) -> IResult<'_, Term> {
+    map(preceded(char('-'), parse_number), |t| match t {
+        Term::Int(n) => Term::Int(-&n),
+        Term::Float(x) => Term::Float(-x),
+        _ => unreachable!("numbers are integers or floats"),
+    })(input)
 }
 
-fn parse_bool(input: &str) -> IResult<&str, Term> {
+fn parse_bool(input: &str) -> IResult<'_, Term> {
     alt((
-        map(tag("true"), |_| Term::Bool(true)),
-        map(tag("false"), |_| Term::Bool(false)),
+        map(keyword("true"), |_| Term::Bool(true)),
+        map(keyword("false"), |_| Term::Bool(false)),
     ))(input)
 }
 
+// Syntax: \n, \t, \r, \0, \\, \" or \' after a backslash
+fn parse_escape(input: &str) -> IResult<'_, char> {
+    expect(
+        "an escape sequence",
+        alt((
+            map(char('n'), |_| '\n'),
+            map(char('t'), |_| '\t'),
+            map(char('r'), |_| '\r'),
+            map(char('0'), |_| '\0'),
+            char('\\'),
+            char('"'),
+            char('\''),
+        )),
+    )(input)
+}
+
+// A character of a string or character literal: anything but the quote, a backslash or a
+// line break, or an escape sequence
+fn parse_literal_char<'a>(quote: char) -> impl FnMut(&'a str) -> IResult<'a, char> {
+    alt((
+        preceded(char('\\'), cut(parse_escape)),
+        satisfy(move |c| c != quote && c != '\\' && c != '\n'),
+    ))
+}
+
+// Syntax: "hello\n"
+fn parse_string(input: &str) -> IResult<'_, String> {
+    let (input, _) = char('"')(input)?;
+    let (input, s) = fold_many0(parse_literal_char('"'), String::new, |mut s, c| {
+        s.push(c);
+        s
+    })(input)?;
+    let (input, _) = cut(expect("`\"`", char('"')))(input)?;
+    Ok((input, s))
+}
+
+// Syntax: 'a', '\n'
+fn parse_char(input: &str) -> IResult<'_, char> {
+    let (input, _) = char('\'')(input)?;
+    let (input, c) = cut(expect("a character", parse_literal_char('\'')))(input)?;
+    let (input, _) = cut(expect("`'`", char('\'')))(input)?;
+    Ok((input, c))
+}
+
 // Syntax: if x then y else z
-fn parse_if_then_else(input: &str) -> IResult<&str, Term> {
-    let (input, _) = ws(tag("if"))(input)?;
-    let (input, cond) = ws(parse_expression)(input)?;
-    let (input, _) = ws(tag("then"))(input)?;
-    let (input, t1) = ws(parse_expression)(input)?;
-    let (input, _) = ws(tag("else"))(input)?;
-    let (input, t2) = ws(parse_expression)(input)?;
+fn parse_if_then_else(input: &str) -> IResult<'_, Term> {
+    let (input, _) = ws(keyword("if"))(input)?;
+    let (input, cond) = ws(parse_expr)(input)?;
+    let (input, _) = ws(expect("`then`", keyword("then")))(input)?;
+    let (input, t1) = ws(parse_expr)(input)?;
+    let (input, _) = ws(expect("`else`", keyword("else")))(input)?;
+    let (input, t2) = ws(parse_expr)(input)?;
     Ok((input, ifte(cond, t1, t2)))
 }
 
-// Syntax: x + y
-fn parse_binary_op(input: &str) -> IResult<&str, Term> {
-    let (input, left) = ws(parse_expression)(input)?; // Parse the left operand
-    let (input, op) = ws(parse_prim_op)(input)?; // Parse the operator with whitespace handling
-    let (input, right) = ws(parse_expression)(input)?; // Parse the right operand
-    Ok((input, primop(op, left, right)))
+// Syntax: let x = 1; y = x + 1 in x * y, or letrec for bindings that refer to each other
+fn parse_let(input: &str) -> IResult<'_, Term> {
+    let (input, recursive) = ws(alt((map(keyword("letrec"), |_| true), map(keyword("let"), |_| false))))(input)?;
+    let (mut input, first) = parse_local_binding(input)?;
+    let mut bindings = vec![first];
+    while let Ok((rest, _)) = ws(char::<_, Failure>(';'))(input) {
+        let (after, binding) = parse_local_binding(rest)?;
+        // The names of a letrec group have to be distinct
+        if recursive && bindings.iter().any(|(name, _)| *name == binding.0) {
+            let expected = vec!["a name that is not bound yet"];
+            return Err(nom::Err::Failure(Failure { input: rest.trim_start(), expected }));
+        }
+        bindings.push(binding);
+        input = after;
+    }
+    let (input, _) = ws(expect("`;` or `in`", keyword("in")))(input)?;
+    let (input, body) = ws(parse_expr)(input)?;
+    let body = Box::new(body);
+    Ok((input, if recursive { Term::LetRec(bindings, body) } else { Term::Let(bindings, body) }))
+}
+
+// Syntax: x = 1
+fn parse_local_binding(input: &str) -> IResult<'_, (String, Term)> {
+    let (input, name) = ws(expect("a binding name", parse_identifier))(input)?;
+    let (input, _) = ws(expect("`=`", terminated(tag("="), not(char('=')))))(input)?;
+    let (input, t) = ws(parse_expr)(input)?;
+    Ok((input, (name, t)))
+}
+
+// Syntax: (x) or (x, y, z). Parses one or more comma separated items in parentheses.
+fn parenthesised<'a, O: 'a>(item: fn(&'a str) -> IResult<'a, O>) -> impl FnMut(&'a str) -> IResult<'a, Vec<O>> {
+    move |input: &'a str| {
+        let (input, _) = ws(char('('))(input)?;
+        let (mut input, first) = ws(item)(input)?;
+        let mut items = vec![first];
+        // Unlike `separated_list1`, reports why an item after `,` failed
+        while let Ok((rest, _)) = ws(char::<_, Failure>(','))(input) {
+            let (rest, next) = ws(item)(rest)?;
+            items.push(next);
+            input = rest;
+        }
+        let (input, _) = ws(expect("`)`", char(')')))(input)?;
+        Ok((input, items))
+    }
+}
+
+// Syntax: case x of 0 -> y | n if n > 0 -> z | _ -> w
+// An arm extends as far as possible, so a nested case needs parentheses.
+fn parse_case(input: &str) -> IResult<'_, Term> {
+    let (input, _) = ws(keyword("case"))(input)?;
+    let (input, scrutinee) = ws(parse_expr)(input)?;
+    let (input, _) = ws(expect("`of`", keyword("of")))(input)?;
+    let (input, _) = opt(ws(char('|')))(input)?;
+    let (mut input, first) = parse_arm(input)?;
+    let mut arms = vec![first];
+    // Unlike `separated_list1`, reports why an arm after `|` failed
+    while let Ok((rest, _)) = ws(char::<_, Failure>('|'))(input) {
+        let (rest, arm) = parse_arm(rest)?;
+        arms.push(arm);
+        input = rest;
+    }
+    Ok((input, case(scrutinee, arms)))
 }
 
-fn parse_prim_op(input: &str) -> IResult<&str, PrimOp> {
+// Syntax: p -> x or p if x -> y
+fn parse_arm(input: &str) -> IResult<'_, Arm> {
+    let (input, pattern) = ws(parse_pattern)(input)?;
+    let (input, guard) = match ws(keyword("if"))(input) {
+        Ok((input, _)) => map(ws(parse_expr), Some)(input)?,
+        Err(_) => (input, None),
+    };
+    let (input, _) = ws(expect("`->`", tag("->")))(input)?;
+    let (input, body) = ws(parse_expr)(input)?;
+    Ok((input, arm(pattern, guard, body)))
+}
+
+// Syntax: Cons x (Cons y _), or any pattern atom
+fn parse_pattern(input: &str) -> IResult<'_, Pattern> {
     alt((
+        |input| {
+            let (input, tag) = ws(parse_type_name)(input)?;
+            let (input, args) = many0(ws(parse_pattern_atom))(input)?;
+            Ok((input, Pattern::Con(tag, args)))
+        },
+        parse_pattern_atom,
+    ))(input)
+}
+
+// Syntax: 1, -1, true, "s", 'c', x, _, Nil, (p), (p, q)
+fn parse_pattern_atom(input: &str) -> IResult<'_, Pattern> {
+    expect(
+        "a pattern",
+        alt((
+            map(keyword("_"), |_| Pattern::Wildcard),
+            map(parse_type_name, |tag| Pattern::Con(tag, vec![])),
+            map(parse_type_var, Pattern::Var),
+            map_res(recognize(pair(opt(char('-')), digit1)), |s: &str| BigInt::from_str(s).map(Pattern::Int)),
+            map(parse_bool, |t| Pattern::Bool(t == Term::Bool(true))),
+            map(parse_string, Pattern::Str),
+            map(parse_char, Pattern::Char),
+            map(parenthesised(parse_pattern), |mut items| match items.len() {
+                1 => items.remove(0),
+                _ => Pattern::Tuple(items),
+            }),
+        )),
+    )(input)
+}
+
+// Binding strength of an infix operator; higher binds tighter.
+fn precedence(op: PrimOp) -> u8 {
+    match op {
+        PrimOp::Or => 1,
+        PrimOp::And => 2,
+        PrimOp::Eq | PrimOp::Ne => 3,
+        PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge => 4,
+        PrimOp::Cons | PrimOp::Concat => 5,
+        PrimOp::Add | PrimOp::Sub => 6,
+        PrimOp::Mul | PrimOp::Div | PrimOp::Mod => 7,
+        PrimOp::Pow => 8,
+    }
+}
+
+// Syntax: x + y * z, parsed by precedence climbing. All operators are left associative,
+// except `::`, `++` and `^`, which are right associative: 1 :: 2 :: [] is 1 :: (2 :: []).
+fn parse_binary_op(input: &str, min_prec: u8) -> IResult<'_, Term> {
+    let (mut input, mut left) = ws(parse_app)(input)?;
+    while let Ok((rest, op)) = ws(parse_prim_op)(input) {
+        let prec = precedence(op);
+        if prec < min_prec {
+            break;
+        }
+        let right_assoc = matches!(op, PrimOp::Cons | PrimOp::Concat | PrimOp::Pow);
+        let next_prec = if right_assoc { prec } else { prec + 1 };
+        let (rest, right) = parse_binary_op(rest, next_prec)?;
+        left = primop(op, left, right);
+        input = rest;
+    }
+    Ok((input, left))
+}
+
+fn parse_prim_op(input: &str) -> IResult<'_, PrimOp> {
+    alt((
+        map(tag("++"), |_| PrimOp::Concat),
         map(tag("+"), |_| PrimOp::Add),
-        map(tag("-"), |_| PrimOp::Sub),
+        // not the arrow of a case arm
+        map(terminated(tag("-"), not(char('>'))), |_| PrimOp::Sub),
         map(tag("*"), |_| PrimOp::Mul),
         map(tag("/"), |_| PrimOp::Div),
+        map(tag("%"), |_| PrimOp::Mod),
+        map(tag("^"), |_| PrimOp::Pow),
         map(tag("=="), |_| PrimOp::Eq),
+        map(tag("!="), |_| PrimOp::Ne),
+        map(tag("<="), |_| PrimOp::Le),
         map(tag("<"), |_| PrimOp::Lt),
+        map(tag(">="), |_| PrimOp::Ge),
         map(tag(">"), |_| PrimOp::Gt),
+        map(tag("&&"), |_| PrimOp::And),
+        map(tag("||"), |_| PrimOp::Or),
+        map(tag("::"), |_| PrimOp::Cons),
     ))(input)
 }
 
-fn parse_identifier(input: &str) -> IResult<&str, String> {
-    let keywords = ["if", "then", "else", "true", "false"];
-    let (input, name) = recognize(pair(
+fn parse_identifier(input: &str) -> IResult<'_, String> {
+    let keywords = ["if", "then", "else", "true", "false", "data", "case", "of", "let", "letrec", "in"];
+    let (rest, name) = recognize(pair(
         satisfy(|c: char| c.is_ascii_alphabetic()),
         take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
     ))(input)?;
 
     if keywords.contains(&name) {
-        Err(nom::Err::Error(make_error(input, ErrorKind::Satisfy)))
+        Err(nom::Err::Error(nom::error::make_error(input, ErrorKind::Satisfy)))
     } else {
-        Ok((input, name.to_string()))
+        Ok((rest, name.to_string()))
     }
 }
 
-// Syntax: λx. x
-fn parse_abs(input: &str) -> IResult<&str, Term> {
+// Syntax: λx. x or λx y z. x, which is short for λx. λy. λz. x
+// A parameter can also be a tuple: λ(x, y). x + y
+fn parse_abs(input: &str) -> IResult<'_, Term> {
     // TODO: Also accept Haskell style notation: \x -> x
     let (input, _) = ws(char('λ'))(input)?; 
-    let (input, var) = ws(parse_identifier)(input)?;
-    let (input, _) = ws(char('.'))(input)?; 
-    let (input, body) = ws(parse_expression)(input)?;
-    Ok((input, abs(&var, body)))
+    let (input, params) = many1(ws(expect("a parameter name", parse_param)))(input)?;
+    let (input, _) = ws(expect("`.`", char('.')))(input)?; 
+    let (input, body) = ws(parse_expr)(input)?;
+    Ok((
+        input,
+        params.into_iter().rev().fold(body, |body, param| match param {
+            Pattern::Var(x) => abs(&x, body),
+            pattern => abs_pattern(pattern, body),
+        }),
+    ))
+}
+
+// Syntax: x, or a tuple of parameters (x, (y, _))
+fn parse_param(input: &str) -> IResult<'_, Pattern> {
+    alt((
+        map(parse_identifier, Pattern::Var),
+        map(keyword("_"), |_| Pattern::Wildcard),
+        map(parenthesised(parse_param), |mut items| match items.len() {
+            1 => items.remove(0),
+            _ => Pattern::Tuple(items),
+        }),
+    ))(input)
 }
 
-// Syntax: x y
-fn parse_app(input: &str) -> IResult<&str, Term> {
-    let (input, l) = ws(parse_expression)(input)?;
-    let (input, r) = ws(parse_expression)(input)?;
-    Ok((input, app(l, r)))
+// Syntax: f x y, which is short for ((f x) y)
+fn parse_app(input: &str) -> IResult<'_, Term> {
+    let (input, head) = ws(alt((parse_negative_number, parse_atom)))(input)?;
+    fold_many0(ws(parse_atom), move || head.clone(), app)(input)
 }
 
-fn parse_complex_expression(input: &str) -> IResult<&str, Term> {
-    alt((parse_abs, parse_app, parse_if_then_else, parse_binary_op))(input)
+// Syntax: [], [x, y, z]
+fn parse_list(input: &str) -> IResult<'_, Term> {
+    let (input, _) = ws(char('['))(input)?;
+    if let Ok((input, _)) = ws(char::<_, Failure>(']'))(input) {
+        return Ok((input, list(vec![])));
+    }
+    let (mut input, first) = ws(parse_expr)(input)?;
+    let mut items = vec![first];
+    while let Ok((rest, _)) = ws(char::<_, Failure>(','))(input) {
+        let (rest, item) = ws(parse_expr)(rest)?;
+        items.push(item);
+        input = rest;
+    }
+    let (input, _) = ws(expect("`,` or `]`", char(']')))(input)?;
+    Ok((input, list(items)))
 }
 
-// Syntax: Parenteses are used around complex expressions
-pub fn parse_expression(input: &str) -> IResult<&str, Term> {
+// Syntax: { x = 1, y = 2 } or { r | x = 1 }, a copy of the record r with x replaced
+fn parse_record(input: &str) -> IResult<'_, Term> {
+    let (input, _) = ws(char('{'))(input)?;
+    if let Ok((input, _)) = ws(char::<_, Failure>('}'))(input) {
+        return Ok((input, Term::Record(vec![])));
+    }
+    // A literal starts with a field name and `=`; `{ x == y | ...}` updates the record x == y
+    let mut field_start = pair(ws(parse_identifier), ws(terminated(tag("="), not(char('=')))));
+    let (input, base) = match field_start(input) {
+        Ok(_) => (input, None),
+        Err(_) => {
+            let (input, base) = ws(parse_expr)(input)?;
+            let (input, _) = ws(expect("`|`", char('|')))(input)?;
+            (input, Some(base))
+        }
+    };
+    let (mut input, first) = parse_field(input)?;
+    let mut fields = vec![first];
+    while let Ok((rest, _)) = ws(char::<_, Failure>(','))(input) {
+        let (after, field) = parse_field(rest)?;
+        // A field may only be given once
+        if fields.iter().any(|(name, _)| *name == field.0) {
+            let expected = vec!["a field name that is not used yet"];
+            return Err(nom::Err::Failure(Failure { input: rest.trim_start(), expected }));
+        }
+        fields.push(field);
+        input = after;
+    }
+    let (input, _) = ws(expect("`,` or `}`", char('}')))(input)?;
+    match base {
+        Some(base) => Ok((input, Term::Update(Box::new(base), fields))),
+        None => Ok((input, Term::Record(fields))),
+    }
+}
+
+// Syntax: x = 1
+fn parse_field(input: &str) -> IResult<'_, (String, Term)> {
+    let (input, name) = ws(expect("a field name", parse_identifier))(input)?;
+    let (input, _) = ws(expect("`=`", terminated(tag("="), not(char('=')))))(input)?;
+    let (input, t) = ws(parse_expr)(input)?;
+    Ok((input, (name, t)))
+}
+
+// Syntax: #2 x, the second component of the tuple x
+fn parse_proj(input: &str) -> IResult<'_, Term> {
+    let (input, _) = char('#')(input)?;
+    let (input, index) = expect(
+        "a component number",
+        map_res(digit1, |s: &str| match usize::from_str(s) {
+            Ok(0) => Err("components are numbered from 1".to_string()),
+            n => n.map_err(|e| e.to_string()),
+        }),
+    )(input)?;
+    let (input, t) = ws(parse_atom)(input)?;
+    Ok((input, proj(index, t)))
+}
+
+// Literals, variables, lists, records, projections, tuples and parenthesised expressions,
+// followed by any number of field selections: r.x.y
+fn parse_atom(input: &str) -> IResult<'_, Term> {
+    let (input, t) = expect(
+        "an expression",
+        alt((
+            parse_var,
+            parse_number,
+            parse_bool,
+            map(parse_string, Term::Str),
+            map(parse_char, Term::Char),
+            parse_list,
+            parse_record,
+            parse_proj,
+            map(parenthesised(parse_expr), |mut items| match items.len() {
+                1 => items.remove(0),
+                _ => tuple(items),
+            }),
+        )),
+    )(input)?;
+    fold_many0(
+        preceded(char('.'), expect("a field name", parse_identifier)),
+        move || t.clone(),
+        |t, field| Term::Select(Box::new(t), field),
+    )(input)
+}
+
+// Syntax: Parentheses are only needed to group expressions
+fn parse_expr(input: &str) -> IResult<'_, Term> {
+    alt((parse_abs, parse_if_then_else, parse_case, parse_let, |input| parse_binary_op(input, 0)))(input)
+}
+
+// Syntax: x = y;
+fn parse_binding(input: &str) -> IResult<'_, (String, Term)> {
+    let (input, name) = ws(expect("a binding name", parse_identifier))(input)?;
+    let (input, _) = ws(expect("`=`", tag("=")))(input)?;
+    let (input, term) = ws(parse_expr)(input)?;
+    let (input, _) = ws(expect("`;`", tag(";")))(input)?;
+    Ok((input, (name, term)))
+}
+
+// Syntax: a, List, List a, a -> b
+fn parse_type(input: &str) -> IResult<'_, TypeExpr> {
+    let (input, ty) = alt((
+        |input| {
+            let (input, name) = ws(parse_type_name)(input)?;
+            let (input, args) = many0(ws(parse_type_atom))(input)?;
+            Ok((input, TypeExpr::Con(name, args)))
+        },
+        parse_type_atom,
+    ))(input)?;
+    match ws(tag::<_, _, Failure>("->"))(input) {
+        Ok((input, _)) => {
+            let (input, result) = parse_type(input)?;
+            Ok((input, TypeExpr::Fun(Box::new(ty), Box::new(result))))
+        }
+        Err(_) => Ok((input, ty)),
+    }
+}
+
+// Type variables, type names without parameters and parenthesised types
+fn parse_type_atom(input: &str) -> IResult<'_, TypeExpr> {
+    expect(
+        "a type",
+        alt((
+            map(parse_type_var, TypeExpr::Var),
+            map(parse_type_name, |name| TypeExpr::Con(name, vec![])),
+            delimited(ws(char('(')), parse_type, ws(expect("`)`", char(')')))),
+        )),
+    )(input)
+}
+
+// Type and constructor names start with an upper case letter, type variables with a lower case one
+fn parse_type_name(input: &str) -> IResult<'_, String> {
+    let (rest, name) = parse_identifier(input)?;
+    if name.starts_with(|c: char| c.is_ascii_uppercase()) {
+        Ok((rest, name))
+    } else {
+        Err(nom::Err::Error(nom::error::make_error(input, ErrorKind::Satisfy)))
+    }
+}
+
+fn parse_type_var(input: &str) -> IResult<'_, String> {
+    let (rest, name) = parse_identifier(input)?;
+    if name.starts_with(|c: char| c.is_ascii_lowercase()) {
+        Ok((rest, name))
+    } else {
+        Err(nom::Err::Error(nom::error::make_error(input, ErrorKind::Satisfy)))
+    }
+}
+
+// Syntax: data List a = Nil | Cons a (List a);
+fn parse_data(input: &str) -> IResult<'_, DataDecl> {
+    let (input, _) = ws(keyword("data"))(input)?;
+    let (input, name) = ws(expect("a type name", parse_type_name))(input)?;
+    let (input, params) = many0(ws(parse_type_var))(input)?;
+    let (input, _) = ws(expect("`=`", tag("=")))(input)?;
+    let constructor = pair(
+        ws(expect("a constructor name", parse_type_name)),
+        many0(ws(parse_type_atom)),
+    );
+    let (input, constructors) = separated_list1(ws(char('|')), constructor)(input)?;
+    let (input, _) = ws(expect("`;`", tag(";")))(input)?;
+    Ok((input, DataDecl { name, params, constructors }))
+}
+
+fn parse_decl(input: &str) -> IResult<'_, Decl> {
     alt((
-        parse_var,
-        parse_int,
-        parse_bool,
-        delimited(ws(char('(')), parse_complex_expression, ws(char(')'))),
+        map(parse_data, Decl::Data),
+        map(parse_binding, |(name, term)| Decl::Binding(name, term)),
     ))(input)
 }
 
-// Syntax: x = y; z = w;
-pub fn parse_program(input: &str) -> IResult<&str, Vec<(String, Term)>> {
-    many1(terminated(
-        separated_pair(ws(parse_identifier), ws(tag("=")), ws(parse_expression)),
-        ws(tag(";")),
-    ))(input)
+// Unlike `many0`, reports why a declaration failed instead of stopping in front of it.
+fn parse_decls(input: &str) -> IResult<'_, Vec<Decl>> {
+    let (mut input, _) = multispace0(input)?;
+    let mut decls = Vec::new();
+    while !input.is_empty() {
+        let (rest, decl) = parse_decl(input)?;
+        decls.push(decl);
+        input = rest;
+    }
+    Ok((input, decls))
+}
+
+// Runs `parser` on the whole of `source`, converting failures into a located `ParseError`.
+fn parse_all<'a, O>(
+    source: &'a str,
+    mut parser: impl FnMut(&'a str) -> IResult<'a, O>,
+    expected_after: Vec<&str>,
+) -> Result<O, ParseError> {
+    match parser(source) {
+        Ok(("", output)) => Ok(output),
+        Ok((rest, _)) => Err(ParseError::unexpected(source, rest, expected_after)),
+        Err(nom::Err::Error(failure)) | Err(nom::Err::Failure(failure)) => Err(
+            ParseError::unexpected(source, failure.input, failure.expected),
+        ),
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input"),
+    }
+}
+
+/// Parses a single expression, which must make up the whole input.
+pub fn parse_expression(input: &str) -> Result<Term, ParseError> {
+    parse_all(input, ws(parse_expr), vec!["an operator", "end of input"])
+}
+
+// Syntax: x = y; z = w; data T = A | B T;
+pub fn parse_program(input: &str) -> Result<Vec<Decl>, ParseError> {
+    parse_all(input, parse_decls, vec!["a declaration"])
 }
 
 // Parse the main program. Expect the main expression to be defined.
-pub fn parse_main_program(input: &str) -> Result<Program, &str> {
-    let (input, prog) = parse_program(input).expect("parse error");
-    assert!(input.is_empty(), "parse error: trailing input");
+// Data declarations bind their constructor names in the environment.
+pub fn parse_main_program(input: &str) -> Result<Program, ParseError> {
+    let prog = parse_program(input)?;
 
     let mut env = HashMap::new();
-    for (name, term) in prog {
-        env.insert(name, term);
+    let mut data = Vec::new();
+    let mut names = Vec::new();
+    for decl in prog {
+        match decl {
+            Decl::Binding(name, term) => {
+                names.push(name.clone());
+                env.insert(name, term);
+            }
+            Decl::Data(decl) => {
+                for (name, term) in decl.constructor_bindings() {
+                    names.push(name.clone());
+                    env.insert(name, term);
+                }
+                data.push(decl);
+            }
+        }
     }
 
-    let main = env.get("main").expect("main function not found");
-    
-    Ok(Program {
-        env: env.clone(),
-        main: main.clone(),
-    })
+    let main = env.get("main").cloned().ok_or_else(|| {
+        let end = input.trim_end().len();
+        ParseError::new(input, end..end, ParseErrorKind::MissingMain)
+    })?;
+
+    Ok(Program { env, main, data, names })
 }
 
 #[cfg(test)]
@@ -146,7 +765,7 @@ mod tests {
     #[test]
     fn test_abs() -> R {
         let input = "(λx. (x y))";
-        let (_, term) = parse_expression(input)?;
+        let term = parse_expression(input)?;
         assert_eq!(abs("x", app(var("x"), var("y"))), term);
         Ok(())
     }
@@ -154,7 +773,7 @@ mod tests {
     #[test]
     fn test_if() -> R {
         let input = "(λx. (if x then y  else 0))";
-        let (_, term) = parse_expression(input)?;
+        let term = parse_expression(input)?;
         assert_eq!(abs("x", ifte(var("x"), var("y"), i(0))), term,);
         Ok(())
     }
@@ -162,10 +781,390 @@ mod tests {
     #[test]
     fn test_app_app() -> R {
         let input = "((x y) z)";
-        let (_, term) = parse_expression(input)?;
+        let term = parse_expression(input)?;
         assert_eq!(app(app(var("x"), var("y")), var("z")), term,);
         Ok(())
     }
 
+    #[test]
+    fn test_precedence() -> R {
+        let input = "1 + 2 * 3 < x == true";
+        let term = parse_expression(input)?;
+        assert_eq!(
+            eq(lt(add(i(1), mul(i(2), i(3))), var("x")), b(true)),
+            term
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_left_associative() -> R {
+        let term = parse_expression("10 - 4 - 3")?;
+        assert_eq!(sub(sub(i(10), i(4)), i(3)), term);
+        let term = parse_expression("8 / 2 * 3 / 4")?;
+        assert_eq!(div(mul(div(i(8), i(2)), i(3)), i(4)), term);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parenthesised_operators() -> R {
+        let term = parse_expression("((2 + 3) * 4)")?;
+        assert_eq!(mul(add(i(2), i(3)), i(4)), term);
+        let term = parse_expression("(λn. n * (f (n - 1)))")?;
+        assert_eq!(abs("n", mul(var("n"), app(var("f"), sub(var("n"), i(1))))), term);
+        Ok(())
+    }
+
+    #[test]
+    fn test_juxtaposition() -> R {
+        let term = parse_expression("f a b c")?;
+        assert_eq!(app(app(app(var("f"), var("a")), var("b")), var("c")), term);
+        let term = parse_expression("n * fac (n - 1) + 1")?;
+        assert_eq!(
+            add(mul(var("n"), app(var("fac"), sub(var("n"), i(1)))), i(1)),
+            term
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_binder_abs() -> R {
+        let term = parse_expression("λx y z. x z (y z)")?;
+        assert_eq!(
+            abs("x", abs("y", abs("z", app(app(var("x"), var("z")), app(var("y"), var("z")))))),
+            term
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_logical_and_comparison_operators() -> R {
+        let term = parse_expression("a || b && x != 1 + 2 % 3 ^ 2 ^ y || not c")?;
+        let expected = or(
+            or(
+                var("a"),
+                and(var("b"), ne(var("x"), add(i(1), modulo(i(2), pow(i(3), pow(i(2), var("y"))))))),
+            ),
+            app(var("not"), var("c")),
+        );
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        let term = parse_expression("x <= y == y >= x")?;
+        assert_eq!(eq(le(var("x"), var("y")), ge(var("y"), var("x"))), term);
+        // `||` is not mistaken for the separator of case arms
+        let term = parse_expression("case x of true -> a || b | false -> c")?;
+        let expected = case(
+            var("x"),
+            vec![arm(Pattern::Bool(true), None, or(var("a"), var("b"))), arm(Pattern::Bool(false), None, var("c"))],
+        );
+        assert_eq!(expected, term);
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_integer_literals() -> R {
+        let term = parse_expression("case 123456789012345678901234567890 of -99999999999999999999 -> 1 | n -> n")?;
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        assert_eq!("(case 123456789012345678901234567890 of -99999999999999999999 -> 1 | n -> n)", term.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_literals() -> R {
+        assert_eq!(add(f(2.75), f(1e-3)), parse_expression("2.75 + 1e-3")?);
+        assert_eq!(f(2.5e10), parse_expression("2.5E+10")?);
+        // A negative literal starts an application; elsewhere `-` is subtraction
+        assert_eq!(sub(f(-0.5), i(-2)), parse_expression("-0.5 - -2")?);
+        assert_eq!(sub(var("f"), i(1)), parse_expression("f -1")?);
+        assert_eq!(Term::Select(Box::new(var("r")), "x".to_string()), parse_expression("r.x")?);
+        let term = app(app(var("f"), f(-1.5)), mul(f(1e100), i(-3)));
+        assert_eq!("((f (-1.5)) (1e100 * -3))", term.to_string());
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_float_exponent() {
+        for (input, column, found) in [("2.5e", 5, "end of input"), ("1E+x", 4, "`x`"), ("3ex", 3, "`x`")] {
+            let err = parse_expression(input).unwrap_err();
+            assert_eq!((1, column), (err.line, err.column), "{input}");
+            let expected = vec!["exponent digits".to_string()];
+            assert_eq!(ParseErrorKind::Unexpected { expected, found: found.to_string() }, err.kind, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_let() -> R {
+        let term = parse_expression("let x = 1; y = x == 2 in λz. y")?;
+        assert_eq!(let_in(vec![("x", i(1)), ("y", eq(var("x"), i(2)))], abs("z", var("y"))), term);
+        let term = parse_expression("letrec f = λn. g n; g = λn. f n in f 1 + 1")?;
+        let expected = letrec(
+            vec![("f", abs("n", app(var("g"), var("n")))), ("g", abs("n", app(var("f"), var("n"))))],
+            add(app(var("f"), i(1)), i(1)),
+        );
+        assert_eq!(expected, term);
+        assert_eq!("(letrec f = (λn. (g n)); g = (λn. (f n)) in ((f 1) + 1))", term.to_string());
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        let term = parse_expression("f (let x = 1 in x) letter inside")?;
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_let() {
+        let err = parse_expression("let x = 1 x").unwrap_err();
+        let expected = vec!["`;` or `in`".to_string()];
+        assert_eq!(ParseErrorKind::Unexpected { expected, found: "end of input".to_string() }, err.kind);
+        let err = parse_expression("letrec f = 1; f = 2 in f").unwrap_err();
+        assert_eq!((1, 15), (err.line, err.column));
+        assert!(parse_expression("let in = 1 in in").is_err());
+    }
+
+    #[test]
+    fn test_keyword_prefix() -> R {
+        let term = parse_expression("if iffy then trueish else false")?;
+        assert_eq!(ifte(var("iffy"), var("trueish"), b(false)), term);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_location() {
+        let input = "fac = λn. n;\nmain = (fac 5;\n";
+        let err = parse_main_program(input).unwrap_err();
+        assert_eq!((2, 14), (err.line, err.column));
+        assert_eq!(27..28, err.span);
+        assert_eq!(
+            ParseErrorKind::Unexpected {
+                expected: vec!["`)`".to_string()],
+                found: "`;`".to_string()
+            },
+            err.kind
+        );
+        assert_eq!("  |\n2 | main = (fac 5;\n  |              ^", err.snippet());
+    }
+
+    #[test]
+    fn test_error_expected_expression() {
+        let err = parse_expression("λx. x +").unwrap_err();
+        assert_eq!((1, 8), (err.line, err.column));
+        assert_eq!(
+            "parse error at 1:8: expected an expression, found end of input",
+            err.to_string().lines().next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_error_trailing_input() {
+        let err = parse_expression("f x )").unwrap_err();
+        assert_eq!(4..5, err.span);
+        assert_eq!(
+            ParseErrorKind::Unexpected {
+                expected: vec!["an operator".to_string(), "end of input".to_string()],
+                found: "`)`".to_string()
+            },
+            err.kind
+        );
+    }
+
+    #[test]
+    fn test_missing_main() {
+        let err = parse_main_program("x = 1;\ny = 2;\n").unwrap_err();
+        assert_eq!(ParseErrorKind::MissingMain, err.kind);
+        assert_eq!((2, 7), (err.line, err.column));
+    }
+
+    #[test]
+    fn test_data_declaration() -> R {
+        let input = "data List = Nil | Cons a List;\nmain = Cons 1 Nil;";
+        let program = parse_main_program(input)?;
+        let decl = &program.data[0];
+        assert_eq!("List", decl.name);
+        assert_eq!(vec!["a".to_string()], decl.all_params());
+        assert_eq!(con("Nil", 0, vec![]), program.env["Nil"]);
+        assert_eq!(con("Cons", 2, vec![]), program.env["Cons"]);
+        assert_eq!(app(app(var("Cons"), i(1)), var("Nil")), program.main);
+        assert_eq!("data List a = Nil | Cons a (List a);", decl.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_declaration_types() -> R {
+        let decls = parse_program("data Pair a b = Pair a b | Fn (a -> b) (Maybe Int);")?;
+        let Decl::Data(decl) = &decls[0] else { panic!("expected a data declaration") };
+        assert_eq!("data Pair a b = Pair a b | Fn (a -> b) (Maybe Int);", decl.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_case() -> R {
+        let input = "case n of 0 -> 1 | m if m > 0 -> m - 1 | _ -> 0";
+        let expected = case(
+            var("n"),
+            vec![
+                arm(Pattern::Int(0.into()), None, i(1)),
+                arm(Pattern::Var("m".to_string()), Some(gt(var("m"), i(0))), sub(var("m"), i(1))),
+                arm(Pattern::Wildcard, None, i(0)),
+            ],
+        );
+        let term = parse_expression(input)?;
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_case_constructor_patterns() -> R {
+        let input = "case xs of | Cons x (Cons (-1) _) -> x | Nil -> true";
+        let nested = Pattern::Con(
+            "Cons".to_string(),
+            vec![Pattern::Int((-1).into()), Pattern::Wildcard],
+        );
+        let expected = case(
+            var("xs"),
+            vec![
+                arm(Pattern::Con("Cons".to_string(), vec![Pattern::Var("x".to_string()), nested]), None, var("x")),
+                arm(Pattern::Con("Nil".to_string(), vec![]), None, b(true)),
+            ],
+        );
+        let term = parse_expression(input)?;
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_case_arm() {
+        let err = parse_expression("case x of 0 -> 1 | ) -> 2").unwrap_err();
+        assert_eq!((1, 20), (err.line, err.column));
+        assert_eq!(
+            ParseErrorKind::Unexpected { expected: vec!["a pattern".to_string()], found: "`)`".to_string() },
+            err.kind
+        );
+    }
+
+    #[test]
+    fn test_list() -> R {
+        assert_eq!(list(vec![]), parse_expression("[ ]")?);
+        let term = parse_expression("[1, x + 1, [true]]")?;
+        assert_eq!(list(vec![i(1), add(var("x"), i(1)), list(vec![b(true)])]), term);
+        assert_eq!("[1, (x + 1), [true]]", term.to_string());
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cons_right_associative() -> R {
+        let term = parse_expression("1 + 1 :: 2 :: xs == ys")?;
+        let expected = eq(cons(add(i(1), i(1)), cons(i(2), var("xs"))), var("ys"));
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_list() {
+        let err = parse_expression("[1, 2 3").unwrap_err();
+        assert_eq!((1, 8), (err.line, err.column));
+    }
+
+    #[test]
+    fn test_tuple() -> R {
+        assert_eq!(add(i(1), i(2)), parse_expression("(1 + 2)")?);
+        let term = parse_expression("(1, (x, true), [2]) == #2 (fst p, y)")?;
+        let expected = eq(
+            tuple(vec![i(1), tuple(vec![var("x"), b(true)]), list(vec![i(2)])]),
+            proj(2, tuple(vec![app(var("fst"), var("p")), var("y")])),
+        );
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tuple_parameters() -> R {
+        let term = parse_expression("λ(x, (y, _)) z. x + y + z")?;
+        let pattern = Pattern::Tuple(vec![
+            Pattern::Var("x".to_string()),
+            Pattern::Tuple(vec![Pattern::Var("y".to_string()), Pattern::Wildcard]),
+        ]);
+        let expected = abs_pattern(pattern, abs("z", add(add(var("x"), var("y")), var("z"))));
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        let term = parse_expression("case p of (0, x) -> x | (_, x) -> x")?;
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_tuple() {
+        let err = parse_expression("(1, )").unwrap_err();
+        assert_eq!((1, 5), (err.line, err.column));
+        let err = parse_expression("#0 (1, 2)").unwrap_err();
+        assert_eq!((1, 2), (err.line, err.column));
+    }
+
+    #[test]
+    fn test_record() -> R {
+        assert_eq!(Term::Record(vec![]), parse_expression("{}")?);
+        let term = parse_expression("{ name = 1, inner = { x = a == b } }.inner.x")?;
+        let expected = select(
+            select(record(vec![("name", i(1)), ("inner", record(vec![("x", eq(var("a"), var("b")))]))]), "inner"),
+            "x",
+        );
+        assert_eq!(expected, term);
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_update() -> R {
+        let term = parse_expression("{ f r | age = r.age + 1, name = 2 }")?;
+        let expected = update(
+            app(var("f"), var("r")),
+            vec![("age", add(select(var("r"), "age"), i(1))), ("name", i(2))],
+        );
+        assert_eq!(expected, term);
+        assert_eq!("{(f r) | age = (r.age + 1), name = 2}", term.to_string());
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_record() {
+        let err = parse_expression("{ x = 1, x = 2 }").unwrap_err();
+        assert_eq!((1, 10), (err.line, err.column));
+        assert!(err.to_string().contains("expected a field name that is not used yet, found `x`"));
+        let err = parse_expression("{ r | 1 }").unwrap_err();
+        assert_eq!((1, 7), (err.line, err.column));
+    }
+
+    #[test]
+    fn test_string_and_char() -> R {
+        let term = parse_expression(r#""say \"hi\"\n" ++ show 'a' ++ "tab\t\\""#)?;
+        let expected = concat(
+            string("say \"hi\"\n"),
+            concat(app(var("show"), character('a')), string("tab\t\\")),
+        );
+        assert_eq!(expected, term);
+        assert_eq!(r#"("say \"hi\"\n" ++ ((show 'a') ++ "tab\t\\"))"#, term.to_string());
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        assert_eq!(character('\''), parse_expression(r"'\''")?);
+        let term = parse_expression(r#"case s of "" -> '0' | "a\0" -> '\n' | _ -> 'x'"#)?;
+        assert_eq!(term, parse_expression(&term.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_in_string() {
+        let err = parse_expression(r#""abc"#).unwrap_err();
+        assert_eq!((1, 5), (err.line, err.column));
+        assert!(err.to_string().contains("expected `\"`, found end of input"));
+        let err = parse_expression(r#""a\qb""#).unwrap_err();
+        assert_eq!((1, 4), (err.line, err.column));
+        assert!(err.to_string().contains("expected an escape sequence, found `qb`"));
+        let err = parse_expression("'ab'").unwrap_err();
+        assert_eq!((1, 3), (err.line, err.column));
+    }
+
     // TODO: Add some more tests to cover the remaining syntax elements
 }
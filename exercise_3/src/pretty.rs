@@ -6,9 +6,12 @@ pub fn pretty_print(term: &Term) -> String {
     match term {
         Term::Var(name) => name.clone(),
         Term::Abs(param, body) => format!("(λ{}. {})", param, pretty_print(body)),
-        Term::App(t1, t2) => format!("({} {})", pretty_print(t1), pretty_print(t2)),
+        Term::App(t1, t2) => format!("({} {})", pretty_print(t1), pretty_print_arg(t2)),
         Term::Int(i) => format!("{i}"),
+        Term::Float(x) => pretty_print_float(*x),
         Term::Bool(b) => format!("{b}"),
+        Term::Str(s) => format!("\"{}\"", escape(s, '"')),
+        Term::Char(c) => format!("'{}'", escape(&c.to_string(), '\'')),
         Term::If(cond, t1, t2) => format!(
             "(if {} then {} else {})",
             pretty_print(cond),
@@ -18,17 +21,123 @@ pub fn pretty_print(term: &Term) -> String {
         Term::PrimOp(op, t1, t2) => format!(
             "({} {} {})",
             pretty_print(t1),
-            match op {
-                PrimOp::Add => "+",
-                PrimOp::Sub => "-",
-                PrimOp::Mul => "*",
-                PrimOp::Div => "/",
-                PrimOp::Eq => "==",
-                PrimOp::Lt => "<",
-                PrimOp::Gt => ">",
-            },
+            op_symbol(*op),
             pretty_print(t2)
         ),
+        Term::Con(tag, _, fields) if fields.is_empty() => tag.clone(),
+        Term::Con(tag, _, fields) => format!(
+            "({} {})",
+            tag,
+            fields.iter().map(pretty_print_arg).collect::<Vec<_>>().join(" ")
+        ),
+        Term::List(items) => format!("[{}]", items.iter().map(pretty_print).collect::<Vec<_>>().join(", ")),
+        Term::Builtin(builtin, args) if args.is_empty() => builtin.name().to_string(),
+        Term::Builtin(builtin, args) => format!(
+            "({} {})",
+            builtin.name(),
+            args.iter().map(pretty_print_arg).collect::<Vec<_>>().join(" ")
+        ),
+        Term::Tuple(items) => format!("({})", items.iter().map(pretty_print).collect::<Vec<_>>().join(", ")),
+        Term::Proj(index, t) => format!("(#{} {})", index, pretty_print(t)),
+        Term::AbsPattern(pattern, body) => format!("(λ{}. {})", pattern, pretty_print(body)),
+        Term::Record(fields) => format!("{{{}}}", pretty_print_fields(fields)),
+        Term::Select(t, field) => format!("{}.{}", pretty_print(t), field),
+        Term::Update(t, fields) => format!("{{{} | {}}}", pretty_print(t), pretty_print_fields(fields)),
+        Term::Case(scrutinee, arms) => format!(
+            "(case {} of {})",
+            pretty_print(scrutinee),
+            arms.iter().map(pretty_print_arm).collect::<Vec<_>>().join(" | ")
+        ),
+        Term::Let(bindings, body) => format!("(let {} in {})", pretty_print_bindings(bindings), pretty_print(body)),
+        Term::LetRec(bindings, body) => {
+            format!("(letrec {} in {})", pretty_print_bindings(bindings), pretty_print(body))
+        }
+    }
+}
+
+// An argument of an application. A negative number needs parentheses, as `f -1` is `f - 1`.
+fn pretty_print_arg(term: &Term) -> String {
+    match term {
+        Term::Int(n) if n.is_negative() => format!("({n})"),
+        Term::Float(x) if x.is_sign_negative() && x.is_finite() => format!("({})", pretty_print_float(*x)),
+        _ => pretty_print(term),
+    }
+}
+
+// Floats always have a fraction or an exponent, so they do not read back as integers.
+// Infinities and NaN have no literals and are printed as the divisions that produce them.
+fn pretty_print_float(x: f64) -> String {
+    if x.is_nan() {
+        "(0.0 / 0.0)".to_string()
+    } else if x.is_infinite() {
+        format!("({}1.0 / 0.0)", if x < 0.0 { "-" } else { "" })
+    } else {
+        format!("{x:?}")
+    }
+}
+
+fn pretty_print_bindings(bindings: &[(String, Term)]) -> String {
+    bindings
+        .iter()
+        .map(|(name, t)| format!("{name} = {}", pretty_print(t)))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn pretty_print_fields(fields: &[(String, Term)]) -> String {
+    fields
+        .iter()
+        .map(|(name, t)| format!("{name} = {}", pretty_print(t)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn pretty_print_arm(arm: &Arm) -> String {
+    match &arm.guard {
+        Some(guard) => format!("{} if {} -> {}", arm.pattern, pretty_print(guard), pretty_print(&arm.body)),
+        None => format!("{} -> {}", arm.pattern, pretty_print(&arm.body)),
+    }
+}
+
+// Escapes the characters that cannot appear literally between the given quotes.
+fn escape(s: &str, quote: char) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '\\' => escaped.push_str("\\\\"),
+            c if c == quote => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The concrete syntax of a primitive operator.
+pub fn op_symbol(op: PrimOp) -> &'static str {
+    match op {
+        PrimOp::Add => "+",
+        PrimOp::Sub => "-",
+        PrimOp::Mul => "*",
+        PrimOp::Div => "/",
+        PrimOp::Mod => "%",
+        PrimOp::Pow => "^",
+        PrimOp::Eq => "==",
+        PrimOp::Ne => "!=",
+        PrimOp::Lt => "<",
+        PrimOp::Gt => ">",
+        PrimOp::Le => "<=",
+        PrimOp::Ge => ">=",
+        PrimOp::And => "&&",
+        PrimOp::Or => "||",
+        PrimOp::Cons => "::",
+        PrimOp::Concat => "++",
     }
 }
 
@@ -38,3 +147,89 @@ impl fmt::Display for Term {
         write!(f, "{}", pretty_print(self))
     }
 }
+
+/// Display trait implementation for Pattern. Constructor patterns with fields are only
+/// parenthesised when nested.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Int(n) => write!(f, "{n}"),
+            Pattern::Bool(v) => write!(f, "{v}"),
+            Pattern::Str(s) => write!(f, "{}", Term::Str(s.clone())),
+            Pattern::Char(c) => write!(f, "{}", Term::Char(*c)),
+            Pattern::Var(x) => write!(f, "{x}"),
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Tuple(args) => {
+                write!(f, "({})", args.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))
+            }
+            Pattern::Con(tag, args) => {
+                write!(f, "{tag}")?;
+                for arg in args {
+                    match arg {
+                        Pattern::Con(_, args) if !args.is_empty() => write!(f, " ({arg})")?,
+                        Pattern::Int(n) if n.is_negative() => write!(f, " ({arg})")?,
+                        _ => write!(f, " {arg}")?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Display trait implementation for TypeExpr.
+impl fmt::Display for TypeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeExpr::Var(a) => write!(f, "{a}"),
+            TypeExpr::Con(name, args) => {
+                write!(f, "{name}")?;
+                for arg in args {
+                    match arg {
+                        TypeExpr::Var(_) => write!(f, " {arg}")?,
+                        TypeExpr::Con(_, args) if args.is_empty() => write!(f, " {arg}")?,
+                        _ => write!(f, " ({arg})")?,
+                    }
+                }
+                Ok(())
+            }
+            TypeExpr::Fun(t1, t2) => match **t1 {
+                TypeExpr::Fun(..) => write!(f, "({t1}) -> {t2}"),
+                _ => write!(f, "{t1} -> {t2}"),
+            },
+        }
+    }
+}
+
+/// Display trait implementation for DataDecl. Implicit parameters are written out.
+impl fmt::Display for DataDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self.all_params();
+        write!(f, "data {}", self.name)?;
+        for param in &params {
+            write!(f, " {param}")?;
+        }
+        // A bare reference to the declared type stands for the type applied to its parameters
+        let applied = TypeExpr::Con(self.name.clone(), params.iter().map(|p| TypeExpr::Var(p.clone())).collect());
+        let constructors: Vec<String> = self
+            .constructors
+            .iter()
+            .map(|(tag, fields)| {
+                let mut text = tag.clone();
+                for field in fields {
+                    let field = match field {
+                        TypeExpr::Con(name, args) if *name == self.name && args.is_empty() => &applied,
+                        field => field,
+                    };
+                    match field {
+                        TypeExpr::Var(_) => text.push_str(&format!(" {field}")),
+                        TypeExpr::Con(_, args) if args.is_empty() => text.push_str(&format!(" {field}")),
+                        _ => text.push_str(&format!(" ({field})")),
+                    }
+                }
+                text
+            })
+            .collect();
+        write!(f, " = {};", constructors.join(" | "))
+    }
+}
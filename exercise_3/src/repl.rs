@@ -0,0 +1,352 @@
+use crate::eval::*;
+use crate::parser::*;
+use crate::step::{is_value, reductions, step};
+use crate::term::*;
+use crate::types::infer;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+Enter an expression to evaluate it, or `name = expr;` to define a name.
+Commands:
+  :load <file>   load the definitions of a file
+  :env           list all definitions
+  :type <expr>   show the type of an expression
+  :trace <expr>  evaluate an expression and show each reduction
+  :step <expr>   reduce an expression one step at a time, naming each rule,
+                 for at most 1000 steps
+  :reset         forget all definitions
+  :help          show this message
+  :quit          leave the REPL";
+
+// How many steps `:step` shows unless the limits set `max_steps`
+const MAX_STEPS: usize = 1000;
+
+/// What the REPL does after handling a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Print the text (if not empty) and read the next line.
+    Continue(String),
+    Quit,
+}
+
+/// An interactive session. Definitions persist across lines.
+#[derive(Debug, Default)]
+pub struct Repl {
+    env: Env,
+    data: Vec<DataDecl>,
+    limits: EvalLimits,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl::default()
+    }
+
+    /// A session that evaluates within the given limits; `:step` stops after `max_steps`, or
+    /// after 1000 steps if it is not set.
+    pub fn with_limits(limits: EvalLimits) -> Repl {
+        Repl { limits, ..Repl::default() }
+    }
+
+    fn options(&self) -> EvalOptions {
+        EvalOptions { limits: self.limits.clone(), ..EvalOptions::default() }
+    }
+
+    /// The definitions entered so far.
+    pub fn env(&self) -> &Env {
+        &self.env
+    }
+
+    /// Handles one line of input. Errors are reported in the output, they never end the session.
+    pub fn handle(&mut self, line: &str) -> Outcome {
+        let line = line.trim();
+        let output = if let Some(command) = line.strip_prefix(':') {
+            let (command, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            match (command, arg.trim()) {
+                ("quit" | "q", _) => return Outcome::Quit,
+                ("help" | "h", _) => HELP.to_string(),
+                ("env", _) => self.show_env(),
+                ("reset", _) => {
+                    self.env.clear();
+                    self.data.clear();
+                    "Environment cleared.".to_string()
+                }
+                ("load" | "l", file) if !file.is_empty() => self.load(file),
+                ("type" | "t", expr) if !expr.is_empty() => self.show_type(expr),
+                ("trace", expr) if !expr.is_empty() => self.trace(expr),
+                ("step", expr) if !expr.is_empty() => self.steps(expr),
+                _ => format!("Unknown command `{line}`, type :help for help."),
+            }
+        } else if line.is_empty() {
+            String::new()
+        } else if is_definition(line) || line.starts_with("data ") {
+            self.define(line)
+        } else {
+            self.evaluate(line)
+        };
+        Outcome::Continue(output)
+    }
+
+    fn define(&mut self, line: &str) -> String {
+        let source = if line.ends_with(';') { line.to_string() } else { format!("{line};") };
+        match parse_program(&source) {
+            Ok(bindings) => self.add_bindings(bindings),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn add_bindings(&mut self, decls: Vec<Decl>) -> String {
+        let mut names = Vec::new();
+        for decl in decls {
+            match decl {
+                Decl::Binding(name, term) => {
+                    names.push(name.clone());
+                    self.env.insert(name, term);
+                }
+                Decl::Data(decl) => {
+                    names.push(decl.name.clone());
+                    self.env.extend(decl.constructor_bindings());
+                    self.data.retain(|d| d.name != decl.name);
+                    self.data.push(decl);
+                }
+            }
+        }
+        format!("Defined {}.", names.join(", "))
+    }
+
+    fn load(&mut self, file: &str) -> String {
+        match fs::read_to_string(file) {
+            Ok(source) => match parse_program(&source) {
+                Ok(bindings) => self.add_bindings(bindings),
+                Err(e) => format!("{file}: {e}"),
+            },
+            Err(e) => format!("cannot read {file}: {e}"),
+        }
+    }
+
+    fn show_env(&self) -> String {
+        let mut names: Vec<&String> = self
+            .env
+            .keys()
+            .filter(|name| !matches!(&self.env[*name], Term::Con(tag, _, fields) if tag == *name && fields.is_empty()))
+            .collect();
+        names.sort();
+        self.data
+            .iter()
+            .map(|decl| decl.to_string())
+            .chain(names.into_iter().map(|name| format!("{name} = {};", self.env[name])))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn show_type(&self, expr: &str) -> String {
+        match parse_expression(expr) {
+            Ok(term) => match infer(&self.env, &self.data, &term) {
+                Ok(scheme) => format!("{expr} : {scheme}"),
+                Err(e) => e.to_string(),
+            },
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn evaluate(&self, expr: &str) -> String {
+        match parse_expression(expr) {
+            Ok(term) => match eval_with(&self.env, &term, &self.options()) {
+                Ok(result) => result.to_string(),
+                Err(e) => format!("error: {e}"),
+            },
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn trace(&self, expr: &str) -> String {
+        let term = match parse_expression(expr) {
+            Ok(term) => term,
+            Err(e) => return e.to_string(),
+        };
+        let (result, steps) = eval_traced(&self.env, &term, &self.options());
+        let mut lines: Vec<String> = steps
+            .iter()
+            .enumerate()
+            .map(|(n, step)| format!("{:>3}. {}  ⟶  {}", n + 1, step.redex, step.result))
+            .collect();
+        lines.push(match result {
+            Ok(result) => result.to_string(),
+            Err(e) => format!("error: {e}"),
+        });
+        lines.join("\n")
+    }
+
+    fn steps(&self, expr: &str) -> String {
+        let term = match parse_expression(expr) {
+            Ok(term) => term,
+            Err(e) => return e.to_string(),
+        };
+        let mut last = term.clone();
+        let mut lines = vec![format!("     {term}")];
+        let max_steps = self.limits.max_steps.unwrap_or(MAX_STEPS);
+        for (n, step) in reductions(&self.env, &term).take(max_steps).enumerate() {
+            lines.push(format!("{:>3}. {}  [{}]", n + 1, step.term, step.rule));
+            last = step.term;
+        }
+        if lines.len() > max_steps && step(&self.env, &last).is_some() {
+            lines.push(format!("stopped after {max_steps} steps"));
+        } else if !is_value(&last) {
+            // A term that is not a value is stuck where evaluation fails
+            let options = EvalOptions { strict: true, ..self.options() };
+            if let Err(e) = eval_with(&self.env, &last, &options) {
+                lines.push(format!("error: {e}"));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+// A definition starts with a name followed by `=` (but not `==`).
+fn is_definition(line: &str) -> bool {
+    let rest = line.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+    rest.len() < line.len() && {
+        let rest = rest.trim_start();
+        rest.starts_with('=') && !rest.starts_with("==")
+    }
+}
+
+/// Runs a REPL session, reading lines from `input` until `:quit` or end of input.
+pub fn run(input: impl BufRead, output: impl Write) -> io::Result<()> {
+    run_with(Repl::new(), input, output)
+}
+
+/// Like `run`, with a session that may have been set up already.
+pub fn run_with(mut repl: Repl, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    writeln!(output, "fun REPL, type :help for help.")?;
+    let mut line = String::new();
+    loop {
+        write!(output, "fun> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+        match repl.handle(&line) {
+            Outcome::Continue(text) if text.is_empty() => {}
+            Outcome::Continue(text) => writeln!(output, "{text}")?,
+            Outcome::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(repl: &mut Repl, line: &str) -> String {
+        match repl.handle(line) {
+            Outcome::Continue(text) => text,
+            Outcome::Quit => panic!("unexpected quit"),
+        }
+    }
+
+    #[test]
+    fn test_definitions_persist() {
+        let mut repl = Repl::new();
+        assert_eq!("Defined double.", reply(&mut repl, "double = λx. x * 2;"));
+        assert_eq!("Defined x.", reply(&mut repl, "x = 21"));
+        assert_eq!("42", reply(&mut repl, "double x"));
+        assert_eq!("true", reply(&mut repl, "x == 21"));
+        assert_eq!("double = (λx. (x * 2));\nx = 21;", reply(&mut repl, ":env"));
+        assert_eq!("Environment cleared.", reply(&mut repl, ":reset"));
+        assert_eq!("(double x)", reply(&mut repl, "double x"));
+    }
+
+    #[test]
+    fn test_errors_do_not_end_session() {
+        let mut repl = Repl::new();
+        assert!(reply(&mut repl, "(1 +").starts_with("parse error at 1:5"));
+        assert_eq!("error: division by zero in (1 / 0)", reply(&mut repl, "1 / 0"));
+        assert!(reply(&mut repl, ":frobnicate").starts_with("Unknown command"));
+        assert_eq!("3", reply(&mut repl, "1 + 2"));
+        assert_eq!(Outcome::Quit, repl.handle(":quit"));
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut repl = Repl::new();
+        reply(&mut repl, "inc = λn. n + 1");
+        let trace = reply(&mut repl, ":trace inc 1");
+        assert_eq!(
+            vec![
+                "  1. inc  ⟶  (λn. (n + 1))",
+                "  2. ((λn. (n + 1)) 1)  ⟶  (1 + 1)",
+                "  3. (1 + 1)  ⟶  2",
+                "2",
+            ],
+            trace.lines().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let mut repl = Repl::new();
+        reply(&mut repl, "inc = λn. n + 1");
+        assert_eq!(
+            vec![
+                "     (inc (1 * 2))",
+                "  1. ((λn. (n + 1)) (1 * 2))  [global lookup]",
+                "  2. ((λn. (n + 1)) 2)  [delta-primop]",
+                "  3. (2 + 1)  [beta]",
+                "  4. 3  [delta-primop]",
+            ],
+            reply(&mut repl, ":step inc (1 * 2)").lines().collect::<Vec<_>>()
+        );
+        let steps = reply(&mut repl, ":step 1 + (0 - 1) / 0");
+        let expected = vec![
+            "     (1 + ((0 - 1) / 0))",
+            "  1. (1 + (-1 / 0))  [delta-primop]",
+            "error: division by zero in (-1 / 0)",
+        ];
+        assert_eq!(expected, steps.lines().collect::<Vec<_>>());
+
+        let mut repl = Repl::with_limits(EvalLimits { max_steps: Some(2), ..EvalLimits::default() });
+        let steps = reply(&mut repl, ":step (λx. x x) (λx. x x)");
+        assert_eq!(Some("stopped after 2 steps"), steps.lines().last());
+        assert!(reply(&mut repl, "(λx. x x) (λx. x x)").starts_with("error: evaluation stopped after 3 reductions"));
+
+        // A term that reduces forever stops by default too
+        let steps = reply(&mut Repl::new(), ":step (λx. x x) (λx. x x)");
+        assert_eq!(Some("stopped after 1000 steps"), steps.lines().last());
+        assert_eq!(1002, steps.lines().count());
+    }
+
+    #[test]
+    fn test_data() {
+        let mut repl = Repl::new();
+        assert_eq!("Defined List.", reply(&mut repl, "data List = Nil | Cons a List;"));
+        assert_eq!("(Cons 1 (Cons 2 Nil))", reply(&mut repl, "Cons 1 (Cons 2 Nil)"));
+        assert_eq!("Cons 1 : List Int -> List Int", reply(&mut repl, ":type Cons 1"));
+        assert_eq!("data List a = Nil | Cons a (List a);", reply(&mut repl, ":env"));
+    }
+
+    #[test]
+    fn test_load_and_type() {
+        let path = std::env::temp_dir().join(format!("fun-repl-{}.fun", std::process::id()));
+        fs::write(&path, "fac = λn. if n == 0 then 1 else n * fac (n - 1);\n").unwrap();
+        let mut repl = Repl::new();
+        assert_eq!("Defined fac.", reply(&mut repl, &format!(":load {}", path.display())));
+        fs::remove_file(&path).unwrap();
+        assert_eq!("120", reply(&mut repl, "fac 5"));
+        assert_eq!("fac : Int -> Int", reply(&mut repl, ":type fac"));
+    }
+
+    #[test]
+    fn test_run() {
+        let input = "x = 2\nx * x\n:quit\nx\n";
+        let mut output = Vec::new();
+        run(input.as_bytes(), &mut output).unwrap();
+        assert_eq!(
+            "fun REPL, type :help for help.\nfun> Defined x.\nfun> 4\nfun> ",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
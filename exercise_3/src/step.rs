@@ -0,0 +1,431 @@
+use crate::eval::{apply_primop, empty_env, eval_strict, match_pattern, substitute, substitute_let, unfold_letrec};
+use crate::eval::{substitute_all, EvalOptions};
+use crate::term::*;
+use std::fmt;
+
+/// The reduction rule applied by a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// An abstraction applied to a value: `(λx. t) v` becomes `t` with `v` for `x`.
+    Beta,
+    /// A primitive operation on values: `1 + 2` becomes `3`.
+    DeltaPrimOp,
+    /// A fully applied built-in function: `head [1]` becomes `1`.
+    DeltaBuiltin,
+    /// `if true then t1 else t2` becomes `t1`.
+    IfTrue,
+    /// `if false then t1 else t2` becomes `t2`.
+    IfFalse,
+    /// A top-level binding or built-in is replaced by its definition.
+    GlobalLookup,
+    /// A constructor or built-in function that expects more arguments takes the next one.
+    Argument,
+    /// `case` selects the first arm whose pattern matches.
+    Case,
+    /// `let` substitutes its first binding.
+    Let,
+    /// `letrec` replaces its names by the group.
+    LetRec,
+    /// A component of a tuple or a field of a record is selected, or a record is updated.
+    Field,
+    /// The fields of a record are sorted by name.
+    Record,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::Beta => "beta",
+            Rule::DeltaPrimOp => "delta-primop",
+            Rule::DeltaBuiltin => "delta-builtin",
+            Rule::IfTrue => "if-true",
+            Rule::IfFalse => "if-false",
+            Rule::GlobalLookup => "global lookup",
+            Rule::Argument => "argument",
+            Rule::Case => "case",
+            Rule::Let => "let",
+            Rule::LetRec => "letrec",
+            Rule::Field => "field",
+            Rule::Record => "record",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// One step of a reduction sequence: the rule applied and the whole term after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub rule: Rule,
+    pub term: Term,
+}
+
+/// Performs a single reduction step, or returns `None` if the term is a value or stuck.
+///
+/// Reduction is call-by-value from left to right and does not go under abstractions, so the
+/// last term of a sequence that ends in a value is the result of `eval_strict`. A term gets
+/// stuck where `eval_strict` fails.
+pub fn step(env: &Env, term: &Term) -> Option<Term> {
+    step_labelled(env, term).map(|step| step.term)
+}
+
+/// Like `step`, but also tells which rule was applied.
+pub fn step_labelled(env: &Env, term: &Term) -> Option<Step> {
+    reduce(env, term).map(|(rule, term)| Step { rule, term })
+}
+
+/// The steps that reduce a term, until it is a value or stuck. The sequence does not end if
+/// evaluation does not terminate.
+pub fn reductions<'a>(env: &'a Env, term: &Term) -> Reductions<'a> {
+    Reductions { env, term: Some(term.clone()) }
+}
+
+/// Iterator over the steps of a reduction sequence, see `reductions`.
+pub struct Reductions<'a> {
+    env: &'a Env,
+    term: Option<Term>,
+}
+
+impl Iterator for Reductions<'_> {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        let step = step_labelled(self.env, self.term.as_ref()?);
+        self.term = step.as_ref().map(|step| step.term.clone());
+        step
+    }
+}
+
+/// Whether a term is a value, so reduction stops there.
+pub fn is_value(term: &Term) -> bool {
+    match term {
+        Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => true,
+        Term::Abs(..) | Term::AbsPattern(..) => true,
+        Term::Con(_, _, items) | Term::List(items) | Term::Tuple(items) => items.iter().all(is_value),
+        Term::Builtin(builtin, args) => args.len() < builtin.arity() && args.iter().all(is_value),
+        Term::Record(fields) => {
+            fields.iter().all(|(_, t)| is_value(t)) && fields.windows(2).all(|w| w[0].0 < w[1].0)
+        }
+        _ => false,
+    }
+}
+
+// Reduces the first of `terms` that is not a value yet.
+fn reduce_first(env: &Env, terms: &[Term]) -> Option<(Rule, Vec<Term>)> {
+    let index = terms.iter().position(|t| !is_value(t))?;
+    let (rule, t) = reduce(env, &terms[index])?;
+    let mut terms = terms.to_vec();
+    terms[index] = t;
+    Some((rule, terms))
+}
+
+fn reduce(env: &Env, term: &Term) -> Option<(Rule, Term)> {
+    match term {
+        Term::Var(x) => match env.get(x) {
+            Some(t) => Some((Rule::GlobalLookup, t.clone())),
+            None => Builtin::from_name(x).map(|builtin| (Rule::GlobalLookup, Term::Builtin(builtin, vec![]))),
+        },
+
+        Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => None,
+        Term::Abs(..) | Term::AbsPattern(..) => None,
+
+        Term::App(t1, t2) => {
+            if !is_value(t1) || !is_value(t2) {
+                let (rule, [l, r]) = reduce_pair(env, t1, t2)?;
+                return Some((rule, app(l, r)));
+            }
+            let (l, r) = (&**t1, &**t2);
+            match l {
+                Term::Abs(param, body) => Some((Rule::Beta, substitute(body, param, r))),
+                Term::AbsPattern(pattern, body) => {
+                    let mut bindings = Vec::new();
+                    if match_pattern(pattern, r, &mut bindings) != Some(true) {
+                        return None;
+                    }
+                    Some((Rule::Beta, substitute_all(body, &bindings)))
+                }
+                Term::Con(tag, arity, fields) if fields.len() < *arity => {
+                    let mut fields = fields.clone();
+                    fields.push(r.clone());
+                    Some((Rule::Argument, Term::Con(tag.clone(), *arity, fields)))
+                }
+                Term::Builtin(builtin, args) => {
+                    let mut args = args.clone();
+                    args.push(r.clone());
+                    if args.len() < builtin.arity() {
+                        Some((Rule::Argument, Term::Builtin(*builtin, args)))
+                    } else {
+                        Some((Rule::DeltaBuiltin, delta(*builtin, args)?))
+                    }
+                }
+                _ => None,
+            }
+        }
+
+        Term::If(cond, t1, t2) => match &**cond {
+            Term::Bool(true) => Some((Rule::IfTrue, *t1.clone())),
+            Term::Bool(false) => Some((Rule::IfFalse, *t2.clone())),
+            c => {
+                let (rule, c) = reduce(env, c)?;
+                Some((rule, ifte(c, *t1.clone(), *t2.clone())))
+            }
+        },
+
+        Term::PrimOp(op, t1, t2) => {
+            if !is_value(t1) {
+                let (rule, l) = reduce(env, t1)?;
+                return Some((rule, primop(*op, l, *t2.clone())));
+            }
+            // `&&` and `||` do not evaluate the right operand if the left one decides the result
+            if let PrimOp::And | PrimOp::Or = op {
+                match &**t1 {
+                    Term::Bool(v) if *v == (*op == PrimOp::Or) => return Some((Rule::DeltaPrimOp, b(*v))),
+                    Term::Bool(_) => {}
+                    _ => return None,
+                }
+            }
+            if !is_value(t2) {
+                let (rule, r) = reduce(env, t2)?;
+                return Some((rule, primop(*op, *t1.clone(), r)));
+            }
+            let options = EvalOptions { strict: true, ..EvalOptions::default() };
+            let result = apply_primop(*op, *t1.clone(), *t2.clone(), &options).ok()?;
+            Some((Rule::DeltaPrimOp, result))
+        }
+
+        Term::Con(tag, arity, fields) => {
+            let (rule, fields) = reduce_first(env, fields)?;
+            Some((rule, Term::Con(tag.clone(), *arity, fields)))
+        }
+
+        Term::List(items) => reduce_first(env, items).map(|(rule, items)| (rule, Term::List(items))),
+
+        Term::Tuple(items) => reduce_first(env, items).map(|(rule, items)| (rule, Term::Tuple(items))),
+
+        Term::Builtin(builtin, args) if !args.iter().all(is_value) => {
+            let (rule, args) = reduce_first(env, args)?;
+            Some((rule, Term::Builtin(*builtin, args)))
+        }
+        Term::Builtin(builtin, args) if args.len() >= builtin.arity() => {
+            Some((Rule::DeltaBuiltin, delta(*builtin, args.clone())?))
+        }
+        Term::Builtin(..) => None,
+
+        Term::Record(fields) => {
+            let terms: Vec<Term> = fields.iter().map(|(_, t)| t.clone()).collect();
+            if !terms.iter().all(is_value) {
+                let (rule, terms) = reduce_first(env, &terms)?;
+                let fields = fields.iter().zip(terms).map(|((name, _), t)| (name.clone(), t)).collect();
+                return Some((rule, Term::Record(fields)));
+            }
+            if is_value(term) {
+                return None;
+            }
+            let mut fields = fields.clone();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Some((Rule::Record, Term::Record(fields)))
+        }
+
+        Term::Proj(index, t) => match &**t {
+            Term::Tuple(items) if is_value(t) => {
+                let item = items.get(index.checked_sub(1)?)?;
+                Some((Rule::Field, item.clone()))
+            }
+            t => {
+                let (rule, t) = reduce(env, t)?;
+                Some((rule, proj(*index, t)))
+            }
+        },
+
+        Term::Select(t, field) => match &**t {
+            Term::Record(fields) if is_value(t) => {
+                let (_, v) = fields.iter().find(|(name, _)| name == field)?;
+                Some((Rule::Field, v.clone()))
+            }
+            t => {
+                let (rule, t) = reduce(env, t)?;
+                Some((rule, select(t, field)))
+            }
+        },
+
+        Term::Update(t, updates) => {
+            if !is_value(t) {
+                let (rule, t) = reduce(env, t)?;
+                return Some((rule, Term::Update(Box::new(t), updates.clone())));
+            }
+            let terms: Vec<Term> = updates.iter().map(|(_, t)| t.clone()).collect();
+            if !terms.iter().all(is_value) {
+                let (rule, terms) = reduce_first(env, &terms)?;
+                let updates = updates.iter().zip(terms).map(|((name, _), t)| (name.clone(), t)).collect();
+                return Some((rule, Term::Update(t.clone(), updates)));
+            }
+            let Term::Record(fields) = &**t else { return None };
+            let mut fields = fields.clone();
+            for (name, v) in updates {
+                fields.iter_mut().find(|(field, _)| field == name)?.1 = v.clone();
+            }
+            Some((Rule::Field, Term::Record(fields)))
+        }
+
+        Term::Let(bindings, body) => match bindings.split_first() {
+            Some(((x, t), rest)) if is_value(t) => {
+                let result = match substitute_let(rest, body, x, t) {
+                    (rest, t) if rest.is_empty() => t,
+                    (rest, t) => Term::Let(rest, Box::new(t)),
+                };
+                Some((Rule::Let, result))
+            }
+            Some(((x, t), _)) => {
+                let (rule, t) = reduce(env, t)?;
+                let mut bindings = bindings.clone();
+                bindings[0] = (x.clone(), t);
+                Some((rule, Term::Let(bindings, body.clone())))
+            }
+            None => Some((Rule::Let, *body.clone())),
+        },
+
+        // Every name is replaced by the group itself, so a recursive call unfolds it again
+        Term::LetRec(bindings, body) => {
+            let unfolded = match &**body {
+                Term::Var(x) => match bindings.iter().find(|(name, _)| name == x) {
+                    Some((_, t)) => unfold_letrec(bindings, t),
+                    None => unfold_letrec(bindings, body),
+                },
+                _ => unfold_letrec(bindings, body),
+            };
+            Some((Rule::LetRec, unfolded))
+        }
+
+        Term::Case(scrutinee, arms) => {
+            if !is_value(scrutinee) {
+                let (rule, v) = reduce(env, scrutinee)?;
+                return Some((rule, case(v, arms.clone())));
+            }
+            // A guard becomes an `if` that falls back to the remaining arms
+            for (n, arm) in arms.iter().enumerate() {
+                let mut bindings = Vec::new();
+                if !match_pattern(&arm.pattern, scrutinee, &mut bindings)? {
+                    continue;
+                }
+                let bind = |t: &Term| substitute_all(t, &bindings);
+                let result = match &arm.guard {
+                    Some(guard) => ifte(bind(guard), bind(&arm.body), case(*scrutinee.clone(), arms[n + 1..].to_vec())),
+                    None => bind(&arm.body),
+                };
+                return Some((Rule::Case, result));
+            }
+            None
+        }
+    }
+}
+
+// Reduces the first of two terms that is not a value yet.
+fn reduce_pair(env: &Env, t1: &Term, t2: &Term) -> Option<(Rule, [Term; 2])> {
+    let (rule, terms) = reduce_first(env, &[t1.clone(), t2.clone()])?;
+    let [l, r] = <[Term; 2]>::try_from(terms).ok()?;
+    Some((rule, [l, r]))
+}
+
+// Applies a built-in function to all of its arguments. The higher-order ones unfold into
+// applications of their function, the others are computed right away.
+fn delta(builtin: Builtin, args: Vec<Term>) -> Option<Term> {
+    match (builtin, args.as_slice()) {
+        (Builtin::Map, [f, Term::List(items)]) => Some(list(items.iter().map(|x| app(f.clone(), x.clone())).collect())),
+        (Builtin::Filter, [_, Term::List(items)]) if items.is_empty() => Some(list(vec![])),
+        (Builtin::Filter, [p, Term::List(items)]) => {
+            let rest = Term::Builtin(builtin, vec![p.clone(), list(items[1..].to_vec())]);
+            Some(ifte(app(p.clone(), items[0].clone()), cons(items[0].clone(), rest.clone()), rest))
+        }
+        (Builtin::Foldr, [_, z, Term::List(items)]) if items.is_empty() => Some(z.clone()),
+        (Builtin::Foldr, [f, z, Term::List(items)]) => {
+            let rest = Term::Builtin(builtin, vec![f.clone(), z.clone(), list(items[1..].to_vec())]);
+            Some(app(app(f.clone(), items[0].clone()), rest))
+        }
+        (Builtin::Map | Builtin::Filter | Builtin::Foldr, _) => None,
+        // The arguments are values, so evaluation only applies the built-in
+        _ => eval_strict(&empty_env(), &Term::Builtin(builtin, args)).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_strict;
+    use crate::parser::parse_main_program;
+
+    fn rules(env: &Env, term: &Term) -> Vec<Rule> {
+        reductions(env, term).map(|step| step.rule).collect()
+    }
+
+    #[test]
+    fn test_step() {
+        let env = empty_env();
+        // (λx. if x < 2 then x else 0) 1
+        let term = app(abs("x", ifte(lt(var("x"), i(2)), var("x"), i(0))), i(1));
+        let first = step(&env, &term).unwrap();
+        assert_eq!(ifte(lt(i(1), i(2)), i(1), i(0)), first);
+        assert_eq!(Some(ifte(b(true), i(1), i(0))), step(&env, &first));
+        assert_eq!(vec![Rule::Beta, Rule::DeltaPrimOp, Rule::IfTrue], rules(&env, &term));
+        assert_eq!(None, step(&env, &i(1)));
+        assert_eq!(None, step(&env, &abs("x", add(i(1), i(1)))));
+    }
+
+    #[test]
+    fn test_reductions() {
+        let mut env = empty_env();
+        env.insert("inc".to_string(), abs("n", add(var("n"), i(1))));
+        let term = ifte(b(false), i(0), app(var("inc"), i(1)));
+        let steps: Vec<Step> = reductions(&env, &term).collect();
+        let expected = vec![
+            Step { rule: Rule::IfFalse, term: app(var("inc"), i(1)) },
+            Step { rule: Rule::GlobalLookup, term: app(abs("n", add(var("n"), i(1))), i(1)) },
+            Step { rule: Rule::Beta, term: add(i(1), i(1)) },
+            Step { rule: Rule::DeltaPrimOp, term: i(2) },
+        ];
+        assert_eq!(expected, steps);
+        assert_eq!("if-false, global lookup, beta, delta-primop", {
+            let names: Vec<String> = steps.iter().map(|step| step.rule.to_string()).collect();
+            names.join(", ")
+        });
+    }
+
+    #[test]
+    fn test_stuck_terms() {
+        let env = empty_env();
+        let head = Term::Builtin(Builtin::Head, vec![]);
+        for term in [div(i(1), i(0)), app(i(1), i(2)), var("undefined"), add(i(1), b(true)), app(head, list(vec![]))] {
+            assert_eq!(None, step(&env, &term), "{term}");
+            assert!(eval_strict(&env, &term).is_err());
+        }
+        // The error is where reduction stops
+        let term = add(i(1), mul(i(2), div(i(3), i(0))));
+        assert_eq!(None, step(&env, &term));
+    }
+
+    #[test]
+    fn test_steps_agree_with_eval() {
+        let input = r#"
+            data Shape = Circle a | Rect a a;
+            fac = λn. if n == 0 then 1 else n * fac (n - 1);
+            area = λs. case s of Circle r -> 3 * r * r | Rect w h if w == h -> w * w | Rect w h -> w * h;
+            swap = λ(a, b). (b, a);
+            point = { y = 2, x = 1 };
+            total = foldr (λx acc. x + acc) 0 (filter (λx. x % 2 == 0) (map (λx. x * x) [1, 2, 3, 4]));
+            main = (
+                fac 5,
+                map area [Circle 1, Rect 2 2, Rect 2 3],
+                swap (true, 'c'),
+                { point | x = point.y + #1 (10, 20) },
+                total,
+                let x = 2; y = x + 1 in letrec even = λn. if n == 0 then true else odd (n - 1);
+                                            odd = λn. if n == 0 then false else even (n - 1) in (even y, x),
+                show (length "fun") ++ substring "hello" 1 3,
+                (toFloat 3 / 2, round 2.5, null [] || head [] == 1, tail [1, 2] ++ append [3] [4]),
+                λx. x + fac 2
+            );
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let last = reductions(&prog.env, &prog.main).last().unwrap();
+        assert_eq!(eval_strict(&prog.env, &prog.main).unwrap(), last.term);
+        assert!(is_value(&last.term));
+    }
+}
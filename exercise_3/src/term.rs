@@ -1,15 +1,104 @@
+use crate::bignum::BigInt;
 use std::collections::HashMap;
 
 // Representation of our terms
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Term {
     Var(String),
     Abs(String, Box<Term>),
     App(Box<Term>, Box<Term>),
-    Int(i64),
+    Int(BigInt),
+    // A floating-point number. Mixed with an integer, the integer is converted to a float.
+    Float(f64),
     Bool(bool),
+    Str(String),
+    Char(char),
     If(Box<Term>, Box<Term>, Box<Term>),
-    PrimOp(PrimOp, Box<Term>, Box<Term>)
+    PrimOp(PrimOp, Box<Term>, Box<Term>),
+    // A data constructor with its tag, its arity and the fields applied so far.
+    // It is a value once all fields are present.
+    Con(String, usize, Vec<Term>),
+    // Tries the arms in order and evaluates the first one that matches the scrutinee
+    Case(Box<Term>, Vec<Arm>),
+    // A list literal: [1, 2, 3]
+    List(Vec<Term>),
+    // A built-in function with the arguments applied so far.
+    // It is evaluated as soon as all arguments are present.
+    Builtin(Builtin, Vec<Term>),
+    // A tuple with at least two components: (1, true)
+    Tuple(Vec<Term>),
+    // The n-th component of a tuple, counting from 1: #2 (1, true) == true
+    Proj(usize, Box<Term>),
+    // An abstraction that takes its argument apart with a pattern: λ(x, y). x + y
+    AbsPattern(Box<Pattern>, Box<Term>),
+    // A record with named fields: { name = "fun", age = 3 }
+    Record(Vec<(String, Term)>),
+    // Selects a field of a record: r.name
+    Select(Box<Term>, String),
+    // A copy of a record with some fields replaced: { r | age = 4 }
+    Update(Box<Term>, Vec<(String, Term)>),
+    // Local bindings, one after the other: let x = 1; y = x + 1 in x * y
+    // Each name scopes over the later bindings and the body.
+    Let(Vec<(String, Term)>, Box<Term>),
+    // Local recursive bindings: letrec even = λn. ...; odd = λn. ... in even 10
+    // All names scope over all bindings and the body.
+    LetRec(Vec<(String, Term)>, Box<Term>),
+}
+
+impl Term {
+    /// The number of nodes in the term, counting every subterm once.
+    pub fn size(&self) -> usize {
+        let sizes = |terms: &[Term]| terms.iter().map(Term::size).sum::<usize>();
+        let field_sizes = |fields: &[(String, Term)]| fields.iter().map(|(_, t)| t.size()).sum::<usize>();
+        1 + match self {
+            Term::Var(_) | Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => 0,
+            Term::Abs(_, t) | Term::AbsPattern(_, t) | Term::Proj(_, t) | Term::Select(t, _) => t.size(),
+            Term::App(t1, t2) | Term::PrimOp(_, t1, t2) => t1.size() + t2.size(),
+            Term::If(t1, t2, t3) => t1.size() + t2.size() + t3.size(),
+            Term::Con(_, _, items) | Term::List(items) | Term::Builtin(_, items) | Term::Tuple(items) => sizes(items),
+            Term::Case(t, arms) => {
+                let arm_size = |arm: &Arm| arm.guard.as_ref().map_or(0, Term::size) + arm.body.size();
+                t.size() + arms.iter().map(arm_size).sum::<usize>()
+            }
+            Term::Record(fields) => field_sizes(fields),
+            Term::Update(t, fields) | Term::Let(fields, t) | Term::LetRec(fields, t) => t.size() + field_sizes(fields),
+        }
+    }
+}
+
+// An arm of a case expression: pattern if guard -> body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub guard: Option<Term>,
+    pub body: Term,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Int(BigInt),
+    Bool(bool),
+    Str(String),
+    Char(char),
+    // Matches anything and binds it to the name
+    Var(String),
+    // Matches anything: _
+    Wildcard,
+    // Matches a fully applied constructor with the given tag
+    Con(String, Vec<Pattern>),
+    // Matches a tuple with as many components: (x, _)
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// The variables bound by the pattern, from left to right.
+    pub fn binders(&self) -> Vec<String> {
+        match self {
+            Pattern::Var(x) => vec![x.clone()],
+            Pattern::Con(_, args) | Pattern::Tuple(args) => args.iter().flat_map(Pattern::binders).collect(),
+            Pattern::Int(_) | Pattern::Bool(_) | Pattern::Str(_) | Pattern::Char(_) | Pattern::Wildcard => vec![],
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
@@ -18,10 +107,101 @@ pub enum PrimOp {
     Sub, // -
     Mul, // *
     Div, // /
+    Mod, // %, the remainder of /
+    Pow, // ^
     Eq,  // ==
+    Ne,  // !=
     Lt,  // <
     Gt,  // >
-    // TODO: add `&&` and `||` operators
+    Le,  // <=
+    Ge,  // >=
+    And, // &&, does not evaluate the right operand if the left one is false
+    Or,  // ||, does not evaluate the right operand if the left one is true
+    Cons, // ::
+    Concat, // ++ of strings and lists
+}
+
+// Functions that are always in scope unless the program defines the same name
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub enum Builtin {
+    Head,      // head [1, 2] == 1
+    Tail,      // tail [1, 2] == [2]
+    Null,      // null [] == true
+    Length,    // length [1, 2] == 2, also for strings: length "ab" == 2
+    Append,    // append [1] [2] == [1, 2]
+    Map,       // map f [1, 2] == [f 1, f 2]
+    Filter,    // filter p xs keeps the elements for which p is true
+    Foldr,     // foldr f z [1, 2] == f 1 (f 2 z)
+    Fst,       // fst (1, 2) == 1
+    Snd,       // snd (1, 2) == 2
+    Substring, // substring "hello" 1 3 == "ell", 3 characters from index 1
+    Show,      // show 42 == "42"
+    Not,       // not true == false
+    ToFloat,   // toFloat 2 == 2.0
+    Floor,     // floor 2.5 == 2
+    Round,     // round 2.5 == 3, halfway cases are rounded away from zero
+    Truncate,  // truncate (0.0 - 2.5) == -2
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 17] = [
+        Builtin::Head,
+        Builtin::Tail,
+        Builtin::Null,
+        Builtin::Length,
+        Builtin::Append,
+        Builtin::Map,
+        Builtin::Filter,
+        Builtin::Foldr,
+        Builtin::Fst,
+        Builtin::Snd,
+        Builtin::Substring,
+        Builtin::Show,
+        Builtin::Not,
+        Builtin::ToFloat,
+        Builtin::Floor,
+        Builtin::Round,
+        Builtin::Truncate,
+    ];
+
+    /// The name the built-in is referred to by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Head => "head",
+            Builtin::Tail => "tail",
+            Builtin::Null => "null",
+            Builtin::Length => "length",
+            Builtin::Append => "append",
+            Builtin::Map => "map",
+            Builtin::Filter => "filter",
+            Builtin::Foldr => "foldr",
+            Builtin::Fst => "fst",
+            Builtin::Snd => "snd",
+            Builtin::Substring => "substring",
+            Builtin::Show => "show",
+            Builtin::Not => "not",
+            Builtin::ToFloat => "toFloat",
+            Builtin::Floor => "floor",
+            Builtin::Round => "round",
+            Builtin::Truncate => "truncate",
+        }
+    }
+
+    /// The number of arguments the built-in takes.
+    pub fn arity(self) -> usize {
+        match self {
+            Builtin::Head | Builtin::Tail | Builtin::Null | Builtin::Length => 1,
+            Builtin::Fst | Builtin::Snd | Builtin::Show | Builtin::Not => 1,
+            Builtin::ToFloat | Builtin::Floor | Builtin::Round | Builtin::Truncate => 1,
+            Builtin::Append | Builtin::Map | Builtin::Filter => 2,
+            Builtin::Foldr | Builtin::Substring => 3,
+        }
+    }
+
+    /// Looks up a built-in by name.
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Builtin::ALL.into_iter().find(|b| b.name() == name)
+    }
 }
 
 // Binds names to terms
@@ -32,6 +212,64 @@ pub type Env = HashMap<String, Term>;
 pub struct Program { 
     pub env: Env,
     pub main: Term,
+    pub data: Vec<DataDecl>,
+    // The names of the top-level bindings and constructors in the order they are defined.
+    // A name that is defined twice is listed twice; `env` holds its last definition.
+    pub names: Vec<String>,
+}
+
+// A top-level declaration of a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decl {
+    Binding(String, Term),
+    Data(DataDecl),
+}
+
+// A data type declaration: data List a = Nil | Cons a (List a);
+// Type variables that are not listed as parameters are implicit parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub constructors: Vec<(String, Vec<TypeExpr>)>,
+}
+
+// The field types of a data constructor as written in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeExpr {
+    Var(String),
+    Con(String, Vec<TypeExpr>),
+    Fun(Box<TypeExpr>, Box<TypeExpr>),
+}
+
+impl DataDecl {
+    /// The declared parameters followed by the implicit ones, in order of appearance.
+    pub fn all_params(&self) -> Vec<String> {
+        fn collect(ty: &TypeExpr, params: &mut Vec<String>) {
+            match ty {
+                TypeExpr::Var(a) if !params.contains(a) => params.push(a.clone()),
+                TypeExpr::Var(_) => {}
+                TypeExpr::Con(_, args) => args.iter().for_each(|t| collect(t, params)),
+                TypeExpr::Fun(t1, t2) => {
+                    collect(t1, params);
+                    collect(t2, params);
+                }
+            }
+        }
+        let mut params = self.params.clone();
+        for (_, fields) in &self.constructors {
+            fields.iter().for_each(|t| collect(t, &mut params));
+        }
+        params
+    }
+
+    /// Binds every constructor name to its (unapplied) constructor term.
+    pub fn constructor_bindings(&self) -> Vec<(String, Term)> {
+        self.constructors
+            .iter()
+            .map(|(tag, fields)| (tag.clone(), con(tag, fields.len(), vec![])))
+            .collect()
+    }
 }
 
 /// Helper function to create a variable term.
@@ -51,7 +289,12 @@ pub fn app(t1: Term, t2: Term) -> Term {
 
 /// Helper function to create a literal term.
 pub fn i(n: i64) -> Term {
-    Term::Int(n)
+    Term::Int(BigInt::from(n))
+}
+
+/// Helper function to create a floating-point literal term.
+pub fn f(x: f64) -> Term {
+    Term::Float(x)
 }
 
 /// Helper function to create a literal term.
@@ -59,6 +302,16 @@ pub fn b(b: bool) -> Term {
     Term::Bool(b)
 }
 
+/// Helper function to create a string literal term.
+pub fn string(s: &str) -> Term {
+    Term::Str(s.to_string())
+}
+
+/// Helper function to create a character literal term.
+pub fn character(c: char) -> Term {
+    Term::Char(c)
+}
+
 /// Helper function to create an if term.
 pub fn ifte(cond: Term, t1: Term, t2: Term) -> Term {
     Term::If(Box::new(cond), Box::new(t1), Box::new(t2))
@@ -69,6 +322,56 @@ pub fn primop(op: PrimOp, t1: Term, t2: Term) -> Term {
     Term::PrimOp(op, Box::new(t1), Box::new(t2))
 }
 
+/// Helper function to create a constructor term.
+pub fn con(tag: &str, arity: usize, fields: Vec<Term>) -> Term {
+    Term::Con(tag.to_string(), arity, fields)
+}
+
+/// Helper function to create a case term.
+pub fn case(scrutinee: Term, arms: Vec<Arm>) -> Term {
+    Term::Case(Box::new(scrutinee), arms)
+}
+
+/// Helper function to create an arm of a case term.
+pub fn arm(pattern: Pattern, guard: Option<Term>, body: Term) -> Arm {
+    Arm { pattern, guard, body }
+}
+
+/// Helper function to create a list term.
+pub fn list(items: Vec<Term>) -> Term {
+    Term::List(items)
+}
+
+/// Helper function to create a tuple term.
+pub fn tuple(items: Vec<Term>) -> Term {
+    Term::Tuple(items)
+}
+
+/// Helper function to create a projection term.
+pub fn proj(index: usize, t: Term) -> Term {
+    Term::Proj(index, Box::new(t))
+}
+
+/// Helper function to create an abstraction with a pattern parameter.
+pub fn abs_pattern(pattern: Pattern, body: Term) -> Term {
+    Term::AbsPattern(Box::new(pattern), Box::new(body))
+}
+
+/// Helper function to create a record term.
+pub fn record(fields: Vec<(&str, Term)>) -> Term {
+    Term::Record(fields.into_iter().map(|(name, t)| (name.to_string(), t)).collect())
+}
+
+/// Helper function to create a field selection term.
+pub fn select(t: Term, field: &str) -> Term {
+    Term::Select(Box::new(t), field.to_string())
+}
+
+/// Helper function to create a record update term.
+pub fn update(t: Term, fields: Vec<(&str, Term)>) -> Term {
+    Term::Update(Box::new(t), fields.into_iter().map(|(name, t)| (name.to_string(), t)).collect())
+}
+
 pub fn add(t1: Term, t2: Term) -> Term {
     primop(PrimOp::Add, t1, t2)
 }
@@ -85,10 +388,22 @@ pub fn div(t1: Term, t2: Term) -> Term {
     primop(PrimOp::Div, t1, t2)
 }
 
+pub fn modulo(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Mod, t1, t2)
+}
+
+pub fn pow(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Pow, t1, t2)
+}
+
 pub fn eq(t1: Term, t2: Term) -> Term {
     primop(PrimOp::Eq, t1, t2)
 }
 
+pub fn ne(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Ne, t1, t2)
+}
+
 pub fn lt(t1: Term, t2: Term) -> Term {
     primop(PrimOp::Lt, t1, t2)
 }   
@@ -96,3 +411,37 @@ pub fn lt(t1: Term, t2: Term) -> Term {
 pub fn gt(t1: Term, t2: Term) -> Term {
     primop(PrimOp::Gt, t1, t2)
 }
+
+pub fn le(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Le, t1, t2)
+}
+
+pub fn ge(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Ge, t1, t2)
+}
+
+pub fn and(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::And, t1, t2)
+}
+
+pub fn or(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Or, t1, t2)
+}
+
+pub fn cons(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Cons, t1, t2)
+}
+
+pub fn concat(t1: Term, t2: Term) -> Term {
+    primop(PrimOp::Concat, t1, t2)
+}
+
+/// Helper function to create a let term.
+pub fn let_in(bindings: Vec<(&str, Term)>, body: Term) -> Term {
+    Term::Let(bindings.into_iter().map(|(x, t)| (x.to_string(), t)).collect(), Box::new(body))
+}
+
+/// Helper function to create a letrec term.
+pub fn letrec(bindings: Vec<(&str, Term)>, body: Term) -> Term {
+    Term::LetRec(bindings.into_iter().map(|(x, t)| (x.to_string(), t)).collect(), Box::new(body))
+}
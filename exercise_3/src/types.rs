@@ -0,0 +1,1271 @@
+use crate::deps::binding_groups;
+use crate::term::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The types of fun terms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Char,
+    /// A type variable, to be determined by unification.
+    Var(u32),
+    Fun(Box<Type>, Box<Type>),
+    /// A data type applied to its parameters, e.g. `List Int`.
+    Con(String, Vec<Type>),
+    /// A built-in list, e.g. `[Int]`.
+    List(Box<Type>),
+    /// A tuple, e.g. `(Int, Bool)`.
+    Tuple(Vec<Type>),
+    /// A record with its fields sorted by name, e.g. `{age: Int, name: Bool}`.
+    Record(Vec<(String, Type)>),
+}
+
+/// A type with universally quantified variables, e.g. `∀a. a -> a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scheme {
+    pub vars: Vec<u32>,
+    pub ty: Type,
+}
+
+/// A type error, located at the offending subterm.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeError {
+    pub kind: Box<TypeErrorKind>,
+    /// The subterm whose type could not be inferred.
+    pub term: Term,
+    /// The top-level binding the subterm belongs to, if any.
+    pub binding: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeErrorKind {
+    /// Two types that should be equal could not be unified.
+    Mismatch { expected: Type, found: Type },
+    /// Unification would create an infinite type, e.g. for `λx. x x`.
+    InfiniteType { var: Type, ty: Type },
+    UnboundVariable(String),
+    /// A data declaration refers to a type that is not declared, or with the wrong number of parameters.
+    UnknownType(String),
+    /// A constructor pattern does not have as many fields as the constructor.
+    PatternArity { constructor: String, expected: usize, found: usize },
+    /// A projection `#n` of a term that is not known to be a tuple with at least n components.
+    Projection { index: usize, ty: Type },
+    /// A field is selected or updated in a term that is not known to be a record with that field.
+    Field { field: String, ty: Type },
+    /// An overloaded operation is used at a type it does not support, e.g. `show [1]`.
+    Overload { expected: &'static str, found: Type },
+}
+
+// The types an overloaded operation supports. A type that is still unknown when its binding
+// has been checked defaults to the first of them, except for `Eq`, which leaves it unknown, and
+// for a number mixed with a Float, which defaults to Float.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// Lists and strings, for `length` and `++`.
+    Sequence,
+    /// Int, Float, Bool, Char and String, for `show`.
+    Show,
+    /// Int, Float, Char and String, for `<`, `>`, `<=` and `>=`.
+    Ord,
+    /// Any type without functions in it, for `==` and `!=`.
+    Eq,
+    /// Int and Float, for an operand of arithmetic, or one compared with a number.
+    Num { float: bool },
+}
+
+impl Class {
+    fn describe(self) -> &'static str {
+        match self {
+            Class::Sequence => "a list or String",
+            Class::Show => "Int, Float, Bool, Char or String",
+            Class::Ord => "Int, Float, Char or String",
+            Class::Eq => "a type without functions",
+            Class::Num { .. } => "Int or Float",
+        }
+    }
+
+    fn admits(self, ty: &Type) -> bool {
+        match self {
+            Class::Sequence => matches!(ty, Type::List(_) | Type::Str),
+            Class::Show => matches!(ty, Type::Int | Type::Float | Type::Bool | Type::Char | Type::Str),
+            Class::Ord => matches!(ty, Type::Int | Type::Float | Type::Char | Type::Str),
+            Class::Eq => match ty {
+                Type::Fun(_, _) => false,
+                Type::Con(_, tys) | Type::Tuple(tys) => tys.iter().all(|ty| self.admits(ty)),
+                Type::List(ty) => self.admits(ty),
+                Type::Record(fields) => fields.iter().all(|(_, ty)| self.admits(ty)),
+                _ => true,
+            },
+            Class::Num { .. } => is_numeric(ty),
+        }
+    }
+}
+
+// A pending requirement that `ty` is one of the types of `class`.
+#[derive(Debug, Clone)]
+struct Constraint {
+    class: Class,
+    ty: Type,
+    term: Term,
+    binding: Option<String>,
+}
+
+// Whether `ty` is Int or Float, which arithmetic and comparisons can mix.
+fn is_numeric(ty: &Type) -> bool {
+    matches!(ty, Type::Int | Type::Float)
+}
+
+/// Helper function to create a function type.
+pub fn fun(t1: Type, t2: Type) -> Type {
+    Type::Fun(Box::new(t1), Box::new(t2))
+}
+
+/// Infers the principal types of all bindings of a program, including `main`.
+///
+/// Bindings are checked in groups of mutually recursive definitions, in dependency order.
+/// Within a group the bindings are monomorphic; afterwards they are generalised, so top-level
+/// bindings can be used at different types.
+pub fn infer_program(program: &Program) -> Result<HashMap<String, Scheme>, TypeError> {
+    let mut w = Infer::new(&program.data)?;
+    w.infer_env(&program.env)
+}
+
+/// Infers the principal type of `term`, given the top-level bindings in `env` and the data
+/// types declared in `data`.
+pub fn infer(env: &Env, data: &[DataDecl], term: &Term) -> Result<Scheme, TypeError> {
+    let mut w = Infer::new(data)?;
+    let schemes = w.infer_env(env)?;
+    w.binding = None;
+    let (mut s, ty) = w.infer(&schemes, term)?;
+    w.resolve(&mut s)?;
+    Ok(generalize(&HashMap::new(), &apply(&s, &ty)))
+}
+
+// The type schemes of the constructors of `data`: `Cons : a -> List a -> List a`
+fn constructor_schemes(data: &[DataDecl]) -> Result<HashMap<String, Scheme>, TypeError> {
+    let arities: HashMap<&str, usize> = data
+        .iter()
+        .map(|decl| (decl.name.as_str(), decl.all_params().len()))
+        .collect();
+    let mut schemes = HashMap::new();
+    for decl in data {
+        let params = decl.all_params();
+        let vars: HashMap<&str, u32> = params.iter().zip(0..).map(|(p, v)| (p.as_str(), v)).collect();
+        let result = Type::Con(decl.name.clone(), (0..params.len() as u32).map(Type::Var).collect());
+        for (tag, fields) in &decl.constructors {
+            let convert = |ty: &TypeExpr| {
+                type_of_expr(ty, &vars, &arities, decl).map_err(|name| TypeError {
+                    kind: Box::new(TypeErrorKind::UnknownType(name)),
+                    term: var(tag),
+                    binding: None,
+                })
+            };
+            let mut ty = result.clone();
+            for field in fields.iter().rev() {
+                ty = fun(convert(field)?, ty);
+            }
+            schemes.insert(tag.clone(), Scheme { vars: (0..params.len() as u32).collect(), ty });
+        }
+    }
+    Ok(schemes)
+}
+
+// Converts a field type of `decl`. A bare reference to the declared type stands for the type
+// applied to its own parameters, so `data List = Nil | Cons a List;` is `List a`.
+fn type_of_expr(
+    ty: &TypeExpr,
+    vars: &HashMap<&str, u32>,
+    arities: &HashMap<&str, usize>,
+    decl: &DataDecl,
+) -> Result<Type, String> {
+    match ty {
+        TypeExpr::Var(a) => Ok(Type::Var(vars[a.as_str()])),
+        TypeExpr::Fun(t1, t2) => Ok(fun(
+            type_of_expr(t1, vars, arities, decl)?,
+            type_of_expr(t2, vars, arities, decl)?,
+        )),
+        TypeExpr::Con(name, args) if args.is_empty() && name == "Int" => Ok(Type::Int),
+        TypeExpr::Con(name, args) if args.is_empty() && name == "Float" => Ok(Type::Float),
+        TypeExpr::Con(name, args) if args.is_empty() && name == "Bool" => Ok(Type::Bool),
+        TypeExpr::Con(name, args) if args.is_empty() && name == "String" => Ok(Type::Str),
+        TypeExpr::Con(name, args) if args.is_empty() && name == "Char" => Ok(Type::Char),
+        TypeExpr::Con(name, args) if args.is_empty() && *name == decl.name => Ok(Type::Con(
+            name.clone(),
+            (0..arities[name.as_str()] as u32).map(Type::Var).collect(),
+        )),
+        TypeExpr::Con(name, args) if arities.get(name.as_str()) == Some(&args.len()) => Ok(Type::Con(
+            name.clone(),
+            args.iter()
+                .map(|t| type_of_expr(t, vars, arities, decl))
+                .collect::<Result<_, _>>()?,
+        )),
+        TypeExpr::Con(name, _) => Err(name.clone()),
+    }
+}
+
+// The type schemes of the built-in functions use the variables 0 and 1. The overloaded
+// built-ins constrain the variable 0, see `builtin_class`.
+const BUILTIN_VARS: u32 = 2;
+
+fn builtin_scheme(builtin: Builtin) -> Scheme {
+    let a = Type::Var(0);
+    let b = Type::Var(1);
+    let list = |t: &Type| Type::List(Box::new(t.clone()));
+    let ty = match builtin {
+        Builtin::Head => fun(list(&a), a.clone()),
+        Builtin::Tail => fun(list(&a), list(&a)),
+        Builtin::Null => fun(list(&a), Type::Bool),
+        Builtin::Length => fun(a.clone(), Type::Int),
+        Builtin::Append => fun(list(&a), fun(list(&a), list(&a))),
+        Builtin::Map => fun(fun(a.clone(), b.clone()), fun(list(&a), list(&b))),
+        Builtin::Filter => fun(fun(a.clone(), Type::Bool), fun(list(&a), list(&a))),
+        Builtin::Fst => fun(Type::Tuple(vec![a.clone(), b.clone()]), a.clone()),
+        Builtin::Snd => fun(Type::Tuple(vec![a.clone(), b.clone()]), b.clone()),
+        Builtin::Foldr => fun(fun(a.clone(), fun(b.clone(), b.clone())), fun(b.clone(), fun(list(&a), b.clone()))),
+        Builtin::Substring => fun(Type::Str, fun(Type::Int, fun(Type::Int, Type::Str))),
+        Builtin::Show => fun(a.clone(), Type::Str),
+        Builtin::ToFloat => fun(Type::Int, Type::Float),
+        Builtin::Floor | Builtin::Round | Builtin::Truncate => fun(Type::Float, Type::Int),
+        Builtin::Not => fun(Type::Bool, Type::Bool),
+    };
+    let mut vars = Vec::new();
+    type_vars(&ty, &mut vars);
+    Scheme { vars, ty }
+}
+
+fn builtin_class(builtin: Builtin) -> Option<Class> {
+    match builtin {
+        Builtin::Length => Some(Class::Sequence),
+        Builtin::Show => Some(Class::Show),
+        _ => None,
+    }
+}
+
+type Subst = HashMap<u32, Type>;
+
+// Applies a substitution to a type.
+fn apply(s: &Subst, ty: &Type) -> Type {
+    match ty {
+        Type::Var(v) => match s.get(v) {
+            Some(t) => apply(s, t),
+            None => ty.clone(),
+        },
+        Type::Fun(t1, t2) => fun(apply(s, t1), apply(s, t2)),
+        Type::Con(name, args) => Type::Con(name.clone(), args.iter().map(|t| apply(s, t)).collect()),
+        Type::List(t) => Type::List(Box::new(apply(s, t))),
+        Type::Tuple(items) => Type::Tuple(items.iter().map(|t| apply(s, t)).collect()),
+        Type::Record(fields) => Type::Record(fields.iter().map(|(name, t)| (name.clone(), apply(s, t))).collect()),
+        Type::Int | Type::Float | Type::Bool | Type::Str | Type::Char => ty.clone(),
+    }
+}
+
+fn apply_scheme(s: &Subst, scheme: &Scheme) -> Scheme {
+    let s: Subst = s
+        .iter()
+        .filter(|(v, _)| !scheme.vars.contains(v))
+        .map(|(v, t)| (*v, t.clone()))
+        .collect();
+    Scheme { vars: scheme.vars.clone(), ty: apply(&s, &scheme.ty) }
+}
+
+fn apply_env(s: &Subst, env: &HashMap<String, Scheme>) -> HashMap<String, Scheme> {
+    env.iter().map(|(x, sc)| (x.clone(), apply_scheme(s, sc))).collect()
+}
+
+// The substitution that applies `s2` first and then `s1`.
+fn compose(s1: &Subst, s2: &Subst) -> Subst {
+    let mut s: Subst = s2.iter().map(|(v, t)| (*v, apply(s1, t))).collect();
+    for (v, t) in s1 {
+        s.entry(*v).or_insert_with(|| t.clone());
+    }
+    s
+}
+
+fn type_vars(ty: &Type, vars: &mut Vec<u32>) {
+    match ty {
+        Type::Var(v) if !vars.contains(v) => vars.push(*v),
+        Type::Fun(t1, t2) => {
+            type_vars(t1, vars);
+            type_vars(t2, vars);
+        }
+        Type::Con(_, args) => args.iter().for_each(|t| type_vars(t, vars)),
+        Type::List(t) => type_vars(t, vars),
+        Type::Tuple(items) => items.iter().for_each(|t| type_vars(t, vars)),
+        Type::Record(fields) => fields.iter().for_each(|(_, t)| type_vars(t, vars)),
+        _ => {}
+    }
+}
+
+// Quantifies the type variables of `ty` that are not free in `env`.
+fn generalize(env: &HashMap<String, Scheme>, ty: &Type) -> Scheme {
+    let mut env_vars = HashSet::new();
+    for scheme in env.values() {
+        let mut vars = Vec::new();
+        type_vars(&scheme.ty, &mut vars);
+        env_vars.extend(vars.into_iter().filter(|v| !scheme.vars.contains(v)));
+    }
+    let mut vars = Vec::new();
+    type_vars(ty, &mut vars);
+    vars.retain(|v| !env_vars.contains(v));
+    Scheme { vars, ty: ty.clone() }
+}
+
+// State of algorithm W: the supply of fresh type variables, the constructor types and arities,
+// the binding being checked and the constraints of overloaded operations not resolved yet.
+struct Infer {
+    next_var: u32,
+    constructors: HashMap<String, Scheme>,
+    arities: HashMap<String, usize>,
+    binding: Option<String>,
+    constraints: Vec<Constraint>,
+}
+
+impl Infer {
+    fn new(data: &[DataDecl]) -> Result<Infer, TypeError> {
+        let constructors = constructor_schemes(data)?;
+        // Fresh variables must not clash with the quantified variables of the constructors
+        // and the built-ins
+        let next_var = data
+            .iter()
+            .map(|decl| decl.all_params().len() as u32)
+            .max()
+            .unwrap_or(0)
+            .max(BUILTIN_VARS);
+        let arities = data
+            .iter()
+            .flat_map(|decl| decl.constructors.iter().map(|(tag, fields)| (tag.clone(), fields.len())))
+            .collect();
+        Ok(Infer { next_var, constructors, arities, binding: None, constraints: Vec::new() })
+    }
+
+    fn infer_env(&mut self, env: &Env) -> Result<HashMap<String, Scheme>, TypeError> {
+        let mut schemes = HashMap::new();
+        for group in binding_groups(env) {
+            // Assume a fresh monomorphic type for every name of the group
+            let mut group_env = schemes.clone();
+            let mut assumed = Vec::new();
+            for name in &group {
+                let ty = self.fresh();
+                group_env.insert(name.clone(), Scheme { vars: vec![], ty: ty.clone() });
+                assumed.push(ty);
+            }
+
+            let mut s = Subst::new();
+            for (name, assumed) in group.iter().zip(&assumed) {
+                self.binding = Some(name.clone());
+                let term = &env[name];
+                let (s1, ty) = self.infer(&apply_env(&s, &group_env), term)?;
+                s = compose(&s1, &s);
+                let s2 = self.unify(&apply(&s, assumed), &ty, term)?;
+                s = compose(&s2, &s);
+            }
+            self.resolve(&mut s)?;
+
+            let outer = apply_env(&s, &schemes);
+            for (name, assumed) in group.iter().zip(&assumed) {
+                schemes.insert(name.clone(), generalize(&outer, &apply(&s, assumed)));
+            }
+        }
+        Ok(schemes)
+    }
+
+    fn fresh(&mut self) -> Type {
+        self.next_var += 1;
+        Type::Var(self.next_var)
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let s: Subst = scheme.vars.iter().map(|v| (*v, self.fresh())).collect();
+        apply(&s, &scheme.ty)
+    }
+
+    // Records that `ty` has to be one of the types of `class`; `term` is blamed otherwise.
+    fn constrain(&mut self, class: Class, ty: Type, term: &Term) {
+        self.constraints.push(Constraint { class, ty, term: term.clone(), binding: self.binding.clone() });
+    }
+
+    // Checks the pending constraints, defaulting the types that are still unknown.
+    fn resolve(&mut self, s: &mut Subst) -> Result<(), TypeError> {
+        for constraint in std::mem::take(&mut self.constraints) {
+            let ty = apply(s, &constraint.ty);
+            let ty = match ty {
+                Type::Var(v) => {
+                    let default = match constraint.class {
+                        Class::Sequence => Type::List(Box::new(self.fresh())),
+                        Class::Show | Class::Ord | Class::Num { float: false } => Type::Int,
+                        Class::Num { float: true } => Type::Float,
+                        Class::Eq => continue,
+                    };
+                    *s = compose(&HashMap::from([(v, default.clone())]), s);
+                    default
+                }
+                ty => ty,
+            };
+            if !constraint.class.admits(&ty) {
+                let kind = TypeErrorKind::Overload { expected: constraint.class.describe(), found: ty };
+                return Err(TypeError { kind: Box::new(kind), term: constraint.term, binding: constraint.binding });
+            }
+        }
+        Ok(())
+    }
+
+    // Quantifies a local binding like `generalize`, except for the type variables that pending
+    // constraints refer to: they are resolved with the enclosing top-level binding.
+    fn generalize_local(&self, s: &Subst, env: &HashMap<String, Scheme>, ty: &Type) -> Scheme {
+        let mut scheme = generalize(&apply_env(s, env), &apply(s, ty));
+        let mut constrained = Vec::new();
+        for constraint in &self.constraints {
+            type_vars(&apply(s, &constraint.ty), &mut constrained);
+        }
+        scheme.vars.retain(|v| !constrained.contains(v));
+        scheme
+    }
+
+    // Instantiates the type of a built-in, constraining it if the built-in is overloaded.
+    fn instantiate_builtin(&mut self, builtin: Builtin, term: &Term) -> Type {
+        let scheme = builtin_scheme(builtin);
+        let s: Subst = scheme.vars.iter().map(|v| (*v, self.fresh())).collect();
+        if let Some(class) = builtin_class(builtin) {
+            self.constrain(class, apply(&s, &Type::Var(0)), term);
+        }
+        apply(&s, &scheme.ty)
+    }
+
+    fn error(&self, kind: TypeErrorKind, term: &Term) -> TypeError {
+        TypeError { kind: Box::new(kind), term: term.clone(), binding: self.binding.clone() }
+    }
+
+    // Unifies `expected` with `found`; `term` is the subterm blamed on failure.
+    fn unify(&self, expected: &Type, found: &Type, term: &Term) -> Result<Subst, TypeError> {
+        unify(expected, found).map_err(|kind| {
+            let kind = match kind {
+                TypeErrorKind::Mismatch { .. } => TypeErrorKind::Mismatch {
+                    expected: expected.clone(),
+                    found: found.clone(),
+                },
+                kind => kind,
+            };
+            self.error(kind, term)
+        })
+    }
+
+    fn infer(&mut self, env: &HashMap<String, Scheme>, term: &Term) -> Result<(Subst, Type), TypeError> {
+        match term {
+            Term::Var(x) => match (env.get(x), Builtin::from_name(x)) {
+                (Some(scheme), _) => Ok((Subst::new(), self.instantiate(scheme))),
+                (None, Some(builtin)) => Ok((Subst::new(), self.instantiate_builtin(builtin, term))),
+                (None, None) => Err(self.error(TypeErrorKind::UnboundVariable(x.clone()), term)),
+            },
+
+            Term::Abs(param, body) => {
+                let param_ty = self.fresh();
+                let mut env = env.clone();
+                env.insert(param.clone(), Scheme { vars: vec![], ty: param_ty.clone() });
+                let (s, body_ty) = self.infer(&env, body)?;
+                Ok((s.clone(), fun(apply(&s, &param_ty), body_ty)))
+            }
+
+            Term::App(t1, t2) => {
+                let (s1, f_ty) = self.infer(env, t1)?;
+                let (s2, arg_ty) = self.infer(&apply_env(&s1, env), t2)?;
+                let f_ty = apply(&s2, &f_ty);
+                // Blame the argument if the function type is already known
+                let (s3, result_ty) = match &f_ty {
+                    Type::Fun(param_ty, ret_ty) => {
+                        let s3 = self.unify(param_ty, &arg_ty, t2)?;
+                        let result_ty = apply(&s3, ret_ty);
+                        (s3, result_ty)
+                    }
+                    _ => {
+                        let result_ty = self.fresh();
+                        let s3 = self.unify(&f_ty, &fun(arg_ty, result_ty.clone()), t1)?;
+                        let result_ty = apply(&s3, &result_ty);
+                        (s3, result_ty)
+                    }
+                };
+                Ok((compose(&s3, &compose(&s2, &s1)), result_ty))
+            }
+
+            // Each binding is generalised before the next one, like a top-level binding
+            Term::Let(bindings, body) => {
+                let mut env = env.clone();
+                let mut s = Subst::new();
+                for (x, t) in bindings {
+                    let (s1, ty) = self.infer(&apply_env(&s, &env), t)?;
+                    s = compose(&s1, &s);
+                    let scheme = self.generalize_local(&s, &env, &ty);
+                    env.insert(x.clone(), scheme);
+                }
+                let (s1, ty) = self.infer(&apply_env(&s, &env), body)?;
+                Ok((compose(&s1, &s), ty))
+            }
+
+            // The names are monomorphic within the group and generalised in the body
+            Term::LetRec(bindings, body) => {
+                let mut group_env = env.clone();
+                let mut assumed = Vec::new();
+                for (x, _) in bindings {
+                    let ty = self.fresh();
+                    group_env.insert(x.clone(), Scheme { vars: vec![], ty: ty.clone() });
+                    assumed.push(ty);
+                }
+                let mut s = Subst::new();
+                for ((_, t), assumed) in bindings.iter().zip(&assumed) {
+                    let (s1, ty) = self.infer(&apply_env(&s, &group_env), t)?;
+                    s = compose(&s1, &s);
+                    s = compose(&self.unify(&apply(&s, assumed), &ty, t)?, &s);
+                }
+                let mut body_env = env.clone();
+                for ((x, _), assumed) in bindings.iter().zip(&assumed) {
+                    body_env.insert(x.clone(), self.generalize_local(&s, env, assumed));
+                }
+                let (s1, ty) = self.infer(&apply_env(&s, &body_env), body)?;
+                Ok((compose(&s1, &s), ty))
+            }
+
+            Term::Int(_) => Ok((Subst::new(), Type::Int)),
+
+            Term::Float(_) => Ok((Subst::new(), Type::Float)),
+
+            Term::Bool(_) => Ok((Subst::new(), Type::Bool)),
+
+            Term::Str(_) => Ok((Subst::new(), Type::Str)),
+
+            Term::Char(_) => Ok((Subst::new(), Type::Char)),
+
+            // Applying the constructor to its fields, like nested applications
+            Term::Con(tag, _, fields) => {
+                let scheme = self.constructors.get(tag).cloned().ok_or_else(|| {
+                    self.error(TypeErrorKind::UnboundVariable(tag.clone()), term)
+                })?;
+                let ty = self.instantiate(&scheme);
+                self.infer_applied(env, ty, fields)
+            }
+
+            Term::Builtin(builtin, args) => {
+                let ty = self.instantiate_builtin(*builtin, term);
+                self.infer_applied(env, ty, args)
+            }
+
+            Term::AbsPattern(pattern, body) => {
+                let mut env = env.clone();
+                let (s1, param_ty) = self.infer_pattern(pattern, &mut env, term)?;
+                let (s2, body_ty) = self.infer(&apply_env(&s1, &env), body)?;
+                let s = compose(&s2, &s1);
+                Ok((s.clone(), fun(apply(&s, &param_ty), body_ty)))
+            }
+
+            Term::Tuple(items) => {
+                let mut s = Subst::new();
+                let mut types = Vec::new();
+                for item in items {
+                    let (s1, ty) = self.infer(&apply_env(&s, env), item)?;
+                    s = compose(&s1, &s);
+                    types.push(ty);
+                }
+                Ok((s.clone(), Type::Tuple(types.iter().map(|t| apply(&s, t)).collect())))
+            }
+
+            // The type of the tuple has to be known by now, as there is no type for all tuples
+            // with at least n components
+            Term::Proj(index, t) => {
+                let (s, ty) = self.infer(env, t)?;
+                match apply(&s, &ty) {
+                    Type::Tuple(items) if (1..=items.len()).contains(index) => Ok((s, items[index - 1].clone())),
+                    ty => Err(self.error(TypeErrorKind::Projection { index: *index, ty }, term)),
+                }
+            }
+
+            Term::Record(fields) => {
+                let mut s = Subst::new();
+                let mut types = Vec::new();
+                for (name, field) in fields {
+                    let (s1, ty) = self.infer(&apply_env(&s, env), field)?;
+                    s = compose(&s1, &s);
+                    types.push((name.clone(), ty));
+                }
+                let mut types: Vec<(String, Type)> = types.into_iter().map(|(name, t)| (name, apply(&s, &t))).collect();
+                types.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Ok((s, Type::Record(types)))
+            }
+
+            // Like projections, the type of the record has to be known by now
+            Term::Select(t, field) => {
+                let (s, ty) = self.infer(env, t)?;
+                let ty = apply(&s, &ty);
+                let field_ty = self.field_type(&ty, field, term)?;
+                Ok((s, field_ty))
+            }
+
+            Term::Update(t, updates) => {
+                let (mut s, ty) = self.infer(env, t)?;
+                for (field, value) in updates {
+                    let record_ty = apply(&s, &ty);
+                    let field_ty = self.field_type(&record_ty, field, term)?;
+                    let (s1, value_ty) = self.infer(&apply_env(&s, env), value)?;
+                    s = compose(&s1, &s);
+                    s = compose(&self.unify(&apply(&s, &field_ty), &value_ty, value)?, &s);
+                }
+                Ok((s.clone(), apply(&s, &ty)))
+            }
+
+            Term::List(items) => {
+                let mut s = Subst::new();
+                let item_ty = self.fresh();
+                for item in items {
+                    let (s1, ty) = self.infer(&apply_env(&s, env), item)?;
+                    s = compose(&s1, &s);
+                    s = compose(&self.unify(&apply(&s, &item_ty), &ty, item)?, &s);
+                }
+                Ok((s.clone(), Type::List(Box::new(apply(&s, &item_ty)))))
+            }
+
+            Term::If(cond, t1, t2) => {
+                let (s, cond_ty) = self.infer(env, cond)?;
+                let s = compose(&self.unify(&Type::Bool, &cond_ty, cond)?, &s);
+                let (s1, ty1) = self.infer(&apply_env(&s, env), t1)?;
+                let s = compose(&s1, &s);
+                let (s2, ty2) = self.infer(&apply_env(&s, env), t2)?;
+                let s = compose(&s2, &s);
+                let s3 = self.unify(&apply(&s, &ty1), &ty2, t2)?;
+                let s = compose(&s3, &s);
+                Ok((s.clone(), apply(&s, &ty2)))
+            }
+
+            // Every arm's pattern has the scrutinee's type and every body the result type
+            Term::Case(scrutinee, arms) => {
+                let (mut s, scrutinee_ty) = self.infer(env, scrutinee)?;
+                let result_ty = self.fresh();
+                for arm in arms {
+                    let mut arm_env = apply_env(&s, env);
+                    let (s1, pattern_ty) = self.infer_pattern(&arm.pattern, &mut arm_env, term)?;
+                    s = compose(&s1, &s);
+                    let arm_env = apply_env(&s1, &arm_env);
+                    s = compose(&self.unify(&apply(&s, &scrutinee_ty), &pattern_ty, scrutinee)?, &s);
+                    if let Some(guard) = &arm.guard {
+                        let (s2, guard_ty) = self.infer(&apply_env(&s, &arm_env), guard)?;
+                        s = compose(&s2, &s);
+                        s = compose(&self.unify(&Type::Bool, &guard_ty, guard)?, &s);
+                    }
+                    let (s3, body_ty) = self.infer(&apply_env(&s, &arm_env), &arm.body)?;
+                    s = compose(&s3, &s);
+                    s = compose(&self.unify(&apply(&s, &result_ty), &body_ty, &arm.body)?, &s);
+                }
+                Ok((s.clone(), apply(&s, &result_ty)))
+            }
+
+            Term::PrimOp(op, t1, t2) => {
+                let (s1, ty1) = self.infer(env, t1)?;
+                let (s2, ty2) = self.infer(&apply_env(&s1, env), t2)?;
+                let s = compose(&s2, &s1);
+                let ty1 = apply(&s, &ty1);
+                let arithmetic =
+                    matches!(op, PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div | PrimOp::Mod | PrimOp::Pow);
+                // An Int mixed with a Float is converted, so the operand types need not agree
+                if is_numeric(&ty1) && is_numeric(&ty2) && (arithmetic || ty1 != ty2) {
+                    let result_ty = match op {
+                        _ if !arithmetic => Type::Bool,
+                        _ if ty1 == Type::Float || ty2 == Type::Float => Type::Float,
+                        _ => Type::Int,
+                    };
+                    return Ok((s, result_ty));
+                }
+                // An operand of unknown type mixed with a number need only be a number too; mixed with
+                // an Int, the result has its type, which is Float if it turns out to be one
+                let compared =
+                    matches!(op, PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge | PrimOp::Eq | PrimOp::Ne);
+                if arithmetic || compared {
+                    if let (number, unknown @ Type::Var(_)) | (unknown @ Type::Var(_), number) = (&ty1, &ty2) {
+                        if is_numeric(number) {
+                            let float = *number == Type::Float;
+                            self.constrain(Class::Num { float }, unknown.clone(), term);
+                            let result_ty = match op {
+                                _ if compared => Type::Bool,
+                                _ if float => Type::Float,
+                                _ => unknown.clone(),
+                            };
+                            return Ok((s, result_ty));
+                        }
+                    }
+                }
+                let (left_ty, right_ty, result_ty) = match op {
+                    PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div | PrimOp::Mod | PrimOp::Pow => {
+                        match [&ty1, &ty2].into_iter().find(|ty| !matches!(ty, Type::Var(_))) {
+                            // An operand of another type does not match the number
+                            Some(ty) => {
+                                let number = if is_numeric(ty) { ty.clone() } else { Type::Int };
+                                (number.clone(), number.clone(), number)
+                            }
+                            None => {
+                                let a = self.fresh();
+                                self.constrain(Class::Num { float: false }, a.clone(), term);
+                                (a.clone(), a.clone(), a)
+                            }
+                        }
+                    }
+                    PrimOp::And | PrimOp::Or => (Type::Bool, Type::Bool, Type::Bool),
+                    PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge => {
+                        let a = self.fresh();
+                        self.constrain(Class::Ord, a.clone(), term);
+                        (a.clone(), a, Type::Bool)
+                    }
+                    PrimOp::Concat => {
+                        let a = self.fresh();
+                        self.constrain(Class::Sequence, a.clone(), term);
+                        (a.clone(), a.clone(), a)
+                    }
+                    PrimOp::Eq | PrimOp::Ne => {
+                        let a = self.fresh();
+                        self.constrain(Class::Eq, a.clone(), term);
+                        (a.clone(), a, Type::Bool)
+                    }
+                    PrimOp::Cons => {
+                        let a = self.fresh();
+                        let list = Type::List(Box::new(a.clone()));
+                        (a, list.clone(), list)
+                    }
+                };
+                let s = compose(&self.unify(&apply(&s, &left_ty), &ty1, t1)?, &s);
+                let s = compose(&self.unify(&apply(&s, &right_ty), &apply(&s, &ty2), t2)?, &s);
+                let result_ty = apply(&s, &result_ty);
+                Ok((s, result_ty))
+            }
+        }
+    }
+
+    // The type of `field` in the record type `ty`; `term` is blamed if there is no such field.
+    fn field_type(&self, ty: &Type, field: &str, term: &Term) -> Result<Type, TypeError> {
+        match ty {
+            Type::Record(fields) => fields.iter().find(|(name, _)| name == field).map(|(_, t)| t.clone()),
+            _ => None,
+        }
+        .ok_or_else(|| self.error(TypeErrorKind::Field { field: field.to_string(), ty: ty.clone() }, term))
+    }
+
+    // The type of a function of type `ty` applied to `args`, which are at most as many as the
+    // function takes.
+    fn infer_applied(
+        &mut self,
+        env: &HashMap<String, Scheme>,
+        mut ty: Type,
+        args: &[Term],
+    ) -> Result<(Subst, Type), TypeError> {
+        let mut s = Subst::new();
+        for arg in args {
+            let (s1, arg_ty) = self.infer(&apply_env(&s, env), arg)?;
+            s = compose(&s1, &s);
+            match apply(&s, &ty) {
+                Type::Fun(param_ty, result_ty) => {
+                    let s2 = self.unify(&param_ty, &arg_ty, arg)?;
+                    s = compose(&s2, &s);
+                    ty = *result_ty;
+                }
+                _ => unreachable!("applied to at most the number of parameters"),
+            }
+        }
+        Ok((s.clone(), apply(&s, &ty)))
+    }
+
+    // The type of the values matched by `pattern`. Pattern variables are added to `env` with
+    // monomorphic types; errors are blamed on `term`, the case expression.
+    fn infer_pattern(
+        &mut self,
+        pattern: &Pattern,
+        env: &mut HashMap<String, Scheme>,
+        term: &Term,
+    ) -> Result<(Subst, Type), TypeError> {
+        match pattern {
+            Pattern::Int(_) => Ok((Subst::new(), Type::Int)),
+            Pattern::Bool(_) => Ok((Subst::new(), Type::Bool)),
+            Pattern::Str(_) => Ok((Subst::new(), Type::Str)),
+            Pattern::Char(_) => Ok((Subst::new(), Type::Char)),
+            Pattern::Wildcard => Ok((Subst::new(), self.fresh())),
+            Pattern::Var(x) => {
+                let ty = self.fresh();
+                env.insert(x.clone(), Scheme { vars: vec![], ty: ty.clone() });
+                Ok((Subst::new(), ty))
+            }
+            Pattern::Tuple(args) => {
+                let mut s = Subst::new();
+                let mut types = Vec::new();
+                for arg in args {
+                    let (s1, ty) = self.infer_pattern(arg, env, term)?;
+                    s = compose(&s1, &s);
+                    types.push(ty);
+                }
+                Ok((s.clone(), Type::Tuple(types.iter().map(|t| apply(&s, t)).collect())))
+            }
+            Pattern::Con(tag, args) => {
+                let (Some(scheme), Some(&arity)) = (self.constructors.get(tag).cloned(), self.arities.get(tag)) else {
+                    return Err(self.error(TypeErrorKind::UnboundVariable(tag.clone()), term));
+                };
+                if arity != args.len() {
+                    let kind = TypeErrorKind::PatternArity {
+                        constructor: tag.clone(),
+                        expected: arity,
+                        found: args.len(),
+                    };
+                    return Err(self.error(kind, term));
+                }
+                let mut s = Subst::new();
+                let mut ty = self.instantiate(&scheme);
+                for arg in args {
+                    let (s1, arg_ty) = self.infer_pattern(arg, env, term)?;
+                    s = compose(&s1, &s);
+                    match apply(&s, &ty) {
+                        Type::Fun(param_ty, result_ty) => {
+                            s = compose(&self.unify(&param_ty, &arg_ty, term)?, &s);
+                            ty = *result_ty;
+                        }
+                        _ => unreachable!("the arity was checked above"),
+                    }
+                }
+                Ok((s.clone(), apply(&s, &ty)))
+            }
+        }
+    }
+}
+
+// Most general unifier of two types. Mismatches are reported by the caller with the full types.
+fn unify(t1: &Type, t2: &Type) -> Result<Subst, TypeErrorKind> {
+    match (t1, t2) {
+        (Type::Int, Type::Int) | (Type::Float, Type::Float) | (Type::Bool, Type::Bool) => Ok(Subst::new()),
+        (Type::Str, Type::Str) | (Type::Char, Type::Char) => Ok(Subst::new()),
+        (Type::Var(a), Type::Var(b)) if a == b => Ok(Subst::new()),
+        (Type::Var(v), t) | (t, Type::Var(v)) => {
+            let mut vars = Vec::new();
+            type_vars(t, &mut vars);
+            if vars.contains(v) {
+                Err(TypeErrorKind::InfiniteType { var: Type::Var(*v), ty: t.clone() })
+            } else {
+                Ok(HashMap::from([(*v, t.clone())]))
+            }
+        }
+        (Type::Fun(a1, r1), Type::Fun(a2, r2)) => {
+            let s1 = unify(a1, a2)?;
+            let s2 = unify(&apply(&s1, r1), &apply(&s1, r2))?;
+            Ok(compose(&s2, &s1))
+        }
+        (Type::Con(n1, args1), Type::Con(n2, args2)) if n1 == n2 && args1.len() == args2.len() => {
+            let mut s = Subst::new();
+            for (a1, a2) in args1.iter().zip(args2) {
+                s = compose(&unify(&apply(&s, a1), &apply(&s, a2))?, &s);
+            }
+            Ok(s)
+        }
+        (Type::List(t1), Type::List(t2)) => unify(t1, t2),
+        (Type::Tuple(items1), Type::Tuple(items2)) if items1.len() == items2.len() => {
+            let mut s = Subst::new();
+            for (t1, t2) in items1.iter().zip(items2) {
+                s = compose(&unify(&apply(&s, t1), &apply(&s, t2))?, &s);
+            }
+            Ok(s)
+        }
+        (Type::Record(fields1), Type::Record(fields2))
+            if fields1.iter().map(|(name, _)| name).eq(fields2.iter().map(|(name, _)| name)) =>
+        {
+            let mut s = Subst::new();
+            for ((_, t1), (_, t2)) in fields1.iter().zip(fields2) {
+                s = compose(&unify(&apply(&s, t1), &apply(&s, t2))?, &s);
+            }
+            Ok(s)
+        }
+        _ => Err(TypeErrorKind::Mismatch { expected: t1.clone(), found: t2.clone() }),
+    }
+}
+
+// Names type variables a, b, c, ... in order of first appearance across `types`.
+fn var_names(types: &[&Type]) -> HashMap<u32, String> {
+    let mut vars = Vec::new();
+    for ty in types {
+        type_vars(ty, &mut vars);
+    }
+    vars.into_iter()
+        .enumerate()
+        .map(|(n, v)| {
+            let letter = (b'a' + (n % 26) as u8) as char;
+            let name = if n < 26 { letter.to_string() } else { format!("{letter}{}", n / 26) };
+            (v, name)
+        })
+        .collect()
+}
+
+fn fmt_type(ty: &Type, names: &HashMap<u32, String>) -> String {
+    match ty {
+        Type::Int => "Int".to_string(),
+        Type::Float => "Float".to_string(),
+        Type::Bool => "Bool".to_string(),
+        Type::Str => "String".to_string(),
+        Type::Char => "Char".to_string(),
+        Type::Var(v) => names.get(v).cloned().unwrap_or_else(|| format!("t{v}")),
+        Type::Fun(t1, t2) => {
+            let param = fmt_type(t1, names);
+            match **t1 {
+                Type::Fun(..) => format!("({param}) -> {}", fmt_type(t2, names)),
+                _ => format!("{param} -> {}", fmt_type(t2, names)),
+            }
+        }
+        Type::Con(name, args) => {
+            let mut text = name.clone();
+            for arg in args {
+                match arg {
+                    Type::Fun(..) => text.push_str(&format!(" ({})", fmt_type(arg, names))),
+                    Type::Con(_, args) if !args.is_empty() => {
+                        text.push_str(&format!(" ({})", fmt_type(arg, names)))
+                    }
+                    _ => text.push_str(&format!(" {}", fmt_type(arg, names))),
+                }
+            }
+            text
+        }
+        Type::List(t) => format!("[{}]", fmt_type(t, names)),
+        Type::Tuple(items) => format!(
+            "({})",
+            items.iter().map(|t| fmt_type(t, names)).collect::<Vec<_>>().join(", ")
+        ),
+        Type::Record(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(name, t)| format!("{name}: {}", fmt_type(t, names)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", fmt_type(self, &var_names(&[self])))
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ty)
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "type error")?;
+        if let Some(binding) = &self.binding {
+            write!(f, " in `{binding}`")?;
+        }
+        match &*self.kind {
+            TypeErrorKind::Mismatch { expected, found } => {
+                let names = var_names(&[expected, found]);
+                write!(
+                    f,
+                    ": expected {}, found {}",
+                    fmt_type(expected, &names),
+                    fmt_type(found, &names)
+                )?;
+            }
+            TypeErrorKind::InfiniteType { var, ty } => {
+                let names = var_names(&[var, ty]);
+                write!(
+                    f,
+                    ": cannot construct the infinite type {} = {}",
+                    fmt_type(var, &names),
+                    fmt_type(ty, &names)
+                )?;
+            }
+            TypeErrorKind::UnboundVariable(x) => write!(f, ": unbound variable `{x}`")?,
+            TypeErrorKind::UnknownType(name) => write!(f, ": unknown type `{name}`")?,
+            TypeErrorKind::PatternArity { constructor, expected, found } => write!(
+                f,
+                ": constructor `{constructor}` has {expected} field(s), but the pattern has {found}"
+            )?,
+            TypeErrorKind::Projection { index, ty } => {
+                write!(f, ": cannot take component {index} of {ty}, it is not known to be a large enough tuple")?
+            }
+            TypeErrorKind::Field { field, ty } => {
+                write!(f, ": {ty} is not known to be a record with a field `{field}`")?
+            }
+            TypeErrorKind::Overload { expected, found } => write!(f, ": expected {expected}, found {found}")?,
+        }
+        write!(f, " in `{}`", self.term)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_expression, parse_main_program};
+
+    fn main_type(input: &str) -> Result<String, TypeError> {
+        let prog = parse_main_program(input).unwrap();
+        Ok(infer_program(&prog)?["main"].to_string())
+    }
+
+    #[test]
+    fn test_infer_expressions() {
+        let env = Env::new();
+        let cases = [
+            ("1 + 2 * 3", "Int"),
+            ("λx. x", "a -> a"),
+            ("λf g x. f (g x)", "(a -> b) -> (c -> a) -> c -> b"),
+            ("λx y. if x == y then x else y", "a -> a -> a"),
+            ("λn. n < 3", "Int -> Bool"),
+        ];
+        for (input, expected) in cases {
+            let term = parse_expression(input).unwrap();
+            assert_eq!(expected, infer(&env, &[], &term).unwrap().to_string(), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_infer_recursive_program() {
+        let input = r#"
+            fac = λn. if n == 0 then 1 else n * fac (n - 1);
+            main = fac;
+        "#;
+        assert_eq!("Int -> Int", main_type(input).unwrap());
+    }
+
+    #[test]
+    fn test_infer_mutually_recursive_program() {
+        let input = r#"
+            even = λn. if n == 0 then true else odd (n - 1);
+            odd = λn. if n == 0 then false else even (n - 1);
+            main = odd;
+        "#;
+        assert_eq!("Int -> Bool", main_type(input).unwrap());
+    }
+
+    #[test]
+    fn test_let_polymorphism() {
+        let input = r#"
+            id = λx. x;
+            main = if id true then id 1 else 2;
+        "#;
+        assert_eq!("Int", main_type(input).unwrap());
+    }
+
+    #[test]
+    fn test_infer_let() {
+        let env = Env::new();
+        let cases = [
+            ("let x = 1; y = x + 1 in x * y", "Int"),
+            // Local bindings are polymorphic
+            ("let id = λx. x in (id 1, id true)", "(Int, Bool)"),
+            ("letrec len = λxs. if null xs then 0 else 1 + len (tail xs) in (len [1], len [true])", "(Int, Int)"),
+            (
+                "letrec even = λn. if n == 0 then true else odd (n - 1); odd = λn. not (even n) in odd",
+                "Int -> Bool",
+            ),
+            // ... except for overloaded operations, which are resolved with the enclosing binding
+            ("let f = λx. show x in f true", "String"),
+            ("λy. let g = λx. (x, y) in g", "a -> b -> (b, a)"),
+        ];
+        for (input, expected) in cases {
+            let term = parse_expression(input).unwrap();
+            assert_eq!(expected, infer(&env, &[], &term).unwrap().to_string(), "{input}");
+        }
+        let err = main_type("main = letrec f = λx. f in f;").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::InfiniteType { .. }));
+        let err = main_type("main = let f = λx. show x in (f true, f 1);").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Bool, found: Type::Int }, *err.kind);
+    }
+
+    #[test]
+    fn test_infer_data_types() {
+        let input = r#"
+            data List = Nil | Cons a List;
+            data Pair a b = Pair a b;
+            singleton = λx. Cons x Nil;
+            main = Pair (singleton 1) (Cons true Nil);
+        "#;
+        assert_eq!("Pair (List Int) (List Bool)", main_type(input).unwrap());
+        let err = main_type("data List = Nil | Cons a List;\nmain = Cons 1 (Cons true Nil);").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::Mismatch { .. }));
+        let err = main_type("data T = T Tree;\nmain = T;").unwrap_err();
+        assert_eq!(TypeErrorKind::UnknownType("Tree".to_string()), *err.kind);
+    }
+
+    #[test]
+    fn test_infer_case() {
+        let input = r#"
+            data List = Nil | Cons a List;
+            length = λxs. case xs of Nil -> 0 | Cons _ rest -> 1 + length rest;
+            first = λd xs. case xs of Nil -> d | Cons x _ -> x;
+            main = first (length (Cons true Nil)) (Cons 2 Nil);
+        "#;
+        assert_eq!("Int", main_type(input).unwrap());
+        let program = parse_main_program(input).unwrap();
+        let types = infer_program(&program).unwrap();
+        assert_eq!("List a -> Int", types["length"].to_string());
+        assert_eq!("a -> List a -> a", types["first"].to_string());
+
+        assert_eq!("Int", main_type("main = case 1 of 0 -> 0 | n if n > 0 -> n | _ -> 0;").unwrap());
+        let err = main_type("main = case 1 of true -> 0 | _ -> 1;").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Int, found: Type::Bool }, *err.kind);
+        let err = main_type("data List = Nil | Cons a List;\nmain = case Nil of Cons x -> x;").unwrap_err();
+        assert_eq!(
+            TypeErrorKind::PatternArity { constructor: "Cons".to_string(), expected: 2, found: 1 },
+            *err.kind
+        );
+    }
+
+    #[test]
+    fn test_infer_lists() {
+        let input = r#"
+            sum = foldr (λx acc. x + acc) 0;
+            main = sum (map (λx. x * 2) (filter (λx. x > 1) (1 :: [2, 3])));
+        "#;
+        assert_eq!("Int", main_type(input).unwrap());
+        let program = parse_main_program(input).unwrap();
+        assert_eq!("[Int] -> Int", infer_program(&program).unwrap()["sum"].to_string());
+        assert_eq!("[[a]]", main_type("main = [[], []];").unwrap());
+        assert_eq!("(a -> b) -> a -> [b]", main_type("main = λf x. f x :: [];").unwrap());
+        let err = main_type("main = [1, true];").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Int, found: Type::Bool }, *err.kind);
+        assert!(main_type("main = head 1;").is_err());
+    }
+
+    #[test]
+    fn test_infer_tuples() {
+        assert_eq!("(Int, Bool, [a])", main_type("main = (1, true, []);").unwrap());
+        assert_eq!("(a, b) -> (b, a)", main_type("main = λp. (snd p, fst p);").unwrap());
+        assert_eq!("(a, b) -> a", main_type("main = λ(x, _). x;").unwrap());
+        assert_eq!("Bool", main_type("main = #3 (1, 2, true);").unwrap());
+        let input = "swap = λ(x, y). (y, x);\nmain = case swap (1, true) of (b, n) -> if b then n else 0;";
+        assert_eq!("Int", main_type(input).unwrap());
+        let err = main_type("main = λp. #1 p;").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::Projection { index: 1, .. }));
+        let err = main_type("main = #3 (1, 2);").unwrap_err();
+        assert_eq!("type error in `main`: cannot take component 3 of (Int, Int), it is not known to be a large enough tuple in `(#3 (1, 2))`", err.to_string());
+    }
+
+    #[test]
+    fn test_infer_records() {
+        assert_eq!("{age: Int, name: Bool}", main_type("main = { name = true, age = 1 };").unwrap());
+        let input = r#"
+            config = { verbose = false, level = 1 };
+            louder = { config | level = config.level + 1 };
+            main = if louder.verbose then 0 else louder.level;
+        "#;
+        assert_eq!("Int", main_type(input).unwrap());
+        let err = main_type("main = { x = 1 }.y;").unwrap_err();
+        assert_eq!(
+            TypeErrorKind::Field { field: "y".to_string(), ty: Type::Record(vec![("x".to_string(), Type::Int)]) },
+            *err.kind
+        );
+        let err = main_type("main = { { x = 1 } | x = true };").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Int, found: Type::Bool }, *err.kind);
+        assert!(main_type("main = [{ x = 1 }, { y = 1 }];").is_err());
+    }
+
+    #[test]
+    fn test_infer_operators() {
+        let env = Env::new();
+        let cases = [
+            ("λx y. x && not y || x != y", "Bool -> Bool -> Bool"),
+            ("λn. n % 2 == 0 && n ^ 2 >= 4", "Int -> Bool"),
+            ("λs. s <= \"m\"", "String -> Bool"),
+        ];
+        for (input, expected) in cases {
+            let term = parse_expression(input).unwrap();
+            assert_eq!(expected, infer(&env, &[], &term).unwrap().to_string(), "{input}");
+        }
+        let err = main_type("main = 1 && true;").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Bool, found: Type::Int }, *err.kind);
+        // Functions cannot be compared
+        let err = main_type("main = (λx. x) == (λx. x);").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::Overload { expected: "a type without functions", .. }));
+        let err = main_type("main = [λx. x + 1] != [];").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::Overload { found: Type::List(_), .. }));
+        assert!(main_type("main = ([1], (true, 'a')) == ([], (false, 'b'));").is_ok());
+    }
+
+    #[test]
+    fn test_infer_floats() {
+        let env = Env::new();
+        let cases = [
+            ("1.5 * 2", "Float"),
+            ("λx. x / 2.0", "Float -> Float"),
+            ("λx. x + 1", "Int -> Int"),
+            ("λx y. x - y", "Int -> Int -> Int"),
+            ("λn. round (toFloat n * 0.5) < n", "Int -> Bool"),
+            ("λx. show x ++ show 1.0", "Int -> String"),
+            ("1 == 1.0 && 2 >= 0.5", "Bool"),
+            // An Int passed where it is mixed with a Float is converted, as in evaluation
+            ("(λx. x + 1.5) 2", "Float"),
+            ("(λx. 2 * x) 1.5", "Float"),
+            ("(λx y. x < 1 && y == 0.5) 2.5 1", "Bool"),
+            ("let f = λx. x + x in f 1.5", "Float"),
+        ];
+        for (input, expected) in cases {
+            let term = parse_expression(input).unwrap();
+            assert_eq!(expected, infer(&env, &[], &term).unwrap().to_string(), "{input}");
+        }
+        let err = main_type("main = floor 1;").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Float, found: Type::Int }, *err.kind);
+        let err = main_type("main = 1.5 + true;").unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Float, found: Type::Bool }, *err.kind);
+    }
+
+    #[test]
+    fn test_infer_strings() {
+        let env = Env::new();
+        let cases = [
+            (r#""a" ++ show 1"#, "String"),
+            ("λs. substring s 0 (length s - 1)", "String -> String"),
+            ("λc. c < 'z'", "Char -> Bool"),
+            (r#"λs. if s < "m" then length s else 0"#, "String -> Int"),
+            ("λxs. length (xs ++ [true])", "[Bool] -> Int"),
+            // Unknown types default to lists and integers
+            ("λxs ys. xs ++ ys", "[a] -> [a] -> [a]"),
+            ("λx. show x", "Int -> String"),
+        ];
+        for (input, expected) in cases {
+            let term = parse_expression(input).unwrap();
+            assert_eq!(expected, infer(&env, &[], &term).unwrap().to_string(), "{input}");
+        }
+        let err = main_type("main = show [1];").unwrap_err();
+        assert_eq!(
+            TypeErrorKind::Overload { expected: "Int, Float, Bool, Char or String", found: Type::List(Box::new(Type::Int)) },
+            *err.kind
+        );
+        assert_eq!("type error in `main`: expected Int, Float, Bool, Char or String, found [Int] in `show`", err.to_string());
+        let err = main_type("main = true < false;").unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::Overload { found: Type::Bool, .. }));
+        let err = main_type(r#"main = "a" ++ 'b';"#).unwrap_err();
+        assert_eq!(TypeErrorKind::Mismatch { expected: Type::Str, found: Type::Char }, *err.kind);
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let err = main_type("main = 1 + true;").unwrap_err();
+        assert_eq!(
+            TypeErrorKind::Mismatch { expected: Type::Int, found: Type::Bool },
+            *err.kind
+        );
+        assert_eq!(b(true), err.term);
+        assert_eq!(Some("main".to_string()), err.binding);
+
+        let err = main_type("main = if 3 then 1 else 2;").unwrap_err();
+        assert_eq!(
+            TypeErrorKind::Mismatch { expected: Type::Bool, found: Type::Int },
+            *err.kind
+        );
+        assert_eq!(i(3), err.term);
+        assert_eq!(
+            "type error in `main`: expected Bool, found Int in `3`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_infinite_type() {
+        let err = infer(&Env::new(), &[], &abs("x", app(var("x"), var("x")))).unwrap_err();
+        assert!(matches!(*err.kind, TypeErrorKind::InfiniteType { .. }));
+    }
+}
@@ -0,0 +1,925 @@
+use crate::bignum::BigInt;
+use crate::eval::{
+    compare, float_arithmetic, float_to_int, free_variables, int_arithmetic, substitute_all, to_index, EvalError,
+};
+use crate::term::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The result of environment-based evaluation.
+#[derive(Debug, Clone)]
+pub enum Value<'a> {
+    Int(BigInt),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Char(char),
+    /// A function together with the local bindings in scope where it was created.
+    Closure {
+        param: &'a str,
+        body: &'a Term,
+        env: Scope<'a>,
+    },
+    /// A data constructor with the fields applied so far.
+    Con {
+        tag: &'a str,
+        arity: usize,
+        fields: Vec<Value<'a>>,
+    },
+    List(List<'a>),
+    Tuple(Vec<Value<'a>>),
+    /// A record with its fields sorted by name.
+    Record(Vec<(&'a str, Value<'a>)>),
+    /// A function with a pattern parameter together with the local bindings in scope.
+    PatternClosure {
+        pattern: &'a Pattern,
+        body: &'a Term,
+        env: Scope<'a>,
+    },
+    /// A built-in function with the arguments applied so far.
+    Builtin {
+        builtin: Builtin,
+        args: Vec<Value<'a>>,
+    },
+}
+
+/// The items of a list value: a persistent linked list, so consing an item onto a list and
+/// taking its tail are O(1) and lists share their tails.
+#[derive(Debug, Clone, Default)]
+pub struct List<'a>(Option<Rc<Node<'a>>>);
+
+#[derive(Debug)]
+struct Node<'a> {
+    head: Value<'a>,
+    tail: List<'a>,
+}
+
+impl<'a> List<'a> {
+    /// Returns the list with `head` in front of `tail`.
+    pub fn cons(head: Value<'a>, tail: List<'a>) -> List<'a> {
+        List(Some(Rc::new(Node { head, tail })))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// The first item and the rest of the list, unless it is empty.
+    pub fn uncons(&self) -> Option<(&Value<'a>, &List<'a>)> {
+        self.0.as_deref().map(|node| (&node.head, &node.tail))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Value<'a>> + '_ {
+        let mut list = self;
+        std::iter::from_fn(move || {
+            let (head, tail) = list.uncons()?;
+            list = tail;
+            Some(head)
+        })
+    }
+
+    /// Returns the items of this list followed by `other`, which is shared rather than copied.
+    pub fn append(&self, other: List<'a>) -> List<'a> {
+        let items: Vec<&Value<'a>> = self.iter().collect();
+        items.into_iter().rev().fold(other, |tail, head| List::cons(head.clone(), tail))
+    }
+}
+
+impl<'a> FromIterator<Value<'a>> for List<'a> {
+    fn from_iter<I: IntoIterator<Item = Value<'a>>>(items: I) -> Self {
+        let items: Vec<Value<'a>> = items.into_iter().collect();
+        items.into_iter().rev().fold(List::default(), |tail, head| List::cons(head, tail))
+    }
+}
+
+// Drops the nodes one at a time, so that dropping a long list does not overflow the stack.
+impl Drop for List<'_> {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(node) = next {
+            next = Rc::try_unwrap(node).ok().and_then(|mut node| node.tail.0.take());
+        }
+    }
+}
+
+/// Local variable bindings: a persistent linked list, so extending a scope is O(1)
+/// and closures can share the bindings they capture.
+#[derive(Debug, Clone, Default)]
+pub struct Scope<'a>(Option<Rc<Binding<'a>>>);
+
+#[derive(Debug)]
+struct Binding<'a> {
+    frame: Frame<'a>,
+    next: Scope<'a>,
+}
+
+#[derive(Debug)]
+enum Frame<'a> {
+    Value(&'a str, Value<'a>),
+    // The bindings of a `letrec`. They are evaluated when they are looked up, in the scope
+    // that starts with this frame, so they can refer to each other.
+    Recursive(&'a [(String, Term)]),
+}
+
+/// What a name is bound to in a scope.
+#[derive(Debug, Clone, Copy)]
+pub enum Lookup<'s, 'a> {
+    Value(&'s Value<'a>),
+    /// A name bound by the `letrec` group `bindings`; `term` is evaluated in `scope`.
+    Recursive {
+        bindings: &'a [(String, Term)],
+        term: &'a Term,
+        scope: &'s Scope<'a>,
+    },
+}
+
+impl<'a> Scope<'a> {
+    /// Returns a new scope that binds `name` to `value` on top of this one.
+    pub fn bind(&self, name: &'a str, value: Value<'a>) -> Scope<'a> {
+        Scope(Some(Rc::new(Binding {
+            frame: Frame::Value(name, value),
+            next: self.clone(),
+        })))
+    }
+
+    /// Returns a new scope that binds the names of a `letrec` group on top of this one.
+    pub fn bind_recursive(&self, bindings: &'a [(String, Term)]) -> Scope<'a> {
+        Scope(Some(Rc::new(Binding {
+            frame: Frame::Recursive(bindings),
+            next: self.clone(),
+        })))
+    }
+
+    /// Looks up the innermost binding of `name`.
+    pub fn lookup(&self, name: &str) -> Option<Lookup<'_, 'a>> {
+        let mut scope = self;
+        while let Some(binding) = &scope.0 {
+            match &binding.frame {
+                Frame::Value(x, value) if *x == name => return Some(Lookup::Value(value)),
+                Frame::Recursive(bindings) => {
+                    if let Some((_, term)) = bindings.iter().find(|(x, _)| x == name) {
+                        return Some(Lookup::Recursive { bindings, term, scope });
+                    }
+                }
+                Frame::Value(..) => {}
+            }
+            scope = &binding.next;
+        }
+        None
+    }
+}
+
+/// Evaluates a term to a value, looking variables up in the local scope and then in `env`
+/// instead of substituting arguments into function bodies.
+///
+/// Evaluation is strict in the sense of `EvalOptions::strict`: terms that cannot be reduced
+/// are reported as errors. Top-level bindings are evaluated at most once.
+pub fn eval_value<'a>(env: &'a Env, term: &'a Term) -> Result<Value<'a>, EvalError> {
+    Evaluator {
+        globals: env,
+        cache: HashMap::new(),
+        pending: Vec::new(),
+    }
+    .eval(&Scope::default(), term)
+}
+
+/// Converts a value back into a term. A closure becomes an abstraction whose body has the
+/// captured bindings substituted in.
+pub fn readback(value: &Value) -> Term {
+    match value {
+        Value::Int(n) => Term::Int(n.clone()),
+        Value::Float(x) => Term::Float(*x),
+        Value::Bool(v) => b(*v),
+        Value::Str(s) => string(s),
+        Value::Char(c) => character(*c),
+        Value::Closure { param, body, env } => abs(param, close(body, &[param.to_string()], env)),
+        Value::Con { tag, arity, fields } => con(tag, *arity, fields.iter().map(readback).collect()),
+        Value::List(items) => list(items.iter().map(readback).collect()),
+        Value::Tuple(items) => tuple(items.iter().map(readback).collect()),
+        Value::Record(fields) => Term::Record(
+            fields.iter().map(|(name, v)| (name.to_string(), readback(v))).collect(),
+        ),
+        Value::PatternClosure { pattern, body, env } => {
+            abs_pattern((*pattern).clone(), close(body, &pattern.binders(), env))
+        }
+        Value::Builtin { builtin, args } => Term::Builtin(*builtin, args.iter().map(readback).collect()),
+    }
+}
+
+// Substitutes the local bindings in `env` for the free variables of `term` other than `bound`,
+// all at once. A name bound by `letrec` is replaced by its group: f becomes letrec ... in f
+fn close(term: &Term, bound: &[String], env: &Scope) -> Term {
+    let mut free: Vec<String> = free_variables(term).into_iter().filter(|x| !bound.contains(x)).collect();
+    free.sort();
+    let bindings: Vec<(String, Term)> = free
+        .into_iter()
+        .filter_map(|x| {
+            let closed = match env.lookup(&x)? {
+                Lookup::Value(v) => readback(v),
+                Lookup::Recursive { bindings, scope, .. } => {
+                    close(&Term::LetRec(bindings.to_vec(), Box::new(var(&x))), &[], scope)
+                }
+            };
+            Some((x, closed))
+        })
+        .collect();
+    substitute_all(term, &bindings)
+}
+
+// Matches a value against a pattern, binding the pattern variables in `scope`.
+pub(crate) fn match_value<'a>(pattern: &'a Pattern, value: &Value<'a>, scope: &mut Scope<'a>) -> bool {
+    match (pattern, value) {
+        (Pattern::Var(x), _) => {
+            *scope = scope.bind(x, value.clone());
+            true
+        }
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Int(n1), Value::Int(n2)) => n1 == n2,
+        (Pattern::Bool(b1), Value::Bool(b2)) => b1 == b2,
+        (Pattern::Str(s1), Value::Str(s2)) => s1 == s2,
+        (Pattern::Char(c1), Value::Char(c2)) => c1 == c2,
+        (Pattern::Con(tag, args), Value::Con { tag: t, fields, .. }) if tag == t && args.len() == fields.len() => {
+            args.iter().zip(fields).all(|(arg, field)| match_value(arg, field, scope))
+        }
+        (Pattern::Tuple(args), Value::Tuple(fields)) if args.len() == fields.len() => {
+            args.iter().zip(fields).all(|(arg, field)| match_value(arg, field, scope))
+        }
+        _ => false,
+    }
+}
+
+/// Applies a primitive operation to evaluated operands, with the same results and errors as
+/// `eval::apply_primop` in strict mode. `&&` and `||` get both operands here; the caller decides
+/// whether the right one is needed.
+pub(crate) fn apply_value_primop<'a>(op: PrimOp, l: Value<'a>, r: Value<'a>) -> Result<Value<'a>, EvalError> {
+    let redex = |l: &Value, r: &Value| primop(op, readback(l), readback(r));
+    let mismatch = |l: &Value, r: &Value| EvalError::TypeMismatch { op, left: readback(l), right: readback(r) };
+    let result = match op {
+        PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div | PrimOp::Mod | PrimOp::Pow => match (&l, &r) {
+            (Value::Int(n1), Value::Int(n2)) => {
+                if matches!(op, PrimOp::Div | PrimOp::Mod) && n2.is_zero() {
+                    return Err(EvalError::DivisionByZero(redex(&l, &r)));
+                }
+                if op == PrimOp::Pow && n2.is_negative() {
+                    return Err(EvalError::NegativeExponent(redex(&l, &r)));
+                }
+                Value::Int(int_arithmetic(op, n1, n2).ok_or_else(|| EvalError::ArithmeticOverflow(redex(&l, &r)))?)
+            }
+            _ => match (as_float(&l), as_float(&r)) {
+                (Some(x), Some(y)) => Value::Float(float_arithmetic(op, x, y)),
+                _ => return Err(mismatch(&l, &r)),
+            },
+        },
+
+        PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge => {
+            // None if a float operand is NaN, which makes every comparison false
+            let ordering = match (&l, &r) {
+                (Value::Int(n1), Value::Int(n2)) => Some(n1.cmp(n2)),
+                (Value::Str(s1), Value::Str(s2)) => Some(s1.cmp(s2)),
+                (Value::Char(c1), Value::Char(c2)) => Some(c1.cmp(c2)),
+                _ => match (as_float(&l), as_float(&r)) {
+                    (Some(x), Some(y)) => x.partial_cmp(&y),
+                    _ => return Err(mismatch(&l, &r)),
+                },
+            };
+            Value::Bool(ordering.is_some_and(|ordering| compare(op, ordering)))
+        }
+
+        PrimOp::And | PrimOp::Or => match (&l, &r) {
+            (Value::Bool(b1), Value::Bool(b2)) if op == PrimOp::And => Value::Bool(*b1 && *b2),
+            (Value::Bool(b1), Value::Bool(b2)) => Value::Bool(*b1 || *b2),
+            _ => return Err(mismatch(&l, &r)),
+        },
+
+        PrimOp::Concat => match (l, r) {
+            (Value::Str(s1), Value::Str(s2)) => Value::Str(s1 + &s2),
+            (Value::List(xs), Value::List(ys)) => Value::List(xs.append(ys)),
+            (l, r) => return Err(mismatch(&l, &r)),
+        },
+
+        PrimOp::Cons => match r {
+            Value::List(items) => Value::List(List::cons(l, items)),
+            r => return Err(mismatch(&l, &r)),
+        },
+
+        PrimOp::Eq | PrimOp::Ne => match equal(&l, &r) {
+            Some(equal) => Value::Bool(equal == (op == PrimOp::Eq)),
+            None => return Err(mismatch(&l, &r)),
+        },
+    };
+    Ok(result)
+}
+
+// A number as a float: integers are converted when they meet a float.
+fn as_float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(n.to_f64()),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+// Whether `==` holds for two values. Numbers are compared after converting an integer that
+// meets a float, and data structurally. None if the values cannot be compared.
+fn equal(l: &Value, r: &Value) -> Option<bool> {
+    let compound = |v: &Value| matches!(v, Value::Con { .. } | Value::List(_) | Value::Tuple(_) | Value::Record(_));
+    match (l, r) {
+        (Value::Int(n1), Value::Int(n2)) => Some(n1 == n2),
+        (Value::Bool(b1), Value::Bool(b2)) => Some(b1 == b2),
+        (Value::Str(s1), Value::Str(s2)) => Some(s1 == s2),
+        (Value::Char(c1), Value::Char(c2)) => Some(c1 == c2),
+        _ => match (as_float(l), as_float(r)) {
+            (Some(x), Some(y)) => Some(x == y),
+            _ if compound(l) && compound(r) && is_data(l) && is_data(r) => Some(same_data(l, r)),
+            _ => None,
+        },
+    }
+}
+
+// Fully applied constructors, lists, tuples and records whose elements are data again can be
+// compared structurally.
+fn is_data(value: &Value) -> bool {
+    match value {
+        Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Str(_) | Value::Char(_) => true,
+        Value::Con { arity, fields, .. } => fields.len() == *arity && fields.iter().all(is_data),
+        Value::List(items) => items.iter().all(is_data),
+        Value::Tuple(items) => items.iter().all(is_data),
+        Value::Record(fields) => fields.iter().all(|(_, v)| is_data(v)),
+        _ => false,
+    }
+}
+
+// Structural equality of data, under which an integer differs from every float.
+fn same_data(l: &Value, r: &Value) -> bool {
+    let all_same = |xs: &[Value], ys: &[Value]| xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same_data(x, y));
+    match (l, r) {
+        (Value::Int(n1), Value::Int(n2)) => n1 == n2,
+        (Value::Float(x), Value::Float(y)) => x == y,
+        (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+        (Value::Str(s1), Value::Str(s2)) => s1 == s2,
+        (Value::Char(c1), Value::Char(c2)) => c1 == c2,
+        (Value::Con { tag: t1, arity: a1, fields: f1 }, Value::Con { tag: t2, arity: a2, fields: f2 }) => {
+            t1 == t2 && a1 == a2 && all_same(f1, f2)
+        }
+        (Value::List(xs), Value::List(ys)) => {
+            xs.len() == ys.len() && xs.iter().zip(ys.iter()).all(|(x, y)| same_data(x, y))
+        }
+        (Value::Tuple(xs), Value::Tuple(ys)) => all_same(xs, ys),
+        (Value::Record(f1), Value::Record(f2)) => {
+            f1.len() == f2.len() && f1.iter().zip(f2).all(|((n1, v1), (n2, v2))| n1 == n2 && same_data(v1, v2))
+        }
+        _ => false,
+    }
+}
+
+/// Applies a fully applied built-in function other than `map`, `filter` and `foldr` with
+/// a list, which need to call their function argument.
+pub(crate) fn apply_builtin(builtin: Builtin, mut args: Vec<Value<'_>>) -> Result<Value<'_>, EvalError> {
+    let redex = |args: &[Value]| Term::Builtin(builtin, args.iter().map(readback).collect());
+    match (builtin, args.as_slice()) {
+        (Builtin::Fst, [Value::Tuple(items)]) if items.len() == 2 => return Ok(items[0].clone()),
+        (Builtin::Snd, [Value::Tuple(items)]) if items.len() == 2 => return Ok(items[1].clone()),
+        (Builtin::Not, [Value::Bool(v)]) => return Ok(Value::Bool(!v)),
+        (Builtin::Length, [Value::Str(s)]) => return Ok(Value::Int(BigInt::from(s.chars().count() as i64))),
+        (Builtin::Substring, [Value::Str(s), Value::Int(start), Value::Int(len)]) => {
+            let chars: Vec<char> = s.chars().collect();
+            return match (to_index(start), to_index(len)) {
+                (Some(start), Some(len)) if start.checked_add(len).is_some_and(|end| end <= chars.len()) => {
+                    Ok(Value::Str(chars[start..start + len].iter().collect()))
+                }
+                _ => Err(EvalError::IndexOutOfRange(redex(&args))),
+            };
+        }
+        (Builtin::ToFloat, [Value::Int(n)]) => return Ok(Value::Float(n.to_f64())),
+        (Builtin::Floor | Builtin::Round | Builtin::Truncate, [Value::Float(x)]) => {
+            return float_to_int(builtin, *x)
+                .map(Value::Int)
+                .ok_or_else(|| EvalError::InvalidArgument(redex(&args)))
+        }
+        (Builtin::Show, [v @ (Value::Int(_) | Value::Float(_) | Value::Bool(_) | Value::Str(_) | Value::Char(_))]) => {
+            return Ok(Value::Str(readback(v).to_string()))
+        }
+        _ => {}
+    }
+    // The other built-ins take a list as their last argument
+    let items = match args.pop() {
+        Some(Value::List(items)) => items,
+        Some(v) => {
+            args.push(v);
+            return Err(EvalError::InvalidArgument(redex(&args)));
+        }
+        None => unreachable!("built-ins take at least one argument"),
+    };
+    match (builtin, args.as_slice()) {
+        (Builtin::Head | Builtin::Tail, _) if items.is_empty() => {
+            Err(EvalError::EmptyList(redex(&[Value::List(items)])))
+        }
+        (Builtin::Head, _) => Ok(items.uncons().unwrap().0.clone()),
+        (Builtin::Tail, _) => Ok(Value::List(items.uncons().unwrap().1.clone())),
+        (Builtin::Null, _) => Ok(Value::Bool(items.is_empty())),
+        (Builtin::Length, _) => Ok(Value::Int(BigInt::from(items.len() as i64))),
+        (Builtin::Append, [Value::List(xs)]) => Ok(Value::List(xs.append(items))),
+        _ => {
+            args.push(Value::List(items));
+            Err(EvalError::InvalidArgument(redex(&args)))
+        }
+    }
+}
+
+struct Evaluator<'a> {
+    globals: &'a Env,
+    cache: HashMap<&'a str, Value<'a>>,
+    // The top-level bindings being evaluated, innermost last
+    pending: Vec<&'a str>,
+}
+
+impl<'a> Evaluator<'a> {
+    fn eval(&mut self, scope: &Scope<'a>, term: &'a Term) -> Result<Value<'a>, EvalError> {
+        match term {
+            Term::Var(x) => match scope.lookup(x) {
+                Some(Lookup::Value(v)) => Ok(v.clone()),
+                Some(Lookup::Recursive { term, scope, .. }) => self.eval(scope, term),
+                None => self.global(x),
+            },
+
+            Term::Let(bindings, body) => {
+                let mut scope = scope.clone();
+                for (x, t) in bindings {
+                    let v = self.eval(&scope, t)?;
+                    scope = scope.bind(x, v);
+                }
+                self.eval(&scope, body)
+            }
+
+            Term::LetRec(bindings, body) => self.eval(&scope.bind_recursive(bindings), body),
+
+            Term::Abs(param, body) => Ok(Value::Closure {
+                param,
+                body,
+                env: scope.clone(),
+            }),
+
+            Term::App(t1, t2) => {
+                let f = self.eval(scope, t1)?;
+                let arg = self.eval(scope, t2)?;
+                self.apply(f, arg)
+            }
+
+            Term::Int(n) => Ok(Value::Int(n.clone())),
+
+            Term::Float(x) => Ok(Value::Float(*x)),
+
+            Term::Bool(v) => Ok(Value::Bool(*v)),
+
+            Term::Str(s) => Ok(Value::Str(s.clone())),
+
+            Term::Char(c) => Ok(Value::Char(*c)),
+
+            Term::Con(tag, arity, fields) => Ok(Value::Con {
+                tag,
+                arity: *arity,
+                fields: fields.iter().map(|t| self.eval(scope, t)).collect::<Result<_, _>>()?,
+            }),
+
+            Term::If(cond, t1, t2) => match self.eval(scope, cond)? {
+                Value::Bool(true) => self.eval(scope, t1),
+                Value::Bool(false) => self.eval(scope, t2),
+                c => Err(EvalError::NonBooleanCondition(readback(&c))),
+            },
+
+            Term::List(items) => Ok(Value::List(
+                items.iter().map(|t| self.eval(scope, t)).collect::<Result<_, _>>()?,
+            )),
+
+            Term::Builtin(builtin, args) => {
+                let args = args.iter().map(|t| self.eval(scope, t)).collect::<Result<_, _>>()?;
+                self.call_builtin(*builtin, args)
+            }
+
+            Term::Tuple(items) => Ok(Value::Tuple(
+                items.iter().map(|t| self.eval(scope, t)).collect::<Result<_, _>>()?,
+            )),
+
+            Term::Proj(index, t) => match self.eval(scope, t)? {
+                Value::Tuple(mut items) if (1..=items.len()).contains(index) => Ok(items.swap_remove(index - 1)),
+                v => Err(EvalError::InvalidArgument(proj(*index, readback(&v)))),
+            },
+
+            Term::Record(fields) => {
+                let mut fields = fields
+                    .iter()
+                    .map(|(name, t)| Ok((name.as_str(), self.eval(scope, t)?)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                fields.sort_by_key(|(name, _)| *name);
+                Ok(Value::Record(fields))
+            }
+
+            Term::Select(t, field) => match self.eval(scope, t)? {
+                Value::Record(fields) => match fields.iter().position(|(name, _)| name == field) {
+                    Some(n) => Ok(fields[n].1.clone()),
+                    None => Err(EvalError::MissingField {
+                        field: field.clone(),
+                        record: readback(&Value::Record(fields)),
+                    }),
+                },
+                v => Err(EvalError::InvalidArgument(select(readback(&v), field))),
+            },
+
+            Term::Update(t, updates) => match self.eval(scope, t)? {
+                Value::Record(mut fields) => {
+                    for (name, t) in updates {
+                        let Some(n) = fields.iter().position(|(field, _)| field == name) else {
+                            return Err(EvalError::MissingField {
+                                field: name.clone(),
+                                record: readback(&Value::Record(fields)),
+                            });
+                        };
+                        fields[n].1 = self.eval(scope, t)?;
+                    }
+                    Ok(Value::Record(fields))
+                }
+                v => Err(EvalError::InvalidArgument(Term::Update(Box::new(readback(&v)), updates.clone()))),
+            },
+
+            Term::AbsPattern(pattern, body) => Ok(Value::PatternClosure {
+                pattern,
+                body,
+                env: scope.clone(),
+            }),
+
+            Term::Case(scrutinee, arms) => {
+                let v = self.eval(scope, scrutinee)?;
+                for arm in arms {
+                    let mut scope = scope.clone();
+                    if !match_value(&arm.pattern, &v, &mut scope) {
+                        continue;
+                    }
+                    if let Some(guard) = &arm.guard {
+                        match self.eval(&scope, guard)? {
+                            Value::Bool(true) => {}
+                            Value::Bool(false) => continue,
+                            c => return Err(EvalError::NonBooleanCondition(readback(&c))),
+                        }
+                    }
+                    return self.eval(&scope, &arm.body);
+                }
+                Err(EvalError::MatchFailure(readback(&v)))
+            }
+
+            // The right operand is only evaluated if the left one does not decide the result
+            Term::PrimOp(op @ (PrimOp::And | PrimOp::Or), t1, t2) => match self.eval(scope, t1)? {
+                Value::Bool(v) if v == (*op == PrimOp::Or) => Ok(Value::Bool(v)),
+                l @ Value::Bool(_) => apply_value_primop(*op, l, self.eval(scope, t2)?),
+                l => Err(EvalError::TypeMismatch { op: *op, left: readback(&l), right: (**t2).clone() }),
+            },
+
+            Term::PrimOp(op, t1, t2) => {
+                let l = self.eval(scope, t1)?;
+                let r = self.eval(scope, t2)?;
+                apply_value_primop(*op, l, r)
+            }
+        }
+    }
+
+    fn apply(&mut self, f: Value<'a>, arg: Value<'a>) -> Result<Value<'a>, EvalError> {
+        match f {
+            Value::Closure { param, body, env } => self.eval(&env.bind(param, arg), body),
+            Value::Con { tag, arity, mut fields } if fields.len() < arity => {
+                fields.push(arg);
+                Ok(Value::Con { tag, arity, fields })
+            }
+            Value::PatternClosure { pattern, body, env } => {
+                let mut scope = env;
+                if match_value(pattern, &arg, &mut scope) {
+                    self.eval(&scope, body)
+                } else {
+                    Err(EvalError::MatchFailure(readback(&arg)))
+                }
+            }
+            Value::Builtin { builtin, mut args } => {
+                args.push(arg);
+                self.call_builtin(builtin, args)
+            }
+            f => Err(EvalError::NotAFunction(readback(&f))),
+        }
+    }
+
+    // Applies a built-in function once all of its arguments are present.
+    fn call_builtin(&mut self, builtin: Builtin, args: Vec<Value<'a>>) -> Result<Value<'a>, EvalError> {
+        if args.len() < builtin.arity() {
+            return Ok(Value::Builtin { builtin, args });
+        }
+        match (builtin, args.as_slice()) {
+            (Builtin::Map, [f, Value::List(items)]) => Ok(Value::List(
+                items
+                    .iter()
+                    .map(|x| self.apply(f.clone(), x.clone()))
+                    .collect::<Result<_, _>>()?,
+            )),
+            (Builtin::Filter, [p, Value::List(items)]) => {
+                let mut kept = Vec::new();
+                for x in items.iter() {
+                    match self.apply(p.clone(), x.clone())? {
+                        Value::Bool(true) => kept.push(x.clone()),
+                        Value::Bool(false) => {}
+                        c => return Err(EvalError::NonBooleanCondition(readback(&c))),
+                    }
+                }
+                Ok(Value::List(kept.into_iter().collect()))
+            }
+            (Builtin::Foldr, [f, z, Value::List(items)]) => {
+                let items: Vec<&Value<'a>> = items.iter().collect();
+                items.into_iter().rev().try_fold(z.clone(), |acc, x| {
+                    let g = self.apply(f.clone(), x.clone())?;
+                    self.apply(g, acc)
+                })
+            }
+            _ => apply_builtin(builtin, args),
+        }
+    }
+
+    // Top-level bindings are closed terms, so they are evaluated in an empty scope.
+    fn global(&mut self, name: &str) -> Result<Value<'a>, EvalError> {
+        if let Some(v) = self.cache.get(name) {
+            return Ok(v.clone());
+        }
+        let Some((name, term)) = self.globals.get_key_value(name) else {
+            return match Builtin::from_name(name) {
+                Some(builtin) => Ok(Value::Builtin { builtin, args: vec![] }),
+                None => Err(EvalError::UnboundVariable(name.to_string())),
+            };
+        };
+        // A function is a closure right away, so only a binding that is not one can get here
+        // again while it is evaluated
+        if self.pending.contains(&name.as_str()) {
+            return Err(EvalError::CyclicBinding(name.clone()));
+        }
+        self.pending.push(name);
+        let result = self.eval(&Scope::default(), term);
+        self.pending.pop();
+        let v = result?;
+        self.cache.insert(name, v.clone());
+        Ok(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{apply_primop, empty_env, eval, EvalOptions};
+    use crate::parser::parse_main_program;
+
+    #[test]
+    fn test_eval_value_arithmetic() {
+        let term = add(i(2), mul(i(3), i(4)));
+        let env = empty_env();
+        let v = eval_value(&env, &term).unwrap();
+        assert_eq!(eval(&env, &term).unwrap(), readback(&v));
+    }
+
+    #[test]
+    fn test_eval_value_agrees_with_eval() {
+        let input = r#"
+            fac = λn. if n == 0 then 1 else n * fac (n - 1);
+            fib = λn. if n < 2 then n else fib (n - 1) + fib (n - 2);
+            twice = λf x. f (f x);
+            main = twice (λn. fac n) 3 + fib 10;
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!(i(720 + 55), readback(&v));
+    }
+
+    #[test]
+    fn test_eval_value_big_integers() {
+        let input = r#"
+            fac = λn. if n == 0 then 1 else n * fac (n - 1);
+            main = (fac 25, fac 25 / fac 24, 123456789012345678901234567890 % 1000);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!("(15511210043330985984000000, 25, 890)", readback(&v).to_string());
+    }
+
+    #[test]
+    fn test_eval_value_floats() {
+        let input = r#"
+            mean = λxs. toFloat (sum xs) / toFloat (length xs);
+            sum = λxs. if null xs then 0 else head xs + sum (tail xs);
+            main = (mean [1, 2, 4], round (2.5 * 3), 1 / 0.0 > 1e308, show (-1.5));
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!("(2.3333333333333335, 8, true, \"-1.5\")", readback(&v).to_string());
+    }
+
+    #[test]
+    fn test_eval_value_case() {
+        let input = r#"
+            data List = Nil | Cons a List;
+            sum = λxs. case xs of Nil -> 0 | Cons x rest if x > 0 -> x + sum rest | Cons _ rest -> sum rest;
+            main = sum (Cons 1 (Cons (0 - 5) (Cons 2 Nil)));
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(i(3), readback(&v));
+        let term = case(i(1), vec![arm(Pattern::Int(0.into()), None, i(0))]);
+        assert_eq!(EvalError::MatchFailure(i(1)), eval_value(&prog.env, &term).unwrap_err());
+    }
+
+    #[test]
+    fn test_eval_value_lists() {
+        let input = r#"
+            twice = λf x. f (f x);
+            main = foldr (λx acc. x :: acc) [] (map (twice (λx. x + 1)) (append [1] (tail [0, 2])));
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!(list(vec![i(3), i(4)]), readback(&v));
+        let env = empty_env();
+        let term = app(var("head"), list(vec![]));
+        assert_eq!(EvalError::EmptyList(Term::Builtin(Builtin::Head, vec![list(vec![])])), eval_value(&env, &term).unwrap_err());
+        let term = app(app(var("map"), i(1)), list(vec![i(1)]));
+        assert_eq!(EvalError::NotAFunction(i(1)), eval_value(&env, &term).unwrap_err());
+    }
+
+    #[test]
+    fn test_eval_value_tuples() {
+        let input = r#"
+            divmod = λn d. (n / d, n - n / d * d);
+            add = λ(q, r) k. q * k + r;
+            main = (add (divmod 17 5) 10, #1 (fst (divmod 7 2), true));
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!(tuple(vec![i(32), i(3)]), readback(&v));
+        let term = app(var("add"), i(1));
+        assert_eq!(EvalError::MatchFailure(i(1)), eval_value(&prog.env, &term).unwrap_err());
+    }
+
+    #[test]
+    fn test_eval_value_records() {
+        let input = r#"
+            origin = { y = 0, x = 0 };
+            move = λp d. { p | x = p.x + d, y = p.y - d };
+            main = (move (move origin 2) 3, (move origin 1).y);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!(tuple(vec![record(vec![("x", i(5)), ("y", i(-5))]), i(-1)]), readback(&v));
+        let term = select(var("origin"), "z");
+        let missing = EvalError::MissingField { field: "z".to_string(), record: record(vec![("x", i(0)), ("y", i(0))]) };
+        assert_eq!(missing, eval_value(&prog.env, &term).unwrap_err());
+    }
+
+    #[test]
+    fn test_eval_value_strings() {
+        let input = r#"
+            greet = λname. "hello, " ++ name ++ "!";
+            initial = λs. case s of "" -> '?' | _ -> 'x';
+            main = (greet "fun", length (greet ""), show (length [1, 2]) < "3", initial "", [1] ++ [2]);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        let expected = tuple(vec![string("hello, fun!"), i(8), b(true), character('?'), list(vec![i(1), i(2)])]);
+        assert_eq!(expected, readback(&v));
+        let term = app(app(app(var("substring"), string("ab")), i(1)), i(2));
+        assert!(matches!(eval_value(&prog.env, &term), Err(EvalError::IndexOutOfRange(_))));
+    }
+
+    #[test]
+    fn test_eval_value_short_circuit() {
+        let input = r#"
+            member = λx xs. not (null xs) && (head xs == x || member x (tail xs));
+            main = (member 3 [1, 2, 3], member 4 [1, 2, 3], 7 % 3 ^ 2 != 7, 2 <= 1 || 2 >= 2);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!(tuple(vec![b(true), b(false), b(false), b(true)]), readback(&v));
+        let term = pow(i(2), i(-1));
+        assert_eq!(EvalError::NegativeExponent(term.clone()), eval_value(&prog.env, &term).unwrap_err());
+    }
+
+    #[test]
+    fn test_readback_closure() {
+        // (λx. λy. x) 1 evaluates to a closure that captured x = 1
+        let term = app(abs("x", abs("y", var("x"))), i(1));
+        let env = empty_env();
+        let v = eval_value(&env, &term).unwrap();
+        assert!(matches!(v, Value::Closure { param: "y", .. }));
+        assert_eq!(abs("y", i(1)), readback(&v));
+    }
+
+    #[test]
+    fn test_readback_closure_substitutes_at_once() {
+        // The closure captured a = λq. b with the global b, and its own b = 1
+        let prog = parse_main_program("b = 7; main = (λa. λb. λz. a b) (λq. b) 1;").unwrap();
+        let readbacks: Vec<Term> = (0..20).map(|_| readback(&eval_value(&prog.env, &prog.main).unwrap())).collect();
+        assert_eq!(abs("z", app(abs("q", var("b")), i(1))), readbacks[0]);
+        assert!(readbacks.iter().all(|t| *t == readbacks[0]));
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), eval(&prog.env, &readbacks[0]).unwrap());
+    }
+
+    #[test]
+    fn test_eval_value_let() {
+        let input = r#"
+            main = let n = 10; step = 2 in
+                letrec go = λi. if i >= n then [] else i :: go (i + step)
+                in (go 0, let n = 1 in n);
+        "#;
+        let prog = parse_main_program(input).unwrap();
+        let v = eval_value(&prog.env, &prog.main).unwrap();
+        assert_eq!(eval(&prog.env, &prog.main).unwrap(), readback(&v));
+        assert_eq!("([0, 2, 4, 6, 8], 1)", readback(&v).to_string());
+    }
+
+    #[test]
+    fn test_readback_letrec_closure() {
+        // The closure refers to itself and to the let-bound k
+        let term = let_in(
+            vec![("k", i(1))],
+            letrec(vec![("f", abs("n", app(var("f"), add(var("n"), var("k")))))], var("f")),
+        );
+        let env = empty_env();
+        let v = eval_value(&env, &term).unwrap();
+        let group = letrec(vec![("f", abs("n", app(var("f"), add(var("n"), i(1)))))], var("f"));
+        assert_eq!(abs("n", app(group, add(var("n"), i(1)))), readback(&v));
+    }
+
+    #[test]
+    fn test_value_primops_agree_with_apply_primop() {
+        let operands = [
+            i(7),
+            i(0),
+            i(-2),
+            Term::Float(2.5),
+            Term::Float(f64::NAN),
+            b(true),
+            string("ab"),
+            character('a'),
+            list(vec![i(1), i(2)]),
+            list(vec![Term::Float(1.0)]),
+            tuple(vec![i(1), list(vec![])]),
+            con("Some", 1, vec![i(1)]),
+            abs("x", var("x")),
+        ];
+        let ops = [
+            PrimOp::Add,
+            PrimOp::Sub,
+            PrimOp::Mul,
+            PrimOp::Div,
+            PrimOp::Mod,
+            PrimOp::Pow,
+            PrimOp::Lt,
+            PrimOp::Ge,
+            PrimOp::Eq,
+            PrimOp::Ne,
+            PrimOp::And,
+            PrimOp::Or,
+            PrimOp::Concat,
+            PrimOp::Cons,
+        ];
+        let env = empty_env();
+        let options = EvalOptions { strict: true, ..EvalOptions::default() };
+        for op in ops {
+            for l in &operands {
+                for r in &operands {
+                    let (lv, rv) = (eval_value(&env, l).unwrap(), eval_value(&env, r).unwrap());
+                    let expected = apply_primop(op, l.clone(), r.clone(), &options);
+                    let found = apply_value_primop(op, lv, rv).map(|v| readback(&v));
+                    // NaN is not equal to itself, so results are compared as text
+                    assert_eq!(format!("{expected:?}"), format!("{found:?}"), "{}", primop(op, l.clone(), r.clone()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_eval_value_errors() {
+        let env = empty_env();
+        let term = app(abs("x", var("x")), var("y"));
+        assert_eq!(
+            EvalError::UnboundVariable("y".to_string()),
+            eval_value(&env, &term).unwrap_err()
+        );
+        let term = ifte(i(1), i(2), i(3));
+        assert_eq!(
+            EvalError::NonBooleanCondition(i(1)),
+            eval_value(&env, &term).unwrap_err()
+        );
+        let prog = parse_main_program("ones = 1 :: ones; main = head ones;").unwrap();
+        assert_eq!(EvalError::CyclicBinding("ones".to_string()), eval_value(&prog.env, &prog.main).unwrap_err());
+    }
+}
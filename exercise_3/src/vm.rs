@@ -0,0 +1,753 @@
+use crate::bignum::BigInt;
+use crate::eval::{compare, float_arithmetic, int_arithmetic, substitute_all, EvalError};
+use crate::term::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+/// A program compiled to bytecode for the stack machine in this module.
+///
+/// Every abstraction becomes a function whose closures store the values of the variables it
+/// captures, so a variable is an index into the stack frame or into the captured values.
+/// It refers to the terms of the program it was compiled from to turn closures back into terms.
+#[derive(Debug, Clone)]
+pub struct Module<'p> {
+    functions: Vec<Function<'p>>,
+    constants: Vec<Value>,
+    // The right operands of `&&` and `||`, for the error when the left one is not a boolean
+    terms: Vec<&'p Term>,
+    // The top-level bindings used by `main`, each with the function that computes its value
+    globals: Vec<(&'p str, usize)>,
+    main: usize,
+}
+
+/// Why a program cannot be compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// A term the bytecode has no instructions for: data constructors, lists, tuples, records,
+    /// `case`, `letrec`, pattern abstractions and built-in functions.
+    Unsupported(Term),
+    /// A variable that is neither bound locally nor by a top-level binding.
+    UnboundVariable(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Unsupported(t) => write!(f, "cannot compile {t} to bytecode"),
+            CompileError::UnboundVariable(x) => write!(f, "unbound variable `{x}`"),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone)]
+struct Function<'p> {
+    code: Vec<Instr>,
+    // Where the captured values of a new closure come from in the frame that creates it
+    captures: Vec<Slot>,
+    // The abstraction the function was compiled from and the names of the captured
+    // variables, to turn a closure back into a term. Top-level bindings have no abstraction.
+    source: Option<&'p Term>,
+    capture_names: Vec<&'p str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    // A value on the stack, counted from the start of the frame
+    Local(usize),
+    // A value captured by the closure that is running
+    Captured(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instr {
+    Const(usize),
+    Local(usize),
+    Captured(usize),
+    // Pushes the value of a top-level binding, computing it the first time
+    Global(usize),
+    // Creates a closure of a function, capturing the values its `captures` name
+    Closure(usize),
+    PrimOp(PrimOp),
+    // Like `PrimOp` with a constant right operand, which is not pushed
+    PrimOpConst(PrimOp, usize),
+    // Jumps to `end` if the left operand of `&&` or `||` on top of the stack is the result,
+    // and otherwise leaves it there for the right operand
+    ShortCircuit { op: PrimOp, end: usize, right: usize },
+    Jump(usize),
+    JumpIfFalse(usize),
+    // Applies the function under the top of the stack to the top
+    Call,
+    // Like `Call`, but replaces the frame of the running function
+    TailCall,
+    Return,
+    // Drops the n values under the top of the stack: the bindings of a finished `let`
+    Slide(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    // Integers that fit into an `i64` are stored unboxed; the others are `BigInt`
+    Int(i64),
+    BigInt(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Str(Rc<str>),
+    Char(char),
+    Closure(Rc<Closure>),
+}
+
+#[derive(Debug)]
+struct Closure {
+    function: usize,
+    captures: Vec<Value>,
+}
+
+/// Compiles the bindings that `main` uses and `main` itself.
+///
+/// The bytecode covers a subset of the language: numbers, booleans, strings, characters,
+/// variables, abstractions, application, operators, `if` and `let`. Other terms fail with
+/// `CompileError::Unsupported`, and programs that use them have to be evaluated by
+/// `fun::eval` instead, as `fun run --vm` does.
+pub fn compile(prog: &Program) -> Result<Module<'_>, CompileError> {
+    let mut compiler = Compiler {
+        env: &prog.env,
+        functions: Vec::new(),
+        constants: Vec::new(),
+        terms: Vec::new(),
+        globals: Vec::new(),
+        global_index: HashMap::new(),
+        scopes: Vec::new(),
+    };
+    let main = compiler.function(None, &prog.main, None)?;
+    // Compiling a binding can add more bindings to compile
+    let mut n = 0;
+    while n < compiler.globals.len() {
+        let body = &prog.env[compiler.globals[n].0];
+        compiler.globals[n].1 = compiler.function(None, body, None)?;
+        n += 1;
+    }
+    Ok(Module {
+        functions: compiler.functions,
+        constants: compiler.constants,
+        terms: compiler.terms,
+        globals: compiler.globals,
+        main,
+    })
+}
+
+impl Module<'_> {
+    /// Runs `main` and converts its value back into a term. Errors are the ones `eval_strict`
+    /// reports for the same program.
+    pub fn run(&self) -> Result<Term, EvalError> {
+        let value = Machine::new(self).run()?;
+        Ok(self.readback(&value))
+    }
+
+    fn readback(&self, value: &Value) -> Term {
+        match value {
+            Value::Int(n) => Term::Int(BigInt::from(*n)),
+            Value::BigInt(n) => Term::Int((**n).clone()),
+            Value::Float(x) => Term::Float(*x),
+            Value::Bool(v) => b(*v),
+            Value::Str(s) => string(s),
+            Value::Char(c) => character(*c),
+            Value::Closure(closure) => {
+                let function = &self.functions[closure.function];
+                let source = function.source.expect("closures are created from abstractions");
+                let names = function.capture_names.iter().map(|x| x.to_string());
+                let bindings: Vec<_> = names.zip(closure.captures.iter().map(|v| self.readback(v))).collect();
+                substitute_all(source, &bindings)
+            }
+        }
+    }
+}
+
+struct Compiler<'p> {
+    env: &'p Env,
+    functions: Vec<Function<'p>>,
+    constants: Vec<Value>,
+    terms: Vec<&'p Term>,
+    globals: Vec<(&'p str, usize)>,
+    global_index: HashMap<&'p str, usize>,
+    // The functions being compiled, innermost last
+    scopes: Vec<Scope<'p>>,
+}
+
+#[derive(Default)]
+struct Scope<'p> {
+    code: Vec<Instr>,
+    // The values on the stack of a frame: the names of the parameter and of `let` bindings,
+    // `None` for intermediate results
+    stack: Vec<Option<&'p str>>,
+    captures: Vec<(&'p str, Slot)>,
+}
+
+impl<'p> Compiler<'p> {
+    // Compiles the body of a function and returns its index.
+    fn function(
+        &mut self,
+        param: Option<&'p str>,
+        body: &'p Term,
+        source: Option<&'p Term>,
+    ) -> Result<usize, CompileError> {
+        let mut stack = Vec::with_capacity(8);
+        stack.extend(param.map(Some));
+        // Enough for small functions, so that the code is not reallocated while it grows
+        self.scopes.push(Scope { code: Vec::with_capacity(16), stack, ..Scope::default() });
+        let result = self.expr(body, true);
+        let scope = self.scopes.pop().expect("the scope was pushed above");
+        result?;
+        let (capture_names, captures) = scope.captures.into_iter().unzip();
+        self.functions.push(Function { code: scope.code, captures, source, capture_names });
+        Ok(self.functions.len() - 1)
+    }
+
+    fn scope(&mut self) -> &mut Scope<'p> {
+        self.scopes.last_mut().expect("code is compiled inside a function")
+    }
+
+    // Appends an instruction that changes the height of the stack by `pushed - popped`.
+    fn emit(&mut self, instr: Instr, popped: usize, pushed: usize) -> usize {
+        let scope = self.scope();
+        scope.code.push(instr);
+        scope.stack.truncate(scope.stack.len() - popped);
+        scope.stack.extend((0..pushed).map(|_| None));
+        scope.code.len() - 1
+    }
+
+    // Points a jump at the next instruction.
+    fn patch(&mut self, at: usize) {
+        let target = self.scope().code.len();
+        match &mut self.scope().code[at] {
+            Instr::Jump(t) | Instr::JumpIfFalse(t) | Instr::ShortCircuit { end: t, .. } => *t = target,
+            instr => unreachable!("{instr:?} is not a jump"),
+        }
+    }
+
+    // Compiles a term that leaves its value on the stack. In tail position the function
+    // returns it instead.
+    fn expr(&mut self, term: &'p Term, tail: bool) -> Result<(), CompileError> {
+        match term {
+            Term::Var(x) => {
+                let instr = match self.resolve(x, self.scopes.len() - 1) {
+                    Some(Slot::Local(n)) => Instr::Local(n),
+                    Some(Slot::Captured(n)) => Instr::Captured(n),
+                    None if self.env.contains_key(x) => Instr::Global(self.global(x)),
+                    None if Builtin::from_name(x).is_some() => return Err(CompileError::Unsupported(term.clone())),
+                    None => return Err(CompileError::UnboundVariable(x.clone())),
+                };
+                self.emit(instr, 0, 1);
+            }
+            Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_) => {
+                let n = self.constant(term);
+                self.emit(Instr::Const(n), 0, 1);
+            }
+            Term::Abs(param, body) => {
+                let function = self.function(Some(param), body, Some(term))?;
+                self.emit(Instr::Closure(function), 0, 1);
+            }
+            Term::App(t1, t2) => {
+                self.expr(t1, false)?;
+                self.expr(t2, false)?;
+                if tail {
+                    self.emit(Instr::TailCall, 2, 0);
+                    return Ok(());
+                }
+                self.emit(Instr::Call, 2, 1);
+            }
+            Term::If(cond, t1, t2) => {
+                self.expr(cond, false)?;
+                let jump_if_false = self.emit(Instr::JumpIfFalse(0), 1, 0);
+                let height = self.scope().stack.len();
+                self.expr(t1, tail)?;
+                let jump = (!tail).then(|| self.emit(Instr::Jump(0), 0, 0));
+                self.scope().stack.truncate(height);
+                self.patch(jump_if_false);
+                self.expr(t2, tail)?;
+                if let Some(jump) = jump {
+                    self.patch(jump);
+                }
+                return Ok(());
+            }
+            Term::PrimOp(op @ (PrimOp::And | PrimOp::Or), t1, t2) => {
+                self.expr(t1, false)?;
+                self.terms.push(t2);
+                let right = self.terms.len() - 1;
+                let short_circuit = self.emit(Instr::ShortCircuit { op: *op, end: 0, right }, 0, 0);
+                self.expr(t2, false)?;
+                self.emit(Instr::PrimOp(*op), 2, 1);
+                self.patch(short_circuit);
+            }
+            Term::PrimOp(op, t1, t2) if is_literal(t2) => {
+                self.expr(t1, false)?;
+                let n = self.constant(t2);
+                self.emit(Instr::PrimOpConst(*op, n), 1, 1);
+            }
+            Term::PrimOp(op, t1, t2) => {
+                self.expr(t1, false)?;
+                self.expr(t2, false)?;
+                self.emit(Instr::PrimOp(*op), 2, 1);
+            }
+            // The bound values stay on the stack while the body runs
+            Term::Let(bindings, body) => {
+                for (x, t) in bindings {
+                    self.expr(t, false)?;
+                    *self.scope().stack.last_mut().expect("the value was pushed") = Some(x);
+                }
+                self.expr(body, tail)?;
+                if !tail {
+                    self.emit(Instr::Slide(bindings.len()), bindings.len() + 1, 1);
+                }
+                return Ok(());
+            }
+            _ => return Err(CompileError::Unsupported(term.clone())),
+        }
+        if tail {
+            self.emit(Instr::Return, 1, 0);
+        }
+        Ok(())
+    }
+
+    // Adds the value of a literal to the constants and returns its index.
+    fn constant(&mut self, literal: &Term) -> usize {
+        self.constants.push(match literal {
+            Term::Int(n) => int(n.clone()),
+            Term::Float(x) => Value::Float(*x),
+            Term::Bool(v) => Value::Bool(*v),
+            Term::Str(s) => Value::Str(s.as_str().into()),
+            Term::Char(c) => Value::Char(*c),
+            t => unreachable!("{t} is not a literal"),
+        });
+        self.constants.len() - 1
+    }
+
+    // Finds a local variable of the function at `level`. A variable of an enclosing function
+    // is added to the captured variables of each function in between.
+    fn resolve(&mut self, name: &'p str, level: usize) -> Option<Slot> {
+        let scope = &self.scopes[level];
+        if let Some(n) = scope.stack.iter().rposition(|x| *x == Some(name)) {
+            return Some(Slot::Local(n));
+        }
+        if let Some(n) = scope.captures.iter().position(|(x, _)| *x == name) {
+            return Some(Slot::Captured(n));
+        }
+        let slot = self.resolve(name, level.checked_sub(1)?)?;
+        let captures = &mut self.scopes[level].captures;
+        captures.push((name, slot));
+        Some(Slot::Captured(captures.len() - 1))
+    }
+
+    // Returns the index of a top-level binding, which is compiled after `main`.
+    fn global(&mut self, name: &'p str) -> usize {
+        if let Some(&n) = self.global_index.get(name) {
+            return n;
+        }
+        self.globals.push((name, usize::MAX));
+        self.global_index.insert(name, self.globals.len() - 1);
+        self.globals.len() - 1
+    }
+}
+
+#[derive(Debug, Clone)]
+enum GlobalState {
+    Unevaluated,
+    // Being computed, so needing it again is a cycle
+    Pending,
+    Done(Value),
+}
+
+struct Frame<'m> {
+    code: &'m [Instr],
+    pc: usize,
+    // Where the values of the frame start on the stack
+    base: usize,
+    closure: Option<Rc<Closure>>,
+    // The top-level binding whose value the frame computes
+    global: Option<usize>,
+}
+
+struct Machine<'m> {
+    module: &'m Module<'m>,
+    stack: Vec<Value>,
+    // The frames of the callers of the running function
+    frames: Vec<Frame<'m>>,
+    globals: Vec<GlobalState>,
+    // The largest number of callers so far
+    max_frames: usize,
+}
+
+impl<'m> Machine<'m> {
+    fn new(module: &'m Module<'m>) -> Self {
+        Machine {
+            module,
+            // Room for shallow programs, so that they run without growing the stack
+            stack: Vec::with_capacity(32),
+            frames: Vec::with_capacity(16),
+            globals: vec![GlobalState::Unevaluated; module.globals.len()],
+            max_frames: 0,
+        }
+    }
+
+    fn run(&mut self) -> Result<Value, EvalError> {
+        let module = self.module;
+        let code = &module.functions[module.main].code[..];
+        let mut frame = Frame { code, pc: 0, base: 0, closure: None, global: None };
+        loop {
+            let instr = frame.code[frame.pc];
+            frame.pc += 1;
+            match instr {
+                Instr::Const(n) => self.stack.push(module.constants[n].clone()),
+                Instr::Local(n) => self.stack.push(self.stack[frame.base + n].clone()),
+                Instr::Captured(n) => {
+                    let closure = frame.closure.as_ref().expect("only closures capture values");
+                    self.stack.push(closure.captures[n].clone());
+                }
+                Instr::Global(n) => match &self.globals[n] {
+                    GlobalState::Done(v) => self.stack.push(v.clone()),
+                    GlobalState::Pending => return Err(EvalError::CyclicBinding(module.globals[n].0.to_string())),
+                    GlobalState::Unevaluated => {
+                        self.globals[n] = GlobalState::Pending;
+                        let callee = Frame {
+                            code: &module.functions[module.globals[n].1].code,
+                            pc: 0,
+                            base: self.stack.len(),
+                            closure: None,
+                            global: Some(n),
+                        };
+                        self.frames.push(mem::replace(&mut frame, callee));
+                    }
+                },
+                Instr::Closure(function) => {
+                    let captured = frame.closure.as_deref().map_or(&[][..], |closure| &closure.captures);
+                    let captures = module.functions[function]
+                        .captures
+                        .iter()
+                        .map(|slot| match slot {
+                            Slot::Local(n) => self.stack[frame.base + n].clone(),
+                            Slot::Captured(n) => captured[*n].clone(),
+                        })
+                        .collect();
+                    self.stack.push(Value::Closure(Rc::new(Closure { function, captures })));
+                }
+                Instr::PrimOp(op) => {
+                    let r = self.pop();
+                    self.apply_primop(op, &r)?;
+                }
+                Instr::PrimOpConst(op, n) => self.apply_primop(op, &module.constants[n])?,
+                Instr::ShortCircuit { op, end, right } => match self.stack.last() {
+                    Some(Value::Bool(v)) if *v == (op == PrimOp::Or) => frame.pc = end,
+                    Some(Value::Bool(_)) => {}
+                    _ => {
+                        let left = module.readback(&self.pop());
+                        return Err(EvalError::TypeMismatch { op, left, right: module.terms[right].clone() });
+                    }
+                },
+                Instr::Jump(target) => frame.pc = target,
+                Instr::JumpIfFalse(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => frame.pc = target,
+                    c => return Err(EvalError::NonBooleanCondition(module.readback(&c))),
+                },
+                Instr::Call => {
+                    let arg = self.pop();
+                    let closure = self.pop_closure()?;
+                    self.stack.push(arg);
+                    let callee = Frame {
+                        code: &module.functions[closure.function].code,
+                        pc: 0,
+                        base: self.stack.len() - 1,
+                        closure: Some(closure),
+                        global: None,
+                    };
+                    self.frames.push(mem::replace(&mut frame, callee));
+                    self.max_frames = self.max_frames.max(self.frames.len());
+                }
+                Instr::TailCall => {
+                    let arg = self.pop();
+                    let closure = self.pop_closure()?;
+                    self.stack.truncate(frame.base);
+                    self.stack.push(arg);
+                    frame.code = &module.functions[closure.function].code;
+                    frame.pc = 0;
+                    frame.closure = Some(closure);
+                }
+                Instr::Return => {
+                    let v = self.pop();
+                    self.stack.truncate(frame.base);
+                    if let Some(n) = frame.global {
+                        self.globals[n] = GlobalState::Done(v.clone());
+                    }
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            frame = caller;
+                            self.stack.push(v);
+                        }
+                        None => return Ok(v),
+                    }
+                }
+                Instr::Slide(n) => {
+                    let v = self.pop();
+                    self.stack.truncate(self.stack.len() - n);
+                    self.stack.push(v);
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler keeps track of the stack")
+    }
+
+    // Applies an operation to the value on top of the stack and `r`, replacing the value.
+    fn apply_primop(&mut self, op: PrimOp, r: &Value) -> Result<(), EvalError> {
+        // Integer operations replace the left operand in place
+        let l = self.stack.last_mut().expect("the compiler keeps track of the stack");
+        if let (Value::Int(n1), Value::Int(n2)) = (&*l, r) {
+            if let Some(v) = int_primop(op, *n1, *n2) {
+                *l = v;
+                return Ok(());
+            }
+        }
+        let l = self.pop();
+        let v = self.primop(op, l, r.clone())?;
+        self.stack.push(v);
+        Ok(())
+    }
+
+    fn pop_closure(&mut self) -> Result<Rc<Closure>, EvalError> {
+        match self.pop() {
+            Value::Closure(closure) => Ok(closure),
+            f => Err(EvalError::NotAFunction(self.module.readback(&f))),
+        }
+    }
+
+    // The operations that `int_primop` leaves out: large integers, floats, strings, characters
+    // and booleans. Results and errors are the ones `eval_strict` computes; operands are only
+    // turned back into terms for an error.
+    fn primop(&self, op: PrimOp, l: Value, r: Value) -> Result<Value, EvalError> {
+        let module = self.module;
+        let redex = || primop(op, module.readback(&l), module.readback(&r));
+        let mismatch = || EvalError::TypeMismatch { op, left: module.readback(&l), right: module.readback(&r) };
+        Ok(match op {
+            PrimOp::Add | PrimOp::Sub | PrimOp::Mul | PrimOp::Div | PrimOp::Mod | PrimOp::Pow => {
+                if let (Some(n1), Some(n2)) = (big_int(&l), big_int(&r)) {
+                    if matches!(op, PrimOp::Div | PrimOp::Mod) && n2.is_zero() {
+                        return Err(EvalError::DivisionByZero(redex()));
+                    }
+                    if op == PrimOp::Pow && n2.is_negative() {
+                        return Err(EvalError::NegativeExponent(redex()));
+                    }
+                    int(int_arithmetic(op, &n1, &n2).ok_or_else(|| EvalError::ArithmeticOverflow(redex()))?)
+                } else if let (Some(x), Some(y)) = (float(&l), float(&r)) {
+                    Value::Float(float_arithmetic(op, x, y))
+                } else {
+                    return Err(mismatch());
+                }
+            }
+            PrimOp::Lt | PrimOp::Gt | PrimOp::Le | PrimOp::Ge => {
+                // None if a float operand is NaN, which makes every comparison false
+                let ordering = match (&l, &r) {
+                    (Value::Str(s1), Value::Str(s2)) => Some(s1.cmp(s2)),
+                    (Value::Char(c1), Value::Char(c2)) => Some(c1.cmp(c2)),
+                    _ => match (big_int(&l), big_int(&r), float(&l), float(&r)) {
+                        (Some(n1), Some(n2), _, _) => Some(n1.cmp(&n2)),
+                        (_, _, Some(x), Some(y)) => x.partial_cmp(&y),
+                        _ => return Err(mismatch()),
+                    },
+                };
+                Value::Bool(ordering.is_some_and(|ordering| compare(op, ordering)))
+            }
+            PrimOp::Eq | PrimOp::Ne => {
+                let equal = match (&l, &r) {
+                    (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+                    (Value::Str(s1), Value::Str(s2)) => s1 == s2,
+                    (Value::Char(c1), Value::Char(c2)) => c1 == c2,
+                    _ => match (big_int(&l), big_int(&r), float(&l), float(&r)) {
+                        (Some(n1), Some(n2), _, _) => n1 == n2,
+                        (_, _, Some(x), Some(y)) => x == y,
+                        _ => return Err(mismatch()),
+                    },
+                };
+                Value::Bool(equal == (op == PrimOp::Eq))
+            }
+            PrimOp::And | PrimOp::Or => match (&l, &r) {
+                (Value::Bool(b1), Value::Bool(b2)) if op == PrimOp::And => Value::Bool(*b1 && *b2),
+                (Value::Bool(b1), Value::Bool(b2)) => Value::Bool(*b1 || *b2),
+                _ => return Err(mismatch()),
+            },
+            PrimOp::Concat => match (&l, &r) {
+                (Value::Str(s1), Value::Str(s2)) => Value::Str([&**s1, &**s2].concat().into()),
+                _ => return Err(mismatch()),
+            },
+            // There are no lists in the bytecode
+            PrimOp::Cons => return Err(mismatch()),
+        })
+    }
+}
+
+fn is_literal(term: &Term) -> bool {
+    matches!(term, Term::Int(_) | Term::Float(_) | Term::Bool(_) | Term::Str(_) | Term::Char(_))
+}
+
+fn int(n: BigInt) -> Value {
+    match n.to_i64() {
+        Some(n) => Value::Int(n),
+        None => Value::BigInt(Rc::new(n)),
+    }
+}
+
+fn big_int(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(n) => Some(BigInt::from(*n)),
+        Value::BigInt(n) => Some((**n).clone()),
+        _ => None,
+    }
+}
+
+// A number as a float, like integers mixed with floats in arithmetic.
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(BigInt::from(*n).to_f64()),
+        Value::BigInt(n) => Some(n.to_f64()),
+        Value::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+// Arithmetic and comparisons of small integers, unless they overflow or fail.
+fn int_primop(op: PrimOp, n1: i64, n2: i64) -> Option<Value> {
+    Some(match op {
+        PrimOp::Add => Value::Int(n1.checked_add(n2)?),
+        PrimOp::Sub => Value::Int(n1.checked_sub(n2)?),
+        PrimOp::Mul => Value::Int(n1.checked_mul(n2)?),
+        // None for a zero divisor or a negative exponent, which are errors
+        PrimOp::Div => Value::Int(n1.checked_div(n2)?),
+        PrimOp::Mod => Value::Int(n1.checked_rem(n2)?),
+        PrimOp::Pow => Value::Int(n1.checked_pow(u32::try_from(n2).ok()?)?),
+        PrimOp::Eq => Value::Bool(n1 == n2),
+        PrimOp::Ne => Value::Bool(n1 != n2),
+        PrimOp::Lt => Value::Bool(n1 < n2),
+        PrimOp::Gt => Value::Bool(n1 > n2),
+        PrimOp::Le => Value::Bool(n1 <= n2),
+        PrimOp::Ge => Value::Bool(n1 >= n2),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval_strict;
+    use crate::parser::parse_main_program;
+
+    fn run(input: &str) -> Result<Term, EvalError> {
+        compile(&parse_main_program(input).unwrap()).unwrap().run()
+    }
+
+    #[test]
+    fn test_vm_agrees_with_eval() {
+        let inputs = [
+            "fac = λn. if n == 0 then 1 else n * fac (n - 1); main = fac 25;",
+            "fib = λn. if n < 2 then n else fib (n - 1) + fib (n - 2); main = fib 15;",
+            "twice = λf x. f (f x); main = twice (λn. n * n) 3 + twice (twice (λn. n + 1)) 0;",
+            "main = let x = 2; y = x * 3 in let x = y + 1 in x * y - 7 / 2 + 7 % 3 ^ 2;",
+            "main = 1.5 * 2 + 1 < 4.0;",
+            r#"greet = λname. "hello, " ++ name ++ "!"; main = greet "fun" == "hello, fun!";"#,
+            "has = λx. x == 3 || x == 4 && (1 / 0 == 0 || true); main = has 3 && has 5 == false || 'a' < 'b';",
+            "compose = λf g x. f (g x); main = compose (λx. x * 2) (λx. x + 1) 5;",
+            "x = 2 + 3; y = x * x; main = y + x;",
+        ];
+        for input in inputs {
+            let prog = parse_main_program(input).unwrap();
+            assert_eq!(eval_strict(&prog.env, &prog.main), run(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_vm_primops_agree_with_eval() {
+        let inputs = [
+            "main = 7 / 2 + (0 - 7) / 2 + 7 % (0 - 2) + (0 - 7) % 2 + 2 ^ 10 + 0 ^ 0;",
+            "main = 2 ^ 70 + 9223372036854775807 + 1 - 2 ^ 64 / 3 % 1000;",
+            "main = (0 - 9223372036854775807 - 1) / (0 - 1) == 2 ^ 63;",
+            "main = 2 ^ 64 > 2 ^ 63 && 2 ^ 64 != 2 ^ 63 && 2 ^ 64 > 1.5;",
+            "main = 7.5 / 2 + 7.5 % 2 + 2 ^ 0.5 - 1 / 4.0 + 1.0 * 3;",
+            "main = 1 == 1.0 && 1 < 1.5 && 1.0 / 0.0 > 2 ^ 100 && (0.0 / 0.0 < 1) == false;",
+            r#"main = "ab" < "b" && "b" >= "b" && "ab" ++ "c" == "abc" && 'x' > 'a' && 'x' != 'y';"#,
+            "main = (true == false) == (1 > 2) && (true || false) && (2 <= 1) == false;",
+            "main = (1 - 1) ^ 100000000000 + 1 ^ 100000000000 + (0 - 1) ^ 100000000001;",
+        ];
+        for input in inputs {
+            let prog = parse_main_program(input).unwrap();
+            assert_eq!(eval_strict(&prog.env, &prog.main), run(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_vm_readback_closure() {
+        let input = "adder = λn. λm. n + m; main = adder (let k = 2 in k * 5);";
+        assert_eq!(Ok(abs("m", add(i(10), var("m")))), run(input));
+
+        // The captured values are substituted at once, so `b` in the value of `a` stays global
+        let input = "b = 7; main = (λa. λb. λz. a b) (λq. b) 1;";
+        assert_eq!(Ok(abs("z", app(abs("q", var("b")), i(1)))), run(input));
+    }
+
+    #[test]
+    fn test_vm_deep_recursion_and_tail_calls() {
+        let input = r#"
+            sum = λn. if n == 0 then 0 else n + sum (n - 1);
+            loop = λn acc. if n == 0 then acc else loop (n - 1) (acc + n);
+            main = sum 100000 - loop 100000 0;
+        "#;
+        assert_eq!(Ok(i(0)), run(input));
+
+        // The recursive call of `loop` replaces its frame, while each call of `sum` waits for the next
+        for (main, deep) in [("sum 100000", true), ("loop 100000 0", false)] {
+            let prog = parse_main_program(&input.replace("sum 100000 - loop 100000 0", main)).unwrap();
+            let module = compile(&prog).unwrap();
+            let mut machine = Machine::new(&module);
+            assert!(matches!(machine.run(), Ok(Value::Int(_))));
+            assert_eq!(deep, machine.max_frames >= 100000, "{main}: {} frames", machine.max_frames);
+        }
+    }
+
+    #[test]
+    fn test_vm_errors() {
+        let inputs = [
+            "main = 1 / (2 - 2);",
+            "main = if 1 then 2 else 3;",
+            "main = 1 2;",
+            "main = 1 && (1 / 0 == 0);",
+            "main = true + 1;",
+            "x = y + 1; y = x; main = x;",
+            "main = 2 ^ (0 - 1);",
+            "main = 2 ^ 70 / 0;",
+            "main = 3 ^ 100000000000;",
+            r#"main = "a" ++ 'b';"#,
+            r#"main = 1 < "a";"#,
+            "main = (λx. x) == (λx. x);",
+            "main = 'a' + 1;",
+            "main = 1 :: 2;",
+        ];
+        for input in inputs {
+            let prog = parse_main_program(input).unwrap();
+            let expected = eval_strict(&prog.env, &prog.main);
+            assert!(expected.is_err(), "{input}");
+            assert_eq!(expected, run(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let prog = parse_main_program("main = head [1];").unwrap();
+        assert_eq!(CompileError::Unsupported(var("head")), compile(&prog).unwrap_err());
+        let prog = parse_main_program("unused = [1]; main = (λx. x) 1;").unwrap();
+        assert!(compile(&prog).is_ok());
+        let prog = parse_main_program("main = λx. y;").unwrap();
+        assert_eq!(CompileError::UnboundVariable("y".to_string()), compile(&prog).unwrap_err());
+    }
+}